@@ -1,36 +1,152 @@
+use crate::board_binary::{board_decode, board_encode};
+use crate::board_delta::{delta_apply, delta_serialize};
 use crate::board_serializer::{board_deserialize, board_serialize};
-use crate::moves::Move;
-use crate::pawn_rank::PawnRank;
+use crate::fen::{fen_deserialize, fen_serialize};
+use crate::game_record::GameRecord;
+use crate::move_set::MoveSet;
+use crate::illegal_move::IllegalMove;
+use crate::moves::{Move, UNPLACED};
+use crate::notation::{move_notation, parse_move};
+use crate::position::Position;
 use crate::piece::{Color, Piece};
-use crate::piece_rules::{PieceRules, StandardChess, self};
+use crate::piece_definition::PieceSet;
+use crate::piece_row::PieceRow;
+use crate::piece_rules::{GameStatus, PieceRules, StandardChess};
 use crate::piece_serializer::piece_serialize;
-use num_bigint::BigInt;
-use num_traits::Signed;
+use crate::setup::Setup;
+use crate::coord::Coord;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Arc;
 use wasm_bindgen::prelude::*;
+#[cfg(test)]
+use crate::piece_rules::{assert_move_sets_match, play, standard_board};
 pub const STANDARD_BOARD_SIZE: i32 = 8;
+pub const BLACK_PAWN_RANK: i32 = 1;
+pub const WHITE_PAWN_RANK: i32 = 6;
+/// The most files `pieces_in_rect` looks across at once.
+pub const MAX_RECT_FILES: i32 = 1024;
 
 //#[derive(Serialize, Deserialize)]
 
 #[wasm_bindgen]
 pub struct WasmBoard {
     pub(crate) board: Board,
-    pub(crate) rules: StandardChess,
+    pub(crate) rules: Box<dyn PieceRules>,
+}
+
+impl Default for WasmBoard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn parse_coordinate(s: &str) -> Result<Coord, JsValue> {
+    s.parse::<Coord>().map_err(|_| {
+        JsValue::from_str(&format!("{{\"reason\": \"bad_coordinate\", \"message\": \"{} is not a coordinate\"}}", s))
+    })
+}
+
+/// Why `pieces_in_rect` turned a rectangle down, as JSON.
+pub(crate) fn too_wide() -> String {
+    format!("{{\"reason\": \"too_wide\", \"message\": \"the rectangle can be at most {} files wide\"}}", MAX_RECT_FILES)
+}
+
+impl WasmBoard {
+    pub fn with(rules: Box<dyn PieceRules>) -> Self {
+        Self {
+            board: Board::with_piece_set(rules.piece_set()),
+            rules,
+        }
+    }
 }
 
 #[wasm_bindgen]
 impl WasmBoard {
     #[wasm_bindgen(constructor)]
     pub fn new() -> Self {
-        Self {
-            board: Board::new(),
-            rules: StandardChess::new(),
-        }
+        Self::with(Box::new(StandardChess::new()))
+    }
+    /// A board played by the pieces of a rules file rather than the standard ones.
+    pub fn with_rules(rules: String) -> Result<WasmBoard, JsValue> {
+        let rules = StandardChess::from_rules(&rules).map_err(|e| JsValue::from_str(&e))?;
+        Ok(Self::with(Box::new(rules)))
+    }
+    /// Starts a new game from a setup in the format of `setups/standard.json`, or from the
+    /// standard one.
+    pub fn set_up(&mut self, setup: Option<String>) -> Result<(), JsValue> {
+        let setup = match setup {
+            Some(s) => Setup::parse(&s).map_err(|e| JsValue::from_str(&e))?,
+            None => Setup::standard(),
+        };
+        self.board.set_up(&setup);
+        Ok(())
+    }
+    /// The game played through from a record in the style of PGN, with the rules and setup
+    /// its tags name. Fails with why as JSON if a move in it, or in any variation, is illegal.
+    pub fn from_record(record: String) -> Result<WasmBoard, JsValue> {
+        let (board, rules) = GameRecord::parse(&record)
+            .and_then(|r| r.replay())
+            .map_err(|e| JsValue::from_str(&e.serialize()))?;
+        Ok(Self { board, rules: Box::new(rules) })
+    }
+    /// The game so far as a record in the style of PGN, under tags given as a JSON object like
+    /// `{"White": "Ann", "Date": "2024.05.01"}`. A game played by other rules than the standard
+    /// ones needs them as the Rules tag to be read back.
+    pub fn record(&self, tags: Option<String>) -> Option<String> {
+        let tags: Vec<(String, String)> = match tags {
+            Some(s) => {
+                let v: serde_json::Value = serde_json::from_str(&s).ok()?;
+                v.as_object()?.iter().map(|(k, v)| Some((k.clone(), v.as_str()?.to_string()))).collect::<Option<_>>()?
+            }
+            None => Vec::new(),
+        };
+        Some(GameRecord::of(&self.board, self.rules.as_ref(), &tags)?.write())
+    }
+    /// Sets up the position in one line, as `position` writes it.
+    pub fn set_position(&mut self, fen: String) -> Result<(), JsValue> {
+        fen_deserialize(&mut self.board, &fen).map_err(|e| JsValue::from_str(&e))
+    }
+    /// The position in one line, with the pieces on the board, the rows and what they have
+    /// lost, who moves, castling rights and the square a pawn can be taken en passant on.
+    pub fn position(&self) -> String {
+        fen_serialize(&self.board)
     }
-    pub fn build(&mut self, s: String) {
-        board_deserialize(&mut self.board, &s);
+    /// Loads a board from `deconstruct` or the server, leaving this one as it was if the text
+    /// isn't a board this version can read.
+    pub fn build(&mut self, s: String) -> Result<(), JsValue> {
+        board_deserialize(&mut self.board, &s).map_err(|e| JsValue::from_str(&e.serialize()))
     }
-    pub fn deconstruct(&self) -> String {
-        board_serialize(&self.board)
+    /// Catches up with the moves in a delta from the server's `/delta` endpoint. Fails, leaving
+    /// the board as it was, if the delta doesn't fit it; loading the whole board with `build`
+    /// sets things right.
+    pub fn apply_delta(&mut self, delta: String) -> Result<(), JsValue> {
+        delta_apply(&mut self.board, &delta)
+            .map(|_| ())
+            .ok_or_else(|| JsValue::from_str("{\"reason\": \"bad_delta\", \"message\": \"the delta doesn't fit the board\"}"))
+    }
+    /// What happened after the first `ply` moves, as a delta another board can catch up with.
+    pub fn delta_since(&mut self, ply: usize) -> Option<String> {
+        let status = Board::game_status(&mut self.board, self.rules.as_ref());
+        delta_serialize(&self.board, ply, status)
+    }
+    /// How many moves have been made, which is where a delta for this board has to start.
+    pub fn plies(&self) -> usize {
+        self.board.moves.len()
+    }
+    pub fn deconstruct(&mut self) -> String {
+        let status = Board::game_status(&mut self.board, self.rules.as_ref());
+        board_serialize(&self.board, status)
+    }
+    /// Loads a board in the binary form the server sends when asked for it, leaving this one
+    /// as it was if the bytes aren't a board.
+    pub fn build_binary(&mut self, bytes: Vec<u8>) -> Result<(), JsValue> {
+        board_decode(&mut self.board, &bytes).map_err(|e| JsValue::from_str(&e.serialize()))
+    }
+    /// The board in binary, which `build_binary` reads back.
+    pub fn deconstruct_binary(&mut self) -> Vec<u8> {
+        let status = Board::game_status(&mut self.board, self.rules.as_ref());
+        board_encode(&self.board, status)
     }
     pub fn place_piece(
         &mut self,
@@ -42,53 +158,154 @@ impl WasmBoard {
         self.board.place_piece(Piece::new(
             piece,
             if white { Color::White } else { Color::Black },
-            rank.parse::<BigInt>().ok()?,
-            file.parse::<BigInt>().ok()?,
+            rank.parse::<Coord>().ok()?,
+            file.parse::<Coord>().ok()?,
         ))
     }
-    pub fn get_piece_at(&mut self, rank: String, file: String) -> Option<usize> {
+    pub fn get_piece_at(&self, rank: String, file: String) -> Option<usize> {
         self.board
-            .get_piece_at(&rank.parse::<BigInt>().ok()?, &file.parse::<BigInt>().ok()?)
+            .get_piece_at(&rank.parse::<Coord>().ok()?, &file.parse::<Coord>().ok()?)
     }
-    pub fn add_pawns(&mut self, file: String, zoom: String) -> Option<usize> {
-        let f = file.parse::<BigInt>().ok()?;
-        let mut z = zoom.parse::<BigInt>().ok()?;
-        let zero = 0.into();
-        while z >= zero {
-            z -= &1.into();
-            self.board.get_piece_at(&1.into(), &(&f + &z)); //not great, improve later
-            self.board.get_piece_at(&6.into(), &(&f + &z));
-        }
-        Some(0)
+    /// Whatever stands on the square as JSON, including pieces still on their infinite rows.
+    pub fn piece_at(&self, rank: String, file: String) -> Option<String> {
+        let (_, piece) = self.board.piece_at(&rank.parse::<Coord>().ok()?, &file.parse::<Coord>().ok()?)?;
+        Some(piece_serialize(&piece))
+    }
+    /// Everything standing in the rectangle, corners included, as JSON: the pieces on the board
+    /// and those still on their infinite rows.
+    pub fn pieces_in_rect(&self, min_rank: String, min_file: String, max_rank: String, max_file: String) -> Result<String, JsValue> {
+        let pieces = self
+            .board
+            .pieces_in_rect(
+                &parse_coordinate(&min_rank)?,
+                &parse_coordinate(&min_file)?,
+                &parse_coordinate(&max_rank)?,
+                &parse_coordinate(&max_file)?,
+            )
+            .ok_or_else(|| JsValue::from_str(&too_wide()))?;
+        let s: Vec<String> = pieces.iter().map(|(_, piece)| piece_serialize(piece)).collect();
+        Ok(format!("[{}]", s.join(",")))
     }
-    pub fn get_piece_info(&mut self, id: usize) -> Option<String> {
+    pub fn get_piece_info(&self, id: usize) -> Option<String> {
         Some(piece_serialize(self.board.pieces.get(id)?))
     }
-    pub fn promote(&mut self, rank: String, file: String, new_type: String) -> Option<usize> {
-        self.board.promote(
-            &rank.parse::<BigInt>().ok()?,
-            &file.parse::<BigInt>().ok()?,
-            new_type,
+    /// Makes the move, or fails with the reason it is illegal as JSON.
+    pub fn do_move(
+        &mut self,
+        rank: String,
+        file: String,
+        to_rank: String,
+        to_file: String,
+        promotion: Option<String>,
+    ) -> Result<usize, JsValue> {
+        let m = Board::move_legal(&mut self.board, self.rules.as_ref(),
+            &parse_coordinate(&rank)?,
+            &parse_coordinate(&file)?,
+            &parse_coordinate(&to_rank)?,
+            &parse_coordinate(&to_file)?,
+            promotion,
         )
+        .map_err(|e| JsValue::from_str(&e.serialize()))?;
+        Ok(self.board.do_move(m).unwrap_or(0))
     }
-    pub fn do_move(
+    /// Why the move would be illegal as JSON, or nothing if it is fine.
+    pub fn move_error(
         &mut self,
         rank: String,
         file: String,
         to_rank: String,
         to_file: String,
-    ) -> Option<usize> {
-        let m = Board::move_legal(&mut self.board, &self.rules,
-            &rank.parse::<BigInt>().ok()?,
-            &file.parse::<BigInt>().ok()?,
-            &to_rank.parse::<BigInt>().ok()?,
-            &to_file.parse::<BigInt>().ok()?,
-        )?;
-        self.board.do_move(m)
+        promotion: Option<String>,
+    ) -> Option<String> {
+        Board::move_legal(&mut self.board, self.rules.as_ref(),
+            &rank.parse::<Coord>().ok()?,
+            &file.parse::<Coord>().ok()?,
+            &to_rank.parse::<Coord>().ok()?,
+            &to_file.parse::<Coord>().ok()?,
+            promotion,
+        )
+        .err()
+        .map(|e| e.serialize())
+    }
+    /// Whether moving the piece on (rank, file) to `to_rank` needs a promotion, so the piece it
+    /// becomes can be asked for before the move is made.
+    pub fn promotes(&self, rank: String, file: String, to_rank: String) -> bool {
+        let promotes = || {
+            let (_, piece) = self.board.piece_at(&rank.parse::<Coord>().ok()?, &file.parse::<Coord>().ok()?)?;
+            Some(self.rules.promotes(&self.board, &piece, &to_rank.parse::<Coord>().ok()?))
+        };
+        promotes().unwrap_or(false)
+    }
+    /// The types a piece may promote to under these rules, as a JSON list.
+    pub fn promotion_pieces(&self) -> String {
+        serde_json::to_string(&self.rules.piece_set().promotion).expect("piece types always make JSON")
+    }
+    /// Makes the move written in notation, or fails with why it can't be made as JSON.
+    pub fn do_notated_move(&mut self, text: String) -> Result<usize, JsValue> {
+        let m = parse_move(&mut self.board, self.rules.as_ref(), &text).map_err(|e| JsValue::from_str(&e.serialize()))?;
+        Ok(self.board.do_move(m).unwrap_or(0))
+    }
+    /// How the move would be written in notation, or why it is illegal as JSON.
+    pub fn move_notation(
+        &mut self,
+        rank: String,
+        file: String,
+        to_rank: String,
+        to_file: String,
+        promotion: Option<String>,
+    ) -> Result<String, JsValue> {
+        let m = Board::move_legal(&mut self.board, self.rules.as_ref(),
+            &parse_coordinate(&rank)?,
+            &parse_coordinate(&file)?,
+            &parse_coordinate(&to_rank)?,
+            &parse_coordinate(&to_file)?,
+            promotion,
+        )
+        .map_err(|e| JsValue::from_str(&e.serialize()))?;
+        Ok(move_notation(&mut self.board, self.rules.as_ref(), &m).unwrap_or_default())
     }
     pub fn undo_move(&mut self) -> Option<usize> {
         self.board.undo_move()
     }
+    pub fn white_to_move(&self) -> bool {
+        self.board.to_move() == Color::White
+    }
+    /// Hands the move to one side, for setting up a position.
+    pub fn set_to_move(&mut self, white: bool) {
+        self.board.set_to_move(if white { Color::White } else { Color::Black });
+    }
+    pub fn game_status(&mut self) -> String {
+        Board::game_status(&mut self.board, self.rules.as_ref()).to_string()
+    }
+    /// The squares of the pieces of one color that attack the square, as JSON.
+    pub fn attackers_of(&self, rank: String, file: String, white: bool) -> Option<String> {
+        let by = if white { Color::White } else { Color::Black };
+        let attackers = Board::attackers_of(
+            &self.board,
+            self.rules.as_ref(),
+            &rank.parse::<Coord>().ok()?,
+            &file.parse::<Coord>().ok()?,
+            by,
+        );
+        Some(format!("[{}]", attackers.iter().map(|(y, x)| format!("[{}, {}]", y, x)).collect::<Vec<String>>().join(",")))
+    }
+    pub fn is_attacked(&self, rank: String, file: String, white: bool) -> Option<bool> {
+        let by = if white { Color::White } else { Color::Black };
+        Some(Board::is_attacked(
+            &self.board,
+            self.rules.as_ref(),
+            &rank.parse::<Coord>().ok()?,
+            &file.parse::<Coord>().ok()?,
+            by,
+        ))
+    }
+    /// The draw the side to move could claim, if any.
+    pub fn claimable_draw(&self) -> Option<String> {
+        self.rules.claimable_draw(&self.board).map(|x| x.to_string())
+    }
+    pub fn claim_draw(&mut self) -> Option<String> {
+        Board::claim_draw(&mut self.board, self.rules.as_ref()).map(|x| x.to_string())
+    }
     pub fn is_move_legal(
         &mut self,
         rank: String,
@@ -98,14 +315,14 @@ impl WasmBoard {
     ) -> Option<bool> {
         Some(Board::is_move_legal(
             &mut self.board,
-            &self.rules,
-            &rank.parse::<BigInt>().ok()?,
-            &file.parse::<BigInt>().ok()?,
-            &to_rank.parse::<BigInt>().ok()?,
-            &to_file.parse::<BigInt>().ok()?,
+            self.rules.as_ref(),
+            &rank.parse::<Coord>().ok()?,
+            &file.parse::<Coord>().ok()?,
+            &to_rank.parse::<Coord>().ok()?,
+            &to_file.parse::<Coord>().ok()?,
         ))
     }
-    pub fn get_pieces(&mut self) -> Option<String> {
+    pub fn get_pieces(&self) -> Option<String> {
         let mut s = Vec::new();
         for piece in &self.board.pieces {
             if !piece.is_captured() {
@@ -123,225 +340,654 @@ impl WasmBoard {
         swiny: String,
         szoom: String
     ) -> Option<String> {
-    let rank = srank.parse::<BigInt>().ok()?;
-    let file = sfile.parse::<BigInt>().ok()?;
-    let winx = swinx.parse::<BigInt>().ok()?;
-    let winy = swiny.parse::<BigInt>().ok()?;
-    let zoom = szoom.parse::<BigInt>().ok()?;
-    let mut xx = winx.clone();
-    let winxwidth = winx + zoom.clone();
-    let winyheight = winy.clone() + zoom;
-    let mut results = Vec::new();
-    while xx < winxwidth {
-        let mut yy = winy.clone();
-        while yy < winyheight {
-            if Board::is_move_legal(&mut self.board, &self.rules, &rank, &file, &xx, &yy) {
-                results.push(format!("[{}, {}]", xx, yy));
-            }
-            yy += 1;
-        }
-        xx += 1;
-    }
+    let rank = srank.parse::<Coord>().ok()?;
+    let file = sfile.parse::<Coord>().ok()?;
+    let winx = swinx.parse::<Coord>().ok()?;
+    let winy = swiny.parse::<Coord>().ok()?;
+    let zoom = szoom.parse::<Coord>().ok()?;
+    let winxwidth = &winx + &zoom;
+    let winyheight = &winy + &zoom;
+    let results = Board::move_set(&mut self.board, self.rules.as_ref(), &rank, &file)
+        .squares_in(&winx, &winy, &winxwidth, &winyheight)?
+        .iter()
+        .map(|(xx, yy)| format!("[{}, {}]", xx, yy))
+        .collect::<Vec<String>>();
     Some(format!("[{}]", results.join(",")))
     }
+    pub fn get_move_set(&mut self, rank: String, file: String) -> Option<String> {
+        Some(
+            Board::move_set(
+                &mut self.board,
+                self.rules.as_ref(),
+                &rank.parse::<Coord>().ok()?,
+                &file.parse::<Coord>().ok()?,
+            )
+            .serialize(),
+        )
+    }
 }
 
 #[derive(Clone)]
 pub struct Board {
-    pub(crate) turn: BigInt,
+    pub(crate) turn: Coord,
+    /// Whose move it is. Set up positions can hand the first move to either side.
+    pub(crate) to_move: Color,
     pub(crate) pieces: Vec<Piece>,
-    pub(crate) white_pawns: PawnRank,
-    pub(crate) black_pawns: PawnRank,
+    /// Which piece stands on each occupied square, kept in step with `pieces`.
+    pub(crate) squares: HashMap<(Coord, Coord), usize>,
+    /// The ranks of pieces stretching forever in both directions, at most one to a rank.
+    pub(crate) rows: Vec<PieceRow>,
     pub(crate) moves: Vec<Move>,
+    /// The royal kings, the ones that can be checked. Kings brought in from a row are ordinary
+    /// pieces.
     pub(crate) white_king: usize,
     pub(crate) black_king: usize,
+    /// The ranks each side's promoting pieces, like the pawns, promote on, at the far side of
+    /// the opponent's pieces as the game started.
+    pub(crate) white_promotion_rank: Coord,
+    pub(crate) black_promotion_rank: Coord,
+    /// The position before each move, with the halfmove clock after it.
+    pub(crate) history: Vec<(Position, usize)>,
+    pub(crate) claimed_draw: Option<GameStatus>,
+    /// The piece types the game is played with, which say which pieces are royal, castle and
+    /// take part in en passant.
+    pub(crate) piece_set: Arc<PieceSet>,
+}
+
+impl Default for Board {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Board {
     pub fn new() -> Self {
+        Self::with_piece_set(PieceSet::standard())
+    }
+    /// An empty board for a game played with the piece types in `piece_set`.
+    pub(crate) fn with_piece_set(piece_set: Arc<PieceSet>) -> Self {
         Self {
             turn: 0.into(),
+            to_move: Color::White,
             pieces: Vec::new(),
-            white_pawns: PawnRank::new(),
-            black_pawns: PawnRank::new(),
+            squares: HashMap::new(),
+            rows: vec![
+                PieceRow::pawns(Color::White, WHITE_PAWN_RANK.into()),
+                PieceRow::pawns(Color::Black, BLACK_PAWN_RANK.into()),
+            ],
             moves: Vec::new(),
             black_king: 0,
             white_king: 0,
+            white_promotion_rank: 0.into(),
+            black_promotion_rank: (STANDARD_BOARD_SIZE - 1).into(),
+            history: Vec::new(),
+            claimed_draw: None,
+            piece_set,
         }
     }
-    pub fn promote(&mut self, rank: &BigInt  , file: &BigInt , new_type: String) -> Option<usize> {
-        self.get_piece_at(rank, file).and_then(|i| {
-            self.pieces[i].set_type(new_type);
-            Some(i)
-        })
+    /// A fresh game starting from `setup`, with the standard pieces.
+    #[cfg(test)]
+    pub(crate) fn from_setup(setup: &Setup) -> Self {
+        let mut b = Self::new();
+        b.set_up(setup);
+        b
     }
-    pub(crate) fn place_piece(&mut self, piece: Piece) -> Option<usize> {
-        let x = piece;
-        if x.get_type() == "king" {
-            if x.get_color() == Color::White {
-                self.white_king = self.pieces.len();
-            } else {
-                self.black_king = self.pieces.len();
+    /// Starts a fresh game from `setup`, with the same piece types.
+    pub(crate) fn set_up(&mut self, setup: &Setup) {
+        *self = Self::with_piece_set(self.piece_set.clone());
+        self.rows = setup.rows();
+        for p in &setup.pieces {
+            self.place_piece(Piece::new(p.piece.clone(), p.color, p.rank.clone(), p.file.clone()));
+        }
+        for color in [Color::White, Color::Black] {
+            if let Some(rank) = setup.promotion_rank(color) {
+                self.set_promotion_rank(color, rank);
             }
         }
+        self.set_to_move(setup.to_move);
+    }
+    /// Puts a piece on the board. A royal piece placed this way, like the king, becomes its
+    /// side's royal king.
+    pub(crate) fn place_piece(&mut self, piece: Piece) -> Option<usize> {
+        let royal = self.is_royal_type(piece.get_type());
+        let color = piece.get_color();
+        let i = self.add_piece(piece);
+        if royal {
+            self.set_king(color, i);
+        }
+        Some(i)
+    }
+    fn add_piece(&mut self, x: Piece) -> usize {
+        // Whatever is placed on an infinite row takes the place of the piece there.
+        if let Some(row) = self.row_at(x.get_rank()) {
+            row.set_moved(x.get_file());
+        }
         self.pieces.push(x);
-        Some(self.pieces.len() - 1)
+        self.index(self.pieces.len() - 1);
+        self.pieces.len() - 1
+    }
+    /// Records piece `i` on its square, unless it has been captured.
+    fn index(&mut self, i: usize) {
+        let p = &self.pieces[i];
+        if !p.is_captured() {
+            self.squares.insert((p.get_rank().clone(), p.get_file().clone()), i);
+        }
+    }
+    /// Forgets piece `i`'s square, if it is the one recorded there.
+    fn unindex(&mut self, i: usize) {
+        let p = &self.pieces[i];
+        let square = (p.get_rank().clone(), p.get_file().clone());
+        if self.squares.get(&square) == Some(&i) {
+            self.squares.remove(&square);
+        }
     }
     pub(crate) fn last_move(&self) -> Option<usize> {
         self.moves.last().map(|m| m.get_piece())
     }
-    pub(crate) fn do_move_ref(
-        &mut self,
-        m: &Move
-    ) -> Option<usize> {
+    pub(crate) fn to_move(&self) -> Color {
+        self.to_move
+    }
+    pub(crate) fn set_to_move(&mut self, color: Color) {
+        self.to_move = color;
+    }
+    pub(crate) fn set_king(&mut self, color: Color, i: usize) {
+        if color == Color::White {
+            self.white_king = i;
+        } else {
+            self.black_king = i;
+        }
+    }
+    pub(crate) fn king(&self, color: Color) -> Option<usize> {
+        let i = if color == Color::White { self.white_king } else { self.black_king };
+        self.pieces
+            .get(i)
+            .filter(|p| self.is_royal_type(p.get_type()) && p.get_color() == color)
+            .map(|_| i)
+    }
+    pub(crate) fn promotion_rank(&self, color: Color) -> &Coord {
+        if color == Color::White {
+            &self.white_promotion_rank
+        } else {
+            &self.black_promotion_rank
+        }
+    }
+    pub(crate) fn set_promotion_rank(&mut self, color: Color, rank: Coord) {
+        if color == Color::White {
+            self.white_promotion_rank = rank;
+        } else {
+            self.black_promotion_rank = rank;
+        }
+    }
+    /// Whether pieces of type `kind` are royal, like the king, when placed on the board.
+    pub(crate) fn is_royal_type(&self, kind: &str) -> bool {
+        self.piece_set.is(kind, |d| d.royal)
+    }
+    /// The nearest castling partner of `color`, like the rook, along `rank` from `file` in
+    /// direction `df`, with how many files away it is and the index `piece_at` gives. Other
+    /// pieces in between don't hide it.
+    pub(crate) fn castling_partner(&self, color: Color, rank: &Coord, file: &Coord, df: i32) -> Option<(Coord, usize, Cow<'_, Piece>)> {
+        let partner = |kind: &str| self.piece_set.is(kind, |d| d.castling_partner);
+        let mut best = self
+            .pieces
+            .iter()
+            .filter(|p| !p.is_captured() && p.get_rank() == rank && p.get_color() == color && partner(p.get_type()))
+            .map(|p| (p.get_file() - file) * df)
+            .filter(|k| k > &0.into())
+            .min();
+        if let Some(row) = self.row_at(rank).filter(|row| row.get_color() == color && row.get_pattern().iter().any(|kind| partner(kind))) {
+            // Each file lost from the row puts the nearest partner at most one pattern further.
+            let mut k: Coord = 1.into();
+            while best.as_ref().map_or(true, |b| &k < b) {
+                let f = file + &k * df;
+                if !row.has_moved(&f) && partner(row.type_at(&f)) {
+                    best = Some(k);
+                    break;
+                }
+                k += 1;
+            }
+        }
+        let k = best?;
+        let (i, piece) = self.piece_at(rank, &(file + &k * df))?;
+        Some((k, i, piece))
+    }
+    /// Whether piece `i` still counts as part of its row: it was brought onto the board, say to
+    /// be looked at, but hasn't gone anywhere.
+    pub(crate) fn is_row_piece(&self, i: usize) -> bool {
+        let piece = &self.pieces[i];
+        let row = self.row_at(piece.get_rank()).filter(|row| row.get_color() == piece.get_color());
+        !piece.has_moved() && row.is_some_and(|row| row.type_at(piece.get_file()) == piece.get_type())
+    }
+    /// The square a piece taking part in en passant, like the pawn, passed if it just moved two
+    /// squares, where it can be taken en passant.
+    pub(crate) fn en_passant_square(&self) -> Option<(Coord, Coord)> {
+        let m = self.moves.last()?;
+        let motion = m.get_motions().first()?;
+        let pawn = self.piece_set.is(self.pieces[m.get_piece()].get_type(), |d| d.en_passant);
+        if pawn && (motion.get_rank() - motion.get_from_rank()).abs() == 2.into() {
+            Some(((motion.get_rank() + motion.get_from_rank()) / 2, motion.get_file().clone()))
+        } else {
+            None
+        }
+    }
+    /// The infinite row on `rank`, if there is one.
+    pub(crate) fn row_at(&self, rank: &Coord) -> Option<&PieceRow> {
+        self.rows.iter().find(|row| row.get_rank() == rank)
+    }
+    /// `color`'s infinite rows.
+    pub(crate) fn rows_of(&self, color: Color) -> impl Iterator<Item = &PieceRow> {
+        self.rows.iter().filter(move |row| row.get_color() == color)
+    }
+    /// The nearest occupied square among (rank, file) + k * (dr, df) for k >= 1, counting the
+    /// pieces still standing on the infinite rows. Returns that k and the color of whatever
+    /// is there.
+    pub(crate) fn first_blocker(&self, rank: &Coord, file: &Coord, dr: i32, df: i32) -> Option<(Coord, Color)> {
+        let mut best: Option<(Coord, Color)> = None;
+        let mut consider = |k: Coord, color: Color| {
+            if k >= 1.into() && best.as_ref().map_or(true, |(b, _)| &k < b) {
+                best = Some((k, color));
+            }
+        };
+        for p in self.pieces.iter().filter(|p| !p.is_captured()) {
+            let a = p.get_rank() - rank;
+            let b = p.get_file() - file;
+            let k = if dr != 0 { &a / dr } else { &b / df };
+            if a == &k * dr && b == &k * df {
+                consider(k, p.get_color());
+            }
+        }
+        for row in &self.rows {
+            let row_rank = row.get_rank();
+            if dr != 0 {
+                let k = (row_rank - rank) / dr;
+                if &(&k * dr + rank) == row_rank && !row.has_moved(&(file + &k * df)) {
+                    consider(k, row.get_color());
+                }
+            } else if rank == row_rank {
+                let mut k: Coord = 1.into();
+                while row.has_moved(&(file + &k * df)) {
+                    k += 1;
+                }
+                consider(k, row.get_color());
+            }
+        }
+        best
+    }
+    /// Largest absolute coordinate in use, so that anything beyond it is known to be empty
+    /// apart from the infinite rows.
+    pub(crate) fn span(&self) -> Coord {
+        let mut span: Coord = 0.into();
+        for p in self.pieces.iter().filter(|p| !p.is_captured()) {
+            span = span.max(p.get_rank().abs()).max(p.get_file().abs());
+        }
+        for row in &self.rows {
+            span = span.max(row.get_rank().abs());
+            for f in row.files() {
+                span = span.max(f.abs());
+            }
+        }
+        span
+    }
+    /// Brings the pieces a move takes off the infinite rows onto the board, and gives the move
+    /// their indices.
+    fn place_implicit(&mut self, m: Move) -> Move {
+        m.place(|rank, file| {
+            let (color, kind) = self
+                .implicit_piece(rank, file)
+                .expect("moves only take pieces that are still on their infinite rows");
+            let piece = Piece::new(kind.clone(), color, rank.clone(), file.clone());
+            self.add_piece(piece)
+        })
+    }
+    /// Plays the move on the pieces alone, without recording it in the game. Returns the move
+    /// with indices for any pieces it brought onto the board, which is what `unmake_move` needs
+    /// to take it back.
+    pub(crate) fn make_move(&mut self, m: Move) -> Move {
+        let m = self.place_implicit(m);
+        let ply = self.moves.len() + 1;
+        for i in m.get_motions().iter().map(|x| x.get_piece()).chain(m.get_captures().iter().map(|x| x.get_piece())) {
+            self.unindex(i);
+        }
         for motion in m.get_motions() {
-            self.pieces[motion.get_piece()].goto(motion.get_rank(), motion.get_file(), self.moves.len() + 1);
+            self.pieces[motion.get_piece()].goto(motion.get_rank(), motion.get_file(), ply);
+            self.index(motion.get_piece());
         }
         for capture in m.get_captures() {
             self.pieces[capture.get_piece()].capture();
         }
-        Some(0)
+        if let Some(promotion) = m.get_promotion() {
+            self.pieces[promotion.get_piece()].set_type(promotion.get_to().clone());
+        }
+        m
+    }
+    /// Takes back the last move made by `make_move`, down to when each piece first moved and
+    /// the pieces it brought onto the board, which go back to being part of their row.
+    pub(crate) fn unmake_move(&mut self, m: &Move) {
+        let ply = self.moves.len() + 1;
+        for motion in m.get_motions() {
+            self.unindex(motion.get_piece());
+        }
+        for motion in m.get_motions() {
+            let piece = &mut self.pieces[motion.get_piece()];
+            piece.goto(motion.get_from_rank(), motion.get_from_file(), ply);
+            if piece.when_moved() == ply {
+                piece.set_has_moved(0);
+            }
+            self.index(motion.get_piece());
+        }
+        for capture in m.get_captures() {
+            self.pieces[capture.get_piece()].uncapture();
+            self.index(capture.get_piece());
+        }
+        if let Some(promotion) = m.get_promotion() {
+            self.pieces[promotion.get_piece()].set_type(promotion.get_from().clone());
+        }
+        // The pieces it brought in were placed last, and go back to their rows.
+        let placed = |i: usize| {
+            m.get_motions().iter().any(|x| x.get_piece() == i && x.is_from_row())
+                || m.get_captures().iter().any(|x| x.get_piece() == i && x.is_from_row())
+        };
+        while let Some(i) = self.pieces.len().checked_sub(1).filter(|&i| placed(i)) {
+            if let Some(row) = self.row_at(self.pieces[i].get_rank()) {
+                row.set_unmoved(self.pieces[i].get_file());
+            }
+            self.unindex(i);
+            self.pieces.pop();
+        }
     }
     pub(crate) fn do_move(
         &mut self,
         m: Move
     ) -> Option<usize> {
-        self.do_move_ref(&m);
+        let before = Position::of(self);
+        let m = self.place_implicit(m);
+        let pawn = self.piece_set.is(self.pieces[m.get_piece()].get_type(), |d| d.promotes);
+        let clock = if m.get_captures().is_empty() && !pawn {
+            self.halfmove_clock() + 1
+        } else {
+            0
+        };
+        let m = self.make_move(m);
         self.moves.push(m);
+        self.history.push((before, clock));
+        self.to_move = self.to_move.opposite();
         Some(0)
     }
+    /// Takes back a draw claim, or otherwise the last move.
     pub(crate) fn undo_move(
         &mut self,
     ) -> Option<usize> {
-        let m = self.moves.pop()?;
-        for motion in m.get_motions() {
-            self.pieces[motion.get_piece()].goto(motion.get_from_rank(), motion.get_from_file(), self.moves.len() + 1);
-            if self.pieces[motion.get_piece()].when_moved() >= self.moves.len() {
-                self.pieces[motion.get_piece()].set_has_moved(0);
-            }
-        }
-        for capture in m.get_captures() {
-            self.pieces[capture.get_piece()].uncapture();
+        if self.claimed_draw.take().is_some() {
+            return Some(0);
         }
+        let m = self.moves.pop()?;
+        self.history.pop();
+        self.unmake_move(&m);
+        self.to_move = self.to_move.opposite();
         Some(0)
     }
-    pub(crate) fn get_piece_at(&mut self, rank: &BigInt, file: &BigInt) -> Option<usize> {
-        if *rank == 1.into() && !self.black_pawns.has_moved(file) {
-            self.black_pawns.set_moved(file);
-            self.place_piece(Piece::new(
-                "pawn".to_string(),
-                Color::Black,
-                rank.clone(),
-                file.clone(),
-            ));
-        }
-        if *rank == 6.into() && !self.white_pawns.has_moved(file) {
-            self.white_pawns.set_moved(file);
-            self.place_piece(Piece::new(
-                "pawn".to_string(),
-                Color::White,
-                rank.clone(),
-                file.clone(),
-            ));
-        }
-        for i in 0..self.pieces.len() {
-            if self.pieces[i].get_rank() == rank
-                && self.pieces[i].get_file() == file
-                && !self.pieces[i].is_captured()
-            {
-                return Some(i);
-            }
+    /// Moves since the last capture or move of a piece that promotes, like the pawn.
+    pub(crate) fn halfmove_clock(&self) -> usize {
+        self.history.last().map_or(0, |(_, clock)| *clock)
+    }
+    /// How many times the current position has come up, this time included. Nothing before
+    /// the last capture or pawn move can ever come up again.
+    pub(crate) fn repetitions(&self) -> usize {
+        let now = Position::of(self);
+        1 + self
+            .history
+            .iter()
+            .rev()
+            .take(self.halfmove_clock())
+            .filter(|(position, _)| position == &now)
+            .count()
+    }
+    /// The index of the piece on (rank, file). Pieces still on their infinite rows have none
+    /// yet; `piece_at` finds those too.
+    pub(crate) fn get_piece_at(&self, rank: &Coord, file: &Coord) -> Option<usize> {
+        self.squares.get(&(rank.clone(), file.clone())).copied()
+    }
+    /// The color and type of the piece on (rank, file) if it has never left its infinite row.
+    pub(crate) fn implicit_piece(&self, rank: &Coord, file: &Coord) -> Option<(Color, &String)> {
+        let row = self.row_at(rank)?;
+        Some((row.get_color(), row.piece_at(file)?))
+    }
+    /// The piece on (rank, file) with its index, which is `UNPLACED` for a piece still on its
+    /// infinite row.
+    pub(crate) fn piece_at(&self, rank: &Coord, file: &Coord) -> Option<(usize, Cow<'_, Piece>)> {
+        if let Some(i) = self.get_piece_at(rank, file) {
+            return Some((i, Cow::Borrowed(&self.pieces[i])));
         }
-        None
+        let (color, kind) = self.implicit_piece(rank, file)?;
+        Some((UNPLACED, Cow::Owned(Piece::new(kind.clone(), color, rank.clone(), file.clone()))))
     }
-    pub(crate) fn get_collision(
-        &mut self,
-        from_rank: &BigInt,
-        from_file: &BigInt,
-        to_rank: &BigInt,
-        to_file: &BigInt,
-    ) -> bool {
-        let dr = match (to_rank - from_rank).sign() {
-            num_bigint::Sign::Minus => -1,
-            num_bigint::Sign::NoSign => 0,
-            num_bigint::Sign::Plus => 1,
-        };
-        let df = match (to_file - from_file).sign() {
-            num_bigint::Sign::Minus => -1,
-            num_bigint::Sign::NoSign => 0,
-            num_bigint::Sign::Plus => 1,
-        };
-        let mut rank = from_rank.clone();
-        let mut file = from_file.clone();
-        rank += dr;
-        file += df;
-        while &rank != to_rank || &file != to_file {
-            if let Some(_) = self.get_piece_at(&rank, &file) {
-                return false;
+    /// Everything standing in the rectangle, corners included, ordered by rank and then file,
+    /// with the indices `piece_at` gives. Only the occupied squares and the rows crossing the
+    /// rectangle are looked at, so this costs the same however long the game has gone on. None
+    /// for rectangles more than `MAX_RECT_FILES` wide, since a row crossing one holds a piece on
+    /// nearly every file.
+    pub(crate) fn pieces_in_rect(&self, min_rank: &Coord, min_file: &Coord, max_rank: &Coord, max_file: &Coord) -> Option<Vec<(usize, Cow<'_, Piece>)>> {
+        let width = (max_file - min_file).to_i64().filter(|&w| w < i64::from(MAX_RECT_FILES))? + 1;
+        let inside = |rank: &Coord, file: &Coord| min_rank <= rank && rank <= max_rank && min_file <= file && file <= max_file;
+        let mut out: Vec<(usize, Cow<'_, Piece>)> = self
+            .squares
+            .iter()
+            .filter(|((rank, file), _)| inside(rank, file))
+            .map(|(_, &i)| (i, Cow::Borrowed(&self.pieces[i])))
+            .collect();
+        for row in self.rows.iter().filter(|row| min_rank <= row.get_rank() && row.get_rank() <= max_rank) {
+            for (file, kind) in row.pieces_from(min_file, width) {
+                out.push((UNPLACED, Cow::Owned(Piece::new(kind.clone(), row.get_color(), row.get_rank().clone(), file))));
             }
-            rank += dr;
-            file += df;
         }
-        true
+        out.sort_by(|(_, a), (_, b)| (a.get_rank(), a.get_file()).cmp(&(b.get_rank(), b.get_file())));
+        Some(out)
+    }
+    /// The first occupied square among (rank, file) + k * (dr, df) for 0 < k < steps. Only the
+    /// pieces and rows on the line are looked at, however far apart the ends are.
+    pub(crate) fn get_collision(
+        &self,
+        from_rank: &Coord,
+        from_file: &Coord,
+        dr: i32,
+        df: i32,
+        steps: &Coord,
+    ) -> Option<(Coord, Coord)> {
+        let (k, _) = self.first_blocker(from_rank, from_file, dr, df).filter(|(k, _)| k < steps)?;
+        Some((from_rank + &k * dr, from_file + &k * df))
     }
     pub(crate) fn move_legal_at_all(
-        s: &mut Board,
-        rules: &StandardChess,
-        from_rank: &BigInt,
-        from_file: &BigInt,
-        to_rank: &BigInt,
-        to_file: &BigInt,
-    ) -> Option<Move> {
+        s: &Board,
+        rules: &dyn PieceRules,
+        from_rank: &Coord,
+        from_file: &Coord,
+        to_rank: &Coord,
+        to_file: &Coord,
+    ) -> Result<Move, IllegalMove> {
         if from_rank == to_rank && from_file == to_file {
-            return None;
+            return Err(IllegalMove::NotMoving);
         }
-        if let Some(p) = s.get_piece_at(from_rank, from_file) {
-            let good_so_far = rules.can_move(s, p, to_rank, to_file);
-            if let Some(other) = s.get_piece_at(to_rank, to_file) {
-                if s.pieces[p].get_color() == s.pieces[other].get_color() {
-                    return None;
-                }
+        let (_, piece) = s.piece_at(from_rank, from_file).ok_or(IllegalMove::NoPiece)?;
+        if let Some((_, other)) = s.piece_at(to_rank, to_file) {
+            if piece.get_color() == other.get_color() {
+                return Err(IllegalMove::OwnPiece);
             }
-            good_so_far
-        } else {
-            None //no piece there so not legal
         }
+        rules.can_move(s, from_rank, from_file, to_rank, to_file)
     }
     pub(crate) fn move_legal(
         s: &mut Board,
-        rules: &StandardChess,
-        from_rank: &BigInt,
-        from_file: &BigInt,
-        to_rank: &BigInt,
-        to_file: &BigInt,
-    ) -> Option<Move> {
-        if from_rank == to_rank && from_file == to_file {
-            return None;
+        rules: &dyn PieceRules,
+        from_rank: &Coord,
+        from_file: &Coord,
+        to_rank: &Coord,
+        to_file: &Coord,
+        promotion: Option<String>,
+    ) -> Result<Move, IllegalMove> {
+        if s.claimed_draw.is_some() {
+            return Err(IllegalMove::GameOver);
         }
-        if let Some(p) = s.get_piece_at(from_rank, from_file) {
-            if let Some(x) = s.last_move() {
-                if s.pieces[x].get_color() == s.pieces[p].get_color() {
-                    return None;
-                }
-            } else {
-                if s.pieces[p].get_color() != Color::White {
-                    return None;
-                }
-            }
-            let good_so_far = Self::move_legal_at_all(s, rules, from_rank, from_file, to_rank, to_file);
-            good_so_far.and_then(|m| rules.would_be_in_check(s, m))
-        } else {
-            None //no piece there so not legal
+        let (_, piece) = s.piece_at(from_rank, from_file).ok_or(IllegalMove::NoPiece)?;
+        if piece.get_color() != s.to_move() {
+            return Err(IllegalMove::NotYourTurn);
         }
+        let m = Self::move_legal_at_all(s, rules, from_rank, from_file, to_rank, to_file)?;
+        let m = rules.add_promotion(s, m, promotion)?;
+        rules.would_be_in_check(s, m)
     }
     pub(crate) fn is_move_legal(
         s: &mut Board,
-        rules: &StandardChess,
-        from_rank: &BigInt,
-        from_file: &BigInt,
-        to_rank: &BigInt,
-        to_file: &BigInt,
+        rules: &dyn PieceRules,
+        from_rank: &Coord,
+        from_file: &Coord,
+        to_rank: &Coord,
+        to_file: &Coord,
     ) -> bool {
-        Self::move_legal(s, rules, from_rank, from_file, to_rank, to_file).is_some()
+        // Any promotion the rules allow will do when we only want to know whether the square is
+        // reachable.
+        let promotion = s
+            .piece_at(from_rank, from_file)
+            .filter(|(_, p)| rules.promotes(s, p, to_rank))
+            .and_then(|_| rules.piece_set().promotion.first().cloned());
+        Self::move_legal(s, rules, from_rank, from_file, to_rank, to_file, promotion).is_ok()
+    }
+    pub(crate) fn move_set(s: &mut Board, rules: &dyn PieceRules, rank: &Coord, file: &Coord) -> MoveSet {
+        rules.move_set(s, rank, file)
     }
+    pub(crate) fn game_status(s: &mut Board, rules: &dyn PieceRules) -> GameStatus {
+        rules.game_status(s)
+    }
+    /// The squares of every piece of color `by` that attacks the square.
+    pub(crate) fn attackers_of(s: &Board, rules: &dyn PieceRules, rank: &Coord, file: &Coord, by: Color) -> Vec<(Coord, Coord)> {
+        rules.attackers_of(s, rank, file, by)
+    }
+    pub(crate) fn is_attacked(s: &Board, rules: &dyn PieceRules, rank: &Coord, file: &Coord, by: Color) -> bool {
+        rules.is_attacked(s, rank, file, by)
+    }
+    /// Ends the game in a draw if the side to move is entitled to one.
+    pub(crate) fn claim_draw(s: &mut Board, rules: &dyn PieceRules) -> Option<GameStatus> {
+        let draw = rules.claimable_draw(s)?;
+        s.claimed_draw = Some(draw);
+        Some(draw)
+    }
+}
+
+#[test]
+fn test_square_index() {
+    let pm = StandardChess::new();
+    let mut b = standard_board();
+    let scan = |b: &Board| {
+        let mut squares: Vec<_> = b
+            .pieces
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| !p.is_captured())
+            .map(|(i, p)| (p.get_rank().clone(), p.get_file().clone(), i))
+            .collect();
+        squares.sort();
+        let mut indexed: Vec<_> = b.squares.iter().map(|((r, f), &i)| (r.clone(), f.clone(), i)).collect();
+        indexed.sort();
+        assert_eq!(squares, indexed);
+    };
+    // Pawn moves, a capture, and both sides castling.
+    let game = [(6, 4, 4, 4), (1, 3, 3, 3), (4, 4, 3, 3), (0, 6, 2, 5), (7, 6, 5, 5), (1, 4, 2, 4), (7, 5, 4, 2), (0, 5, 3, 2), (7, 4, 7, 6), (0, 4, 0, 6)];
+    for &(rank, file, to_rank, to_file) in &game {
+        play(&mut b, &pm, &[(rank, file, to_rank, to_file)]);
+        scan(&b);
+    }
+    assert_eq!(b.get_piece_at(&7.into(), &5.into()), Some(15));
+    assert_eq!(b.get_piece_at(&7.into(), &7.into()), None);
+    for _ in &game {
+        b.undo_move();
+        scan(&b);
+    }
+    assert_eq!(b.get_piece_at(&7.into(), &4.into()), Some(9));
+    assert_eq!(b.get_piece_at(&7.into(), &7.into()), Some(15));
+}
+
+#[test]
+fn test_make_unmake() {
+    let pm = StandardChess::new();
+    let mut b = standard_board();
+    let snapshot = |b: &Board| {
+        let mut indexed: Vec<_> = b.squares.iter().map(|((r, f), &i)| (r.clone(), f.clone(), i)).collect();
+        indexed.sort();
+        let pawns: Vec<_> = (-12..20)
+            .flat_map(|file| [BLACK_PAWN_RANK, WHITE_PAWN_RANK].iter().map(move |&rank| (rank, file)).collect::<Vec<_>>())
+            .filter(|&(rank, file)| b.implicit_piece(&rank.into(), &file.into()).is_some())
+            .collect();
+        (board_serialize(b, GameStatus::Ongoing), b.pieces.len(), indexed, pawns, b.moves.len())
+    };
+    // A knight that can take pawns straight off the infinite rank, and pawns that can double step.
+    play(&mut b, &pm, &[(7, 1, 5, 2), (1, 4, 3, 4), (5, 2, 3, 3), (0, 6, 2, 5)]);
+    let before = snapshot(&b);
+    assert_move_sets_match(&mut b, &pm, -12, 20);
+    Board::game_status(&mut b, &pm);
+    assert_eq!(snapshot(&b), before);
+
+    let m = Board::move_legal(&mut b, &pm, &3.into(), &3.into(), &1.into(), &2.into(), None).unwrap();
+    assert_eq!(snapshot(&b), before);
+    b.do_move(m);
+    assert!(b.pieces.last().unwrap().is_captured());
+    assert!(b.implicit_piece(&1.into(), &2.into()).is_none());
+    // Taking the move back puts the captured pawn back on its infinite rank.
+    b.undo_move();
+    assert_eq!(snapshot(&b), before);
+    assert_eq!(b.piece_at(&1.into(), &2.into()).map(|(i, _)| i), Some(UNPLACED));
+}
+
+#[test]
+fn test_side_to_move() {
+    let pm = StandardChess::new();
+    let mut b = Board::new();
+    b.place_piece(pm.build_piece(&"king".into(), Color::White, 10.into(), 4.into()).unwrap());
+    b.place_piece(pm.build_piece(&"king".into(), Color::Black, (-10).into(), 4.into()).unwrap());
+    b.set_to_move(Color::Black);
+    let white_first = Board::move_legal(&mut b, &pm, &10.into(), &4.into(), &11.into(), &4.into(), None);
+    assert_eq!(white_first.err(), Some(IllegalMove::NotYourTurn));
+    play(&mut b, &pm, &[(-10, 4, -11, 4), (10, 4, 11, 4), (1, 0, 2, 0)]);
+    assert_eq!(b.to_move(), Color::White);
+    b.undo_move();
+    assert_eq!(b.to_move(), Color::Black);
+    b.undo_move();
+    b.undo_move();
+    assert_eq!(b.to_move(), Color::Black);
+    assert!(b.moves.is_empty());
+}
+
+#[test]
+fn test_pieces_in_rect() {
+    let pm = StandardChess::new();
+    let mut b = standard_board();
+    let rect = |b: &Board, min_rank: i32, min_file: i32, max_rank: i32, max_file: i32| {
+        b.pieces_in_rect(&min_rank.into(), &min_file.into(), &max_rank.into(), &max_file.into())
+            .unwrap()
+            .iter()
+            .map(|(i, p)| (*i, p.get_type().clone(), p.get_rank().clone(), p.get_file().clone()))
+            .collect::<Vec<_>>()
+    };
+    let corner = rect(&b, 0, 0, 1, 1);
+    assert_eq!(corner.len(), 4);
+    assert_eq!(corner[0], (0, "rook".to_string(), 0.into(), 0.into()));
+    assert_eq!(corner[3], (UNPLACED, "pawn".to_string(), 1.into(), 1.into()));
+    // Far out there are only the pawn ranks.
+    assert_eq!(rect(&b, -5, 1000, 10, 1009).len(), 20);
+    assert!(rect(&b, 2, -500, 5, 500).is_empty());
+
+    // Captured pieces are gone, and pawns that moved are found where they went.
+    play(&mut b, &pm, &[(6, 4, 4, 4), (1, 3, 3, 3), (4, 4, 3, 3)]);
+    let middle = rect(&b, 2, 2, 5, 5);
+    assert_eq!(middle.len(), 1);
+    assert_eq!(middle[0].1, "pawn");
+    assert_eq!((middle[0].2.clone(), middle[0].3.clone()), (3.into(), 3.into()));
+    assert_eq!(rect(&b, 1, 3, 1, 3).len(), 0);
+    assert_eq!(rect(&b, 6, 4, 6, 4).len(), 0);
+    assert_eq!(rect(&b, 6, 0, 6, 7).iter().map(|x| x.3.clone()).collect::<Vec<Coord>>(), [0, 1, 2, 3, 5, 6, 7].map(Coord::from));
+
+    // A rectangle too wide to list is turned down however few pieces it holds.
+    let wide = |min_file: Coord, max_file: Coord| b.pieces_in_rect(&0.into(), &min_file, &8.into(), &max_file).is_none();
+    assert_eq!(rect(&b, 1, 0, 1, MAX_RECT_FILES - 1).len(), MAX_RECT_FILES as usize - 1);
+    assert!(wide(0.into(), MAX_RECT_FILES.into()));
+    assert!(wide(0.into(), Coord::from(10).pow(30)));
+    assert!(wide(-Coord::from(10).pow(30), 0.into()));
+    assert!(!wide(Coord::from(10).pow(30), Coord::from(10).pow(30) + 7));
 }
@@ -0,0 +1,444 @@
+use std::convert::TryFrom;
+
+use crate::coord::Coord;
+
+use crate::{
+    board::Board,
+    board_serializer::{board_data, board_from_data, BoardData, RowData, VERSION},
+    moves::{CaptureData, MoveData, MotionData},
+    piece::Color,
+    piece_rules::GameStatus,
+    piece_serializer::PieceData,
+    wire_error::WireError,
+};
+#[cfg(test)]
+use crate::{
+    board_serializer::{board_serialize, sorted},
+    illegal_move::IllegalMove,
+    moves::Move,
+    piece_rules::StandardChess,
+    position::Position,
+    setup::{Setup, REPEATING_SETUP},
+    test_util::Rng,
+};
+
+/// What binary boards start with, ahead of the version of the encoding.
+const MAGIC: &[u8; 3] = b"ICB";
+/// The version of the binary encoding, which holds the same board as JSON of `VERSION`.
+/// Version 1 held version 2 boards, without the promotion ranks.
+const BINARY_VERSION: u8 = 2;
+
+/// A board in binary, built up one field after another.
+struct Writer {
+    out: Vec<u8>,
+    /// Every piece type, numbered in the order they first come up.
+    types: Vec<String>,
+}
+
+impl Writer {
+    fn varint(&mut self, mut x: u64) {
+        while x >= 0x80 {
+            self.out.push(x as u8 | 0x80);
+            x >>= 7;
+        }
+        self.out.push(x as u8);
+    }
+    fn index(&mut self, i: usize) {
+        self.varint(i as u64);
+    }
+    /// A piece index with the flag for whether the piece came in from its row in the lowest bit.
+    fn flagged(&mut self, i: usize, flag: bool) {
+        self.varint((i as u64) << 1 | flag as u64);
+    }
+    fn coord(&mut self, x: &Coord) {
+        x.write_zigzag(&mut self.out);
+    }
+    fn string(&mut self, s: &str) {
+        self.index(s.len());
+        self.out.extend_from_slice(s.as_bytes());
+    }
+    fn kind(&mut self, kind: &str) {
+        let i = self.types.iter().position(|t| t == kind).expect("every type is in the dictionary");
+        self.index(i);
+    }
+    fn color(&mut self, color: Color) {
+        self.out.push((color == Color::Black) as u8);
+    }
+}
+
+/// Reads what `Writer` wrote, front to back.
+struct Reader<'a> {
+    input: &'a [u8],
+    types: Vec<String>,
+}
+
+fn truncated() -> WireError {
+    WireError::Invalid("the binary board ends too soon".to_string())
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, WireError> {
+        let (&b, rest) = self.input.split_first().ok_or_else(truncated)?;
+        self.input = rest;
+        Ok(b)
+    }
+    fn varint(&mut self) -> Result<u64, WireError> {
+        let mut x = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = self.byte()?;
+            if b == 0 && shift > 0 {
+                return Err(WireError::Invalid("a number is longer than it has to be".to_string()));
+            }
+            x |= ((b & 0x7f) as u64).checked_shl(shift).filter(|y| y >> shift == (b & 0x7f) as u64).ok_or_else(too_big)?;
+            if b & 0x80 == 0 {
+                return Ok(x);
+            }
+        }
+        Err(too_big())
+    }
+    fn index(&mut self) -> Result<usize, WireError> {
+        usize::try_from(self.varint()?).map_err(|_| too_big())
+    }
+    /// How many of something follow, each of which takes at least a byte.
+    fn count(&mut self) -> Result<usize, WireError> {
+        Some(self.index()?).filter(|&n| n <= self.input.len()).ok_or_else(truncated)
+    }
+    fn flagged(&mut self) -> Result<(usize, bool), WireError> {
+        let x = self.varint()?;
+        Ok((usize::try_from(x >> 1).map_err(|_| too_big())?, x & 1 == 1))
+    }
+    fn coord(&mut self) -> Result<Coord, WireError> {
+        Coord::read_zigzag(&mut self.input).ok_or_else(|| WireError::Invalid("a coordinate is cut short or longer than it has to be".to_string()))
+    }
+    fn string(&mut self) -> Result<String, WireError> {
+        let n = self.count()?;
+        let (s, rest) = self.input.split_at(n);
+        self.input = rest;
+        String::from_utf8(s.to_vec()).map_err(|_| WireError::Invalid("a string isn't UTF-8".to_string()))
+    }
+    fn kind(&mut self) -> Result<String, WireError> {
+        let i = self.index()?;
+        self.types.get(i).cloned().ok_or_else(|| WireError::Invalid(format!("there is no piece type {}", i)))
+    }
+    fn color(&mut self) -> Result<Color, WireError> {
+        match self.byte()? {
+            0 => Ok(Color::White),
+            1 => Ok(Color::Black),
+            b => Err(WireError::Invalid(format!("{} isn't a color", b))),
+        }
+    }
+}
+
+fn too_big() -> WireError {
+    WireError::Invalid("a number is too big".to_string())
+}
+
+/// The board in binary, holding what `board_serialize` does in far fewer bytes. Coordinates
+/// are zigzag varints, piece types are numbers into a dictionary at the front, and each move
+/// is written from where the one before it ended.
+pub(crate) fn board_encode(b: &Board, status: GameStatus) -> Vec<u8> {
+    let data = board_data(b, status);
+    let rows = data.rows.unwrap_or_default();
+    let mut types: Vec<String> = Vec::new();
+    let promotions = data.moves.iter().filter_map(|m| m.promotion.as_ref()).flat_map(|(_, from, to)| [from, to]);
+    let patterns = rows.iter().flat_map(|row| row.pattern.iter());
+    for kind in data.pieces.iter().map(|p| &p.piece).chain(patterns).chain(promotions) {
+        if !types.contains(kind) {
+            types.push(kind.clone());
+        }
+    }
+    let mut w = Writer { out: MAGIC.to_vec(), types };
+    w.out.push(BINARY_VERSION);
+    w.coord(&data.turn);
+    w.color(data.to_move.unwrap_or(Color::White));
+    for rank in [&data.white_promotion_rank, &data.black_promotion_rank] {
+        w.coord(rank.as_ref().expect("boards always have promotion ranks"));
+    }
+    w.string(data.status.as_deref().unwrap_or_default());
+    w.index(w.types.len());
+    for kind in w.types.clone() {
+        w.string(&kind);
+    }
+
+    w.index(rows.len());
+    for row in &rows {
+        w.color(row.color);
+        w.coord(&row.rank);
+        w.index(row.pattern.len());
+        for kind in &row.pattern {
+            w.kind(kind);
+        }
+        // The files in order, each but the first as how far it is past the one before.
+        let mut moved = row.moved.clone();
+        moved.sort();
+        w.index(moved.len());
+        let mut last = Coord::default();
+        for file in &moved {
+            w.coord(&(file - &last));
+            last = file.clone();
+        }
+    }
+
+    w.index(data.pieces.len());
+    for p in &data.pieces {
+        w.kind(&p.piece);
+        w.out.push((p.color == Color::Black) as u8 | (!p.alive as u8) << 1);
+        w.coord(&p.y);
+        w.coord(&p.x);
+        w.index(p.has_moved);
+    }
+    // The royal kings, one past their index, with 0 for none.
+    for king in [data.white_king, data.black_king] {
+        w.index(king.flatten().map_or(0, |i| i + 1));
+    }
+
+    // Every square is written as how far it is from the last one before it: a motion starts
+    // from where the one before it ended, and ends relative to where it started.
+    w.index(data.moves.len());
+    let (mut rank, mut file) = (Coord::default(), Coord::default());
+    for m in &data.moves {
+        w.index(m.piece);
+        let motions: Vec<_> = m.motions.iter().filter_map(|x| if let MotionData::Full(i, fr, ff, tr, tf, row) = x { Some((i, fr, ff, tr, tf, row)) } else { None }).collect();
+        let captures: Vec<_> = m.captures.iter().filter_map(|x| if let CaptureData::Full(i, r, f, row) = x { Some((i, r, f, row)) } else { None }).collect();
+        w.index(motions.len());
+        for &(i, from_rank, from_file, to_rank, to_file, row) in &motions {
+            w.flagged(*i, *row);
+            w.coord(&(from_rank - &rank));
+            w.coord(&(from_file - &file));
+            w.coord(&(to_rank - from_rank));
+            w.coord(&(to_file - from_file));
+            rank = to_rank.clone();
+            file = to_file.clone();
+        }
+        w.index(captures.len());
+        for &(i, capture_rank, capture_file, row) in &captures {
+            w.flagged(*i, *row);
+            w.coord(&(capture_rank - &rank));
+            w.coord(&(capture_file - &file));
+        }
+        match &m.promotion {
+            None => w.out.push(0),
+            Some((i, from, to)) => {
+                w.out.push(1);
+                w.index(*i);
+                w.kind(from);
+                w.kind(to);
+            }
+        }
+    }
+    w.out
+}
+
+/// Replaces the board with one `board_encode` wrote. The board is left as it was if the bytes
+/// don't make one.
+pub(crate) fn board_decode(b: &mut Board, bytes: &[u8]) -> Result<(), WireError> {
+    let input = bytes.strip_prefix(MAGIC).ok_or_else(|| WireError::Invalid("not a binary board".to_string()))?;
+    let mut r = Reader { input, types: Vec::new() };
+    let version = r.byte()?;
+    if !(1..=BINARY_VERSION).contains(&version) {
+        return Err(WireError::Version(version.into()));
+    }
+    let turn = r.coord()?;
+    let to_move = r.color()?;
+    let promotion_ranks = if version > 1 { [Some(r.coord()?), Some(r.coord()?)] } else { [None, None] };
+    let status = r.string()?;
+    for _ in 0..r.count()? {
+        let kind = r.string()?;
+        r.types.push(kind);
+    }
+
+    let mut rows = Vec::new();
+    for _ in 0..r.count()? {
+        let (color, rank) = (r.color()?, r.coord()?);
+        let pattern = (0..r.count()?).map(|_| r.kind()).collect::<Result<Vec<String>, WireError>>()?;
+        let mut moved = Vec::new();
+        let mut last = Coord::default();
+        for _ in 0..r.count()? {
+            last = &last + &r.coord()?;
+            moved.push(last.clone());
+        }
+        rows.push(RowData { color, rank, pattern, moved });
+    }
+
+    let mut pieces = Vec::new();
+    for _ in 0..r.count()? {
+        let piece = r.kind()?;
+        let flags = r.byte()?;
+        if flags > 3 {
+            return Err(WireError::Invalid(format!("{} isn't a color and whether the piece is taken", flags)));
+        }
+        let color = if flags & 1 == 1 { Color::Black } else { Color::White };
+        let (y, x) = (r.coord()?, r.coord()?);
+        let kind = format!("{}_{}", color.to_string(), piece);
+        pieces.push(PieceData { kind, piece, alive: flags & 2 == 0, y, x, color, has_moved: r.index()? });
+    }
+    let mut kings = [None, None];
+    for king in kings.iter_mut() {
+        *king = r.index()?.checked_sub(1);
+    }
+
+    let mut moves = Vec::new();
+    let (mut rank, mut file) = (Coord::default(), Coord::default());
+    for ply in 1..=r.count()? {
+        let piece = r.index()?;
+        let mut motions = Vec::new();
+        for _ in 0..r.count()? {
+            let (i, row) = r.flagged()?;
+            let from_rank = &rank + &r.coord()?;
+            let from_file = &file + &r.coord()?;
+            rank = &from_rank + &r.coord()?;
+            file = &from_file + &r.coord()?;
+            motions.push(MotionData::Full(i, from_rank, from_file, rank.clone(), file.clone(), row));
+        }
+        let mut captures = Vec::new();
+        for _ in 0..r.count()? {
+            let (i, row) = r.flagged()?;
+            captures.push(CaptureData::Full(i, &rank + &r.coord()?, &file + &r.coord()?, row));
+        }
+        let promotion = match r.byte()? {
+            0 => None,
+            1 => Some((r.index()?, r.kind()?, r.kind()?)),
+            b => return Err(WireError::Invalid(format!("{} doesn't say whether the move promotes", b))),
+        };
+        moves.push(MoveData { ply: Some(ply), motions, captures, promotion, piece });
+    }
+    if !r.input.is_empty() {
+        return Err(WireError::Invalid(format!("{} bytes are left over after the board", r.input.len())));
+    }
+
+    let [white_promotion_rank, black_promotion_rank] = promotion_ranks;
+    let data = BoardData {
+        version: if version > 1 { VERSION } else { 2 },
+        turn,
+        to_move: Some(to_move),
+        pieces,
+        rows: Some(rows),
+        white_king: Some(kings[0]),
+        black_king: Some(kings[1]),
+        white_promotion_rank,
+        black_promotion_rank,
+        moves,
+        status: Some(status),
+        white_pawn_rank: None,
+        black_pawn_rank: None,
+        white_pawns: None,
+        black_pawns: None,
+    };
+    board_from_data(b, data)
+}
+
+/// A legal move for the side to move, tried at random: mostly by pieces on the board, and
+/// sometimes by one still on its row, promoting to whatever comes up.
+#[cfg(test)]
+fn random_move(b: &mut Board, pm: &StandardChess, rng: &mut Rng) -> Option<Move> {
+    let color = b.to_move();
+    for _ in 0..2000 {
+        let rows: Vec<Coord> = b.rows.iter().filter(|r| r.get_color() == color).map(|r| r.get_rank().clone()).collect();
+        let own: Vec<(Coord, Coord)> = b.pieces.iter().filter(|p| !p.is_captured() && p.get_color() == color).map(|p| (p.get_rank().clone(), p.get_file().clone())).collect();
+        let (rank, file) = if own.is_empty() || (!rows.is_empty() && rng.below(3) == 0) {
+            (rows.get(rng.below(rows.len().max(1)))?.clone(), rng.offset(20))
+        } else {
+            own[rng.below(own.len())].clone()
+        };
+        let reach = if rng.below(2) == 0 { 2 } else { 7 };
+        let (to_rank, to_file) = (&rank + &rng.offset(reach), &file + &rng.offset(reach));
+        match Board::move_legal(b, pm, &rank, &file, &to_rank, &to_file, None) {
+            Ok(m) => return Some(m),
+            Err(IllegalMove::PromotionRequired) => {
+                let promotion = ["queen", "rook", "bishop", "knight"][rng.below(4)].to_string();
+                return Board::move_legal(b, pm, &rank, &file, &to_rank, &to_file, Some(promotion)).ok();
+            }
+            Err(_) => {}
+        }
+    }
+    None
+}
+
+#[test]
+fn binary_round_trip() {
+    let pm = StandardChess::new();
+    // Kings with pawns a step from promoting, on top of the pawn rows.
+    let promoting = Setup::parse(
+        r#"{"pieces": [
+            {"piece": "king", "color": "white", "rank": 7, "file": 4}, {"piece": "king", "color": "black", "rank": 0, "file": 4},
+            {"piece": "pawn", "color": "white", "rank": 1, "file": 0}, {"piece": "pawn", "color": "white", "rank": 1, "file": 2},
+            {"piece": "pawn", "color": "black", "rank": 6, "file": 5}, {"piece": "pawn", "color": "black", "rank": 6, "file": 7}],
+        "pawns": [{"color": "white", "rank": 6}, {"color": "black", "rank": 1}]}"#,
+    )
+    .unwrap();
+    for (seed, setup) in [(1, Setup::standard()), (2, Setup::parse(REPEATING_SETUP).unwrap()), (3, promoting)] {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d * seed);
+        let mut b = Board::from_setup(&setup);
+        b.turn = -Coord::from(10).pow(25) * Coord::from(seed as i64);
+        for _ in 0..40 {
+            let m = match random_move(&mut b, &pm, &mut rng) {
+                Some(m) => m,
+                None => break,
+            };
+            b.do_move(m);
+            let bytes = board_encode(&b, GameStatus::Ongoing);
+            assert!(bytes.len() * 4 < board_serialize(&b, GameStatus::Ongoing).len());
+            let mut read = Board::new();
+            board_decode(&mut read, &bytes).unwrap();
+            assert_eq!(sorted(&read), sorted(&b));
+            assert!(Position::of(&read) == Position::of(&b));
+            assert_eq!((read.halfmove_clock(), read.repetitions()), (b.halfmove_clock(), b.repetitions()));
+            assert_eq!(board_encode(&read, GameStatus::Ongoing), bytes);
+        }
+        assert!(b.moves.len() > 20, "only {} moves with seed {}", b.moves.len(), seed);
+        // Every move can be taken back after decoding, just as before it.
+        let mut read = Board::new();
+        board_decode(&mut read, &board_encode(&b, GameStatus::Ongoing)).unwrap();
+        while read.undo_move().is_some() {
+            assert!(b.undo_move().is_some());
+            assert!(Position::of(&read) == Position::of(&b));
+            assert_eq!(sorted(&read), sorted(&b));
+        }
+        assert!(b.moves.is_empty());
+    }
+}
+
+#[test]
+fn binary_errors() {
+    let pm = StandardChess::new();
+    let mut rng = Rng(0x1234_5678_9abc_def1);
+    let mut b = Board::from_setup(&Setup::standard());
+    for _ in 0..30 {
+        let m = random_move(&mut b, &pm, &mut rng).unwrap();
+        b.do_move(m);
+    }
+    let bytes = board_encode(&b, GameStatus::Ongoing);
+    let mut read = Board::new();
+    let before = sorted(&read);
+    // Cut short anywhere, the board doesn't decode and is left as it was.
+    for end in 0..bytes.len() {
+        assert!(board_decode(&mut read, &bytes[..end]).is_err());
+        assert_eq!(sorted(&read), before);
+    }
+    let mut longer = bytes.clone();
+    longer.push(0);
+    assert!(matches!(board_decode(&mut read, &longer), Err(WireError::Invalid(_))));
+    let mut newer = bytes.clone();
+    newer[3] = 3;
+    assert_eq!(board_decode(&mut read, &newer), Err(WireError::Version(3)));
+    assert!(matches!(board_decode(&mut read, b"{\"turn\": \"0\"}"), Err(WireError::Invalid(_))));
+    // Bytes changed at random either decode or are turned down, but never panic.
+    for _ in 0..2000 {
+        let mut changed = bytes.clone();
+        for _ in 0..1 + rng.below(3) {
+            let i = 4 + rng.below(changed.len() - 4);
+            changed[i] ^= 1 << rng.below(8);
+        }
+        if board_decode(&mut read, &changed).is_err() {
+            assert_eq!(sorted(&read), before);
+        }
+        read = Board::new();
+    }
+    // Version 1 has no promotion ranks, which here are the two bytes after the turn and the
+    // side to move.
+    let mut older = bytes.clone();
+    older[3] = 1;
+    older.drain(6..8);
+    board_decode(&mut read, &older).unwrap();
+    assert_eq!(sorted(&read), sorted(&b));
+}
@@ -0,0 +1,156 @@
+use crate::coord::Coord;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    board::Board,
+    moves::{Move, MoveData, UNPLACED},
+    piece::Color,
+    piece_rules::GameStatus,
+};
+
+/// What happened to a board after its first `ply` moves, as it is sent: the moves since, as
+/// they are saved, any draw claimed, and where that leaves the game.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DeltaData {
+    turn: Coord,
+    ply: usize,
+    moves: Vec<MoveData>,
+    claimed_draw: Option<String>,
+    to_move: Color,
+    status: String,
+}
+
+/// Whether move `m`, as it was before it was made, fits the board: every piece it names has
+/// to stand where the move says it does.
+fn fits(b: &Board, m: &Move) -> bool {
+    let stands = |i: usize, rank: &Coord, file: &Coord| match i {
+        UNPLACED => b.implicit_piece(rank, file).is_some(),
+        i => b.pieces.get(i).is_some_and(|p| !p.is_captured() && p.get_rank() == rank && p.get_file() == file),
+    };
+    !m.get_motions().is_empty()
+        && m.get_motions().iter().all(|x| stands(x.get_piece(), x.get_from_rank(), x.get_from_file()))
+        && m.get_captures().iter().all(|x| stands(x.get_piece(), x.get_rank(), x.get_file()))
+        && [Some(m.get_piece()), m.get_promotion().map(|p| p.get_piece())]
+            .iter()
+            .flatten()
+            .all(|&i| i == UNPLACED || i < b.pieces.len())
+}
+
+/// What happened to the board after its first `ply` moves, as JSON. None if the board hasn't
+/// got that far.
+pub(crate) fn delta_serialize(b: &Board, ply: usize, status: GameStatus) -> Option<String> {
+    let moves = b.moves.get(ply..)?.iter().enumerate().map(|(i, m)| m.data(ply + i + 1)).collect();
+    let data = DeltaData {
+        turn: b.turn.clone(),
+        ply,
+        moves,
+        claimed_draw: b.claimed_draw.map(|d| d.to_string()),
+        to_move: b.to_move(),
+        status: status.to_string(),
+    };
+    Some(serde_json::to_string(&data).expect("deltas always make JSON"))
+}
+
+/// Brings the board up to date with a delta from `delta_serialize`. Moves the board already
+/// has are skipped as long as they are the ones the delta lists, so the same delta can arrive
+/// twice. The rest are made as they were before they were made on the other board, which
+/// brings in the pieces they take from the infinite rows under the same indices. Returns the
+/// number of moves made, or None without touching the board if the delta starts past the end
+/// of the game so far, disagrees with the moves the board has or doesn't fit it.
+pub(crate) fn delta_apply(b: &mut Board, s: &str) -> Option<usize> {
+    let data: DeltaData = serde_json::from_str(s).ok()?;
+    let claimed_draw = match data.claimed_draw.as_deref() {
+        None => None,
+        Some("repetition") => Some(GameStatus::Repetition),
+        Some("fifty moves") => Some(GameStatus::FiftyMoves),
+        _ => return None,
+    };
+    let ply = data.ply;
+    let skip = b.moves.len().checked_sub(ply)?;
+    if b.moves[ply..].iter().zip(&data.moves).enumerate().any(|(i, (have, sent))| have.data(ply + i + 1) != *sent) {
+        return None;
+    }
+    let moves = data
+        .moves
+        .into_iter()
+        .enumerate()
+        .skip(skip)
+        .map(|(i, m)| m.into_move(ply + i + 1).map(|m| m.unplaced()))
+        .collect::<Result<Vec<Move>, _>>()
+        .ok()?;
+    let before = b.claimed_draw.take();
+    let mut made = 0;
+    for m in moves {
+        if !fits(b, &m) {
+            for _ in 0..made {
+                b.undo_move();
+            }
+            b.claimed_draw = before;
+            return None;
+        }
+        b.do_move(m);
+        made += 1;
+    }
+    b.turn = data.turn;
+    b.claimed_draw = claimed_draw;
+    Some(made)
+}
+
+#[test]
+fn catch_up() {
+    use crate::piece_rules::StandardChess;
+    use crate::piece_serializer::piece_serialize;
+    use crate::position::Position;
+    use crate::setup::Setup;
+    let pm = StandardChess::new();
+    let same = |a: &Board, b: &Board| {
+        let pieces = |x: &Board| x.pieces.iter().map(piece_serialize).collect::<Vec<String>>();
+        Position::of(a) == Position::of(b) && pieces(a) == pieces(b) && a.moves.len() == b.moves.len() && a.turn == b.turn
+    };
+    let mut server = Board::from_setup(&Setup::standard());
+    let mut client = server.clone();
+    let play = |b: &mut Board, moves: &[(i32, i32, i32, i32)]| {
+        for &(rank, file, to_rank, to_file) in moves {
+            let m = Board::move_legal(b, &pm, &rank.into(), &file.into(), &to_rank.into(), &to_file.into(), None).unwrap();
+            b.do_move(m);
+            b.turn += 1;
+        }
+    };
+    // Pawns from the infinite ranks, a capture of one and castling.
+    play(&mut server, &[(6, 4, 4, 4), (1, 3, 3, 3), (4, 4, 3, 3), (0, 6, 2, 5), (7, 6, 5, 5), (1, 100, 2, 100)]);
+    let delta = delta_serialize(&server, 0, GameStatus::Ongoing).unwrap();
+    assert_eq!(delta_apply(&mut client, &delta), Some(6));
+    assert!(same(&server, &client));
+    play(&mut server, &[(7, 5, 4, 2), (2, 100, 3, 100), (7, 4, 7, 6)]);
+    let delta = delta_serialize(&server, 6, GameStatus::Ongoing).unwrap();
+    // Only what happened since goes over the wire.
+    assert_eq!(delta.matches("motions").count(), 3);
+    assert_eq!(delta_apply(&mut client, &delta), Some(3));
+    assert!(same(&server, &client));
+    // Applying it again changes nothing, and the moves still come off one by one.
+    assert_eq!(delta_apply(&mut client, &delta), Some(0));
+    assert!(same(&server, &client));
+    client.undo_move();
+    assert!(client.get_piece_at(&7.into(), &7.into()).is_some());
+
+    // A delta from further on than the board, or one that doesn't fit it, leaves it alone.
+    let mut behind = Board::from_setup(&Setup::standard());
+    assert_eq!(delta_apply(&mut behind, &delta), None);
+    let mut other = Board::from_setup(&Setup::standard());
+    play(&mut other, &[(6, 0, 5, 0), (1, 0, 2, 0), (6, 1, 5, 1), (1, 1, 2, 1), (6, 2, 5, 2), (1, 2, 2, 2)]);
+    let mut half = other.clone();
+    half.undo_move();
+    let known = half.clone();
+    let wrong = delta_serialize(&server, 5, GameStatus::Ongoing).unwrap();
+    assert_eq!(delta_apply(&mut half, &wrong), None);
+    assert!(same(&half, &known));
+    // Nor does one whose moves the board already has differently, even if the rest would fit.
+    let mut ahead = Board::from_setup(&Setup::standard());
+    play(&mut ahead, &[(6, 7, 5, 7), (1, 7, 2, 7), (6, 4, 4, 4)]);
+    let mut diverged = Board::from_setup(&Setup::standard());
+    play(&mut diverged, &[(6, 7, 4, 7), (1, 7, 3, 7)]);
+    let known = diverged.clone();
+    assert_eq!(delta_apply(&mut diverged, &delta_serialize(&ahead, 0, GameStatus::Ongoing).unwrap()), None);
+    assert!(same(&diverged, &known));
+}
@@ -1,45 +1,246 @@
-use num_bigint::BigInt;
+use crate::coord::Coord;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::{
+    board::{Board, BLACK_PAWN_RANK, WHITE_PAWN_RANK},
+    moves::{Move, MoveData, UNPLACED},
+    piece::Color,
+    piece_row::PieceRow,
+    piece_rules::GameStatus,
+    piece_serializer::PieceData,
+    wire_error::WireError,
+};
+#[cfg(test)]
+use crate::piece::Piece;
+#[cfg(test)]
+use crate::{piece_rules::StandardChess, position::Position, setup::Setup};
+#[cfg(test)]
 use serde_json::Value;
 
-use crate::{board::Board, piece::{Color, Piece}, moves::Move};
-//#[cfg(feature = "server")]
-//use crate::board::Board;
-use crate::piece_serializer::piece_deserialize;
-use crate::piece_serializer::piece_serialize;
+/// The version of the format `board_serialize` writes. Boards saved before the format had
+/// versions are version 1.
+pub(crate) const VERSION: u32 = 3;
+
+fn first_version() -> u32 {
+    1
+}
+
+/// Tells a field that is null apart from one that isn't there at all, which reads as None.
+fn present<'de, D: Deserializer<'de>, T: Deserialize<'de>>(d: D) -> Result<Option<Option<T>>, D::Error> {
+    Option::<T>::deserialize(d).map(Some)
+}
+
+/// An infinite row as it is saved, with the files it has lost.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct RowData {
+    pub(crate) color: Color,
+    pub(crate) rank: Coord,
+    pub(crate) pattern: Vec<String>,
+    pub(crate) moved: Vec<Coord>,
+}
+
+/// A board as it is saved and sent. Version 1 boards may leave out the side to move, the
+/// rows and the royal kings, and the oldest of them have the two pawn ranks instead of rows.
+/// Boards before version 3 leave out the promotion ranks, which were always the standard ones.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct BoardData {
+    #[serde(default = "first_version")]
+    pub(crate) version: u32,
+    pub(crate) turn: Coord,
+    #[serde(default)]
+    pub(crate) to_move: Option<Color>,
+    pub(crate) pieces: Vec<PieceData>,
+    #[serde(default)]
+    pub(crate) rows: Option<Vec<RowData>>,
+    #[serde(default, deserialize_with = "present")]
+    pub(crate) white_king: Option<Option<usize>>,
+    #[serde(default, deserialize_with = "present")]
+    pub(crate) black_king: Option<Option<usize>>,
+    #[serde(default)]
+    pub(crate) white_promotion_rank: Option<Coord>,
+    #[serde(default)]
+    pub(crate) black_promotion_rank: Option<Coord>,
+    pub(crate) moves: Vec<MoveData>,
+    /// How the game stands, for the frontend. Reading a board works it out again.
+    #[serde(default)]
+    pub(crate) status: Option<String>,
+    #[serde(default, deserialize_with = "present", skip_serializing)]
+    pub(crate) white_pawn_rank: Option<Option<Coord>>,
+    #[serde(default, deserialize_with = "present", skip_serializing)]
+    pub(crate) black_pawn_rank: Option<Option<Coord>>,
+    #[serde(default, skip_serializing)]
+    pub(crate) white_pawns: Option<Vec<Coord>>,
+    #[serde(default, skip_serializing)]
+    pub(crate) black_pawns: Option<Vec<Coord>>,
+}
+
+fn invalid<T>(s: String) -> Result<T, WireError> {
+    Err(WireError::Invalid(s))
+}
+
+/// Turns down move `m`, the `ply`th, if it names a piece the board doesn't have.
+fn check_pieces(b: &Board, m: &Move, ply: usize) -> Result<(), WireError> {
+    let mut indices = m
+        .get_motions()
+        .iter()
+        .map(|x| x.get_piece())
+        .chain(m.get_captures().iter().map(|x| x.get_piece()))
+        .chain(m.get_promotion().map(|x| x.get_piece()))
+        .chain([m.get_piece()]);
+    match indices.find(|&j| j >= b.pieces.len()) {
+        Some(j) => invalid(format!("move {} names piece {}, and there are only {}", ply, j, b.pieces.len())),
+        None => Ok(()),
+    }
+}
+
+/// The board as it is saved, whether as JSON or in binary.
+pub(crate) fn board_data(b: &Board, status: GameStatus) -> BoardData {
+    let rows = b
+        .rows
+        .iter()
+        .map(|row| RowData { color: row.get_color(), rank: row.get_rank().clone(), pattern: row.get_pattern().to_vec(), moved: row.files() })
+        .collect();
+    BoardData {
+        version: VERSION,
+        turn: b.turn.clone(),
+        to_move: Some(b.to_move()),
+        pieces: b.pieces.iter().map(PieceData::from).collect(),
+        rows: Some(rows),
+        white_king: Some(b.king(Color::White)),
+        black_king: Some(b.king(Color::Black)),
+        white_promotion_rank: Some(b.promotion_rank(Color::White).clone()),
+        black_promotion_rank: Some(b.promotion_rank(Color::Black).clone()),
+        moves: b.moves.iter().enumerate().map(|(i, m)| m.data(i + 1)).collect(),
+        status: Some(status.to_string()),
+        white_pawn_rank: None,
+        black_pawn_rank: None,
+        white_pawns: None,
+        black_pawns: None,
+    }
+}
 
-pub(crate) fn board_serialize(b: &Board) -> String {
-    let mut out = String::new();
-    let mut pieces = Vec::new();
+pub(crate) fn board_serialize(b: &Board, status: GameStatus) -> String {
+    serde_json::to_string(&board_data(b, status)).expect("boards always make JSON")
+}
+
+fn row_deserialize(row: RowData) -> Result<PieceRow, WireError> {
+    if row.pattern.is_empty() || row.pattern.iter().any(|p| p.is_empty()) {
+        return invalid(format!("the {} row on rank {} has no pieces in its pattern", row.color.to_string(), row.rank));
+    }
+    let read = PieceRow::new(row.color, row.rank, row.pattern);
+    for f in &row.moved {
+        read.set_moved(f);
+    }
+    Ok(read)
+}
 
-    for piece in &b.pieces {
-        pieces.push(piece_serialize(piece));
+/// The infinite pawn rank of boards saved before there were other rows: none for null, and
+/// the standard one for boards saved before the ranks were written down, with the files it
+/// has lost.
+fn pawn_rank_deserialize(color: Color, rank: Option<Option<Coord>>, files: Option<Vec<Coord>>, default: i32) -> Result<Option<PieceRow>, WireError> {
+    let row = match rank {
+        None => PieceRow::pawns(color, default.into()),
+        Some(None) => return Ok(None),
+        Some(Some(rank)) => PieceRow::pawns(color, rank),
+    };
+    match files {
+        Some(files) => files.iter().for_each(|f| row.set_moved(f)),
+        None => return invalid(format!("the {} pawns don't say which files they have left", color.to_string())),
     }
-    out += &format!("{{\"turn\": \"{}\", \"pieces\": [{}], \"white_pawns\": [{}], \"black_pawns\": [{}], \"moves\": [{}]}}", b.turn, pieces.join(","), b.white_pawns.to_string(), b.black_pawns.to_string(), 
-    b.moves.iter().map(|x| x.serialize()).collect::<Vec<String>>().join(","));
+    Ok(Some(row))
+}
 
-    out
+/// Replaces the board with one `board_serialize` wrote, in this version or an older one. The
+/// board is left as it was if the text doesn't make one.
+pub(crate) fn board_deserialize(b: &mut Board, s: &str) -> Result<(), WireError> {
+    board_from_data(b, serde_json::from_str(s)?)
 }
 
-pub(crate) fn board_deserialize(b: &mut Board, s: &String) -> Option<i32> {
-    b.white_pawns.clear();
-    b.black_pawns.clear();
-    b.moves.clear();
-    b.pieces.clear();
-    let v: Value = serde_json::from_str(s).ok()?;
-    b.turn = v["turn"].as_str()?.parse::<BigInt>().ok()?;
-    for p in v["pieces"].as_array()? {
-        b.place_piece(piece_deserialize(&p.to_string())?);
+/// Replaces the board with the saved one, checking that it adds up and playing its moves
+/// again for the positions that came before. The board is left as it was if it doesn't.
+pub(crate) fn board_from_data(b: &mut Board, data: BoardData) -> Result<(), WireError> {
+    if !(1..=VERSION).contains(&data.version) {
+        return Err(WireError::Version(data.version));
+    }
+    let pawn_ranks = data.white_pawn_rank.is_some() || data.black_pawn_rank.is_some() || data.white_pawns.is_some() || data.black_pawns.is_some();
+    if data.rows.is_some() && pawn_ranks {
+        return invalid("the board has both rows and pawn ranks".to_string());
+    }
+    let complete = data.rows.is_some() && data.to_move.is_some() && data.white_king.is_some() && data.black_king.is_some();
+    if data.version > 1 && !complete {
+        return invalid(format!("version {} boards give the rows, the side to move and both kings", data.version));
+    }
+    let promotion_ranks = data.white_promotion_rank.is_some() && data.black_promotion_rank.is_some();
+    if data.version > 2 && !promotion_ranks {
+        return invalid(format!("version {} boards give both promotion ranks", data.version));
+    }
+    let mut read = Board::with_piece_set(b.piece_set.clone());
+    read.turn = data.turn;
+    for (color, rank) in [(Color::White, data.white_promotion_rank), (Color::Black, data.black_promotion_rank)] {
+        if let Some(rank) = rank {
+            read.set_promotion_rank(color, rank);
+        }
+    }
+    read.rows = match data.rows {
+        Some(rows) => rows.into_iter().map(row_deserialize).collect::<Result<Vec<PieceRow>, WireError>>()?,
+        None => {
+            let white = pawn_rank_deserialize(Color::White, data.white_pawn_rank, data.white_pawns, WHITE_PAWN_RANK)?;
+            let black = pawn_rank_deserialize(Color::Black, data.black_pawn_rank, data.black_pawns, BLACK_PAWN_RANK)?;
+            white.into_iter().chain(black).collect()
+        }
+    };
+    for p in data.pieces {
+        read.place_piece(p.into_piece()?);
     }
-    for p in v["white_pawns"].as_array()? {
-        b.white_pawns.set_moved(&p.to_string().parse::<BigInt>().ok()?);
+    // Kings brought in from a row aren't royal, so the royal ones are written down.
+    for (color, king) in [(Color::White, data.white_king), (Color::Black, data.black_king)] {
+        match king {
+            None => {}
+            Some(None) => read.set_king(color, usize::MAX),
+            Some(Some(i)) if read.pieces.get(i).is_some_and(|p| read.is_royal_type(p.get_type()) && p.get_color() == color) => read.set_king(color, i),
+            Some(Some(i)) => return invalid(format!("piece {} isn't a {} king", i, color.to_string())),
+        }
     }
-    for p in v["black_pawns"].as_array()? {
-        b.black_pawns.set_moved(&p.to_string().parse::<BigInt>().ok()?);
+    for (i, m) in data.moves.into_iter().enumerate() {
+        let m = m.into_move(i + 1)?;
+        check_pieces(&read, &m, i + 1)?;
+        read.moves.push(m);
     }
-    for p in v["moves"].as_array()? {
-        b.moves.push(Move::deserialize(&p.to_string())?);
+    let to_move = match data.to_move {
+        Some(color) => color,
+        // Boards saved before the side to move was written down: whoever didn't move last.
+        None => read.last_move().map_or(Color::White, |i| read.pieces[i].get_color().opposite()),
+    };
+    read.set_to_move(to_move);
+    // Moves saved with where they went can be taken back and made again, which gives back the
+    // positions that came before for the draw rules.
+    if read.moves.iter().all(|m| !m.get_motions().is_empty()) {
+        let mut moves = Vec::new();
+        while let Some(m) = read.moves.last().cloned() {
+            // Taking a move back sends the pieces it brought in back to their rows, so no move
+            // before it may name them.
+            check_pieces(&read, &m, read.moves.len())?;
+            read.undo_move();
+            moves.push(m.unplaced());
+        }
+        for m in moves.into_iter().rev() {
+            let squares = m
+                .get_motions()
+                .iter()
+                .map(|x| (x.get_piece(), x.get_from_rank(), x.get_from_file()))
+                .chain(m.get_captures().iter().map(|x| (x.get_piece(), x.get_rank(), x.get_file())));
+            for (i, rank, file) in squares {
+                if i == UNPLACED && read.implicit_piece(rank, file).is_none() {
+                    return invalid(format!("move {} takes a piece from its row at {}, {}, where there is none", read.moves.len() + 1, rank, file));
+                }
+            }
+            read.do_move(m);
+        }
     }
-    Some(0)
+    *b = read;
+    Ok(())
 }
 
 #[test]
@@ -47,10 +248,144 @@ fn both_ways() {
     let mut b = Board::new();
     b.place_piece(Piece::new("pawn".to_string(), Color::White, 1.into(), 1.into()));
     b.place_piece(Piece::new("pawn".to_string(), Color::White, 2.into(), 1.into()));
-    let s = board_serialize(&b);
-    board_deserialize(&mut b, &s);
+    let s = board_serialize(&b, GameStatus::Ongoing);
+    board_deserialize(&mut b, &s).unwrap();
     assert_eq!(b.get_piece_at(&1.into(), &1.into()).unwrap(), 0);
     assert_eq!(b.pieces[0].get_type(), "pawn");
     assert_eq!(b.get_piece_at(&2.into(), &1.into()).unwrap(), 1);
+    assert_eq!(b.to_move(), Color::White);
+    b.set_to_move(Color::Black);
+    let s = board_serialize(&b, GameStatus::Ongoing);
+    board_deserialize(&mut b, &s).unwrap();
+    assert_eq!(b.to_move(), Color::Black);
+
+}
+/// The board as JSON, with the files its rows have lost in order.
+#[cfg(test)]
+pub(crate) fn sorted(b: &Board) -> Value {
+    let mut v: Value = serde_json::from_str(&board_serialize(b, GameStatus::Ongoing)).unwrap();
+    for row in v["rows"].as_array_mut().unwrap() {
+        row["moved"].as_array_mut().unwrap().sort_by_key(|f| f.as_str().unwrap().parse::<i64>().unwrap());
+    }
+    v
+}
+
+#[test]
+fn history_round_trip() {
+    let pm = StandardChess::new();
+    let mut b = Board::from_setup(&Setup::standard());
+    let moves: [(i32, i32, i32, i32, Option<&str>); 21] = [
+        (6, 4, 4, 4, None),
+        (1, 3, 3, 3, None),
+        (4, 4, 3, 3, None),
+        (1, 2, 3, 2, None),
+        // En passant, then a knight takes the pawn that took.
+        (3, 3, 2, 2, None),
+        (0, 1, 2, 2, None),
+        (7, 6, 5, 5, None),
+        (1, -50, 3, -50, None),
+        (7, 5, 4, 2, None),
+        (3, -50, 4, -50, None),
+        (7, 4, 7, 6, None),
+        (4, -50, 5, -50, None),
+        (6, 100, 4, 100, None),
+        // A pawn still on its row is taken, and the one that took it promotes.
+        (5, -50, 6, -49, None),
+        (4, 100, 3, 100, None),
+        (6, -49, 7, -49, Some("knight")),
+        (3, 100, 2, 100, None),
+        (1, 7, 2, 7, None),
+        (2, 100, 1, 101, None),
+        (2, 7, 3, 7, None),
+        (1, 101, 0, 101, Some("queen")),
+    ];
+    for (rank, file, to_rank, to_file, promotion) in moves {
+        let m = Board::move_legal(&mut b, &pm, &rank.into(), &file.into(), &to_rank.into(), &to_file.into(), promotion.map(|p| p.to_string())).unwrap();
+        b.do_move(m);
+        // A pawn that just stepped twice can still be taken en passant after a reload.
+        if (rank, to_rank) == (1, 3) && file == 2 {
+            let mut read = Board::new();
+            board_deserialize(&mut read, &board_serialize(&b, GameStatus::Ongoing)).unwrap();
+            assert!(Board::is_move_legal(&mut read, &pm, &3.into(), &3.into(), &2.into(), &2.into()));
+        }
+    }
+    let saved = board_serialize(&b, GameStatus::Ongoing);
+    let mut read = Board::new();
+    board_deserialize(&mut read, &saved).unwrap();
+    assert_eq!(sorted(&read), sorted(&b));
+    // Every move can be taken back after the reload, just as before it.
+    while !b.moves.is_empty() {
+        assert!(Position::of(&read) == Position::of(&b));
+        assert_eq!(read.halfmove_clock(), b.halfmove_clock());
+        assert_eq!(read.repetitions(), b.repetitions());
+        assert_eq!(sorted(&read), sorted(&b));
+        assert!(read.undo_move().is_some());
+        assert!(b.undo_move().is_some());
+    }
+    assert!(read.undo_move().is_none());
+    assert_eq!(sorted(&read), sorted(&Board::from_setup(&Setup::standard())));
 
-}
\ No newline at end of file
+    // Moves saved before they said where they went still load, though they can't be taken back.
+    let old = r#"{"motions": [[12, 4, 4]], "captures": [], "promotion": null, "piece": 12}"#;
+    let m: Move = serde_json::from_str::<MoveData>(old).unwrap().into_move(1).unwrap();
+    assert_eq!(m.get_piece(), 12);
+    assert!(m.get_motions().is_empty());
+    // A move out of order doesn't load, and leaves the board as it was.
+    let s = saved.replace("\"ply\":1,", "\"ply\":2,");
+    assert_eq!(board_deserialize(&mut read, &s), Err(WireError::Invalid("move 1 says it is move 2".to_string())));
+    assert_eq!(sorted(&read), sorted(&Board::from_setup(&Setup::standard())));
+}
+
+#[test]
+fn versions_and_errors() {
+    let mut b = Board::new();
+    b.place_piece(Piece::new("pawn\", \"alive\": false".to_string(), Color::White, 3.into(), 3.into()));
+    let s = board_serialize(&b, GameStatus::Ongoing);
+    assert!(s.starts_with("{\"version\":3,"));
+    let mut read = Board::new();
+    board_deserialize(&mut read, &s).unwrap();
+    assert_eq!(read.pieces[0].get_type(), "pawn\", \"alive\": false");
+    assert!(!read.pieces[0].is_captured());
+    // Version 2 boards have no promotion ranks, and keep the standard ones.
+    read.set_promotion_rank(Color::White, 3.into());
+    let older = s.replace("\"version\":3", "\"version\":2").replace("\"white_promotion_rank\":\"0\",\"black_promotion_rank\":\"7\",", "");
+    board_deserialize(&mut read, &older).unwrap();
+    assert_eq!((read.promotion_rank(Color::White), read.promotion_rank(Color::Black)), (&0.into(), &7.into()));
+
+    // The format boards were saved in before there were versions, and the one before there
+    // were rows, which also had no side to move or promotions.
+    let piece = r#"{"type": "white_pawn", "piece": "pawn", "alive": true, "y": "4", "x": "4", "color": "white", "has_moved": 1}"#;
+    let unversioned = format!(
+        r#"{{"turn": "1", "to_move": "black", "pieces": [{}], "rows": [{{"color": "white", "rank": "6", "pattern": ["pawn"], "moved": ["4"]}}], "white_king": null, "black_king": null, "moves": [{{"ply": 1, "motions": [[0, "6", "4", "4", "4", true]], "captures": [], "promotion": null, "piece": 0}}], "status": "ongoing"}}"#,
+        piece
+    );
+    board_deserialize(&mut read, &unversioned).unwrap();
+    assert_eq!((read.to_move(), read.moves.len(), read.history.len()), (Color::Black, 1, 1));
+    assert!(read.undo_move().is_some());
+    assert!(read.pieces.is_empty() && read.implicit_piece(&6.into(), &4.into()).is_some());
+    let oldest = format!(r#"{{"turn": "1", "pieces": [{}], "white_pawns": [4], "black_pawns": [], "moves": [{{"motions": [[0, 4, 4]], "captures": [], "piece": 0}}]}}"#, piece);
+    board_deserialize(&mut read, &oldest).unwrap();
+    assert_eq!((read.to_move(), read.moves.len(), read.rows.len()), (Color::Black, 1, 2));
+    assert!(read.implicit_piece(&6.into(), &4.into()).is_none() && read.implicit_piece(&1.into(), &4.into()).is_some());
+
+    // Anything that doesn't make a board is turned down, and the board is left as it was.
+    let before = sorted(&read);
+    let errors = [
+        (s.replace("\"version\":3", "\"version\":4"), "unknown_version"),
+        (s.replace("\"version\":3,", "\"version\":3,\"extra\":1,"), "bad_json"),
+        (s.replace("\"white_promotion_rank\":\"0\",", ""), "invalid"),
+        (s.replace("\"to_move\":\"white\",", ""), "invalid"),
+        (s.replace("\"to_move\":\"white\"", "\"to_move\":\"green\""), "bad_json"),
+        (s.replace("\"white_king\":null", "\"white_king\":0"), "invalid"),
+        (s.replace("\"pattern\":[\"pawn\"]", "\"pattern\":[]"), "invalid"),
+        (unversioned.replace("\"rows\"", "\"white_pawns\": [], \"rows\""), "invalid"),
+        (unversioned.replace("[0, \"6\"", "[1, \"6\""), "invalid"),
+        (unversioned.replace("[0, \"6\", \"4\"", "[0, \"5\", \"4\""), "invalid"),
+        ("[]".to_string(), "bad_json"),
+    ];
+    for (s, reason) in errors {
+        let e = board_deserialize(&mut read, &s).unwrap_err();
+        assert!(e.serialize().starts_with(&format!("{{\"reason\": \"{}\"", reason)), "{}: {}", s, e);
+        assert_eq!(sorted(&read), before);
+    }
+}
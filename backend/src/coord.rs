@@ -0,0 +1,385 @@
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    ops::{Add, AddAssign, Div, Mul, Neg, Rem, Sub, SubAssign},
+    str::FromStr,
+};
+
+use num_bigint::{BigInt, BigUint, ParseBigIntError};
+use num_traits::{Signed, ToPrimitive};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+#[cfg(test)]
+use crate::test_util::Rng;
+
+/// A rank or file. The board has no edges, but real games stay close to the middle, so the
+/// value lives inline as an `i64` and only spills into a `BigInt` when it no longer fits.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Coord(Repr);
+
+/// `Big` only ever holds values outside the `i64` range, so every number has exactly one
+/// representation and the derived equality and hashing agree with the numeric ones.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Repr {
+    Small(i64),
+    Big(BigInt),
+}
+
+impl Coord {
+    fn big(&self) -> Cow<'_, BigInt> {
+        match &self.0 {
+            Repr::Small(x) => Cow::Owned(BigInt::from(*x)),
+            Repr::Big(x) => Cow::Borrowed(x),
+        }
+    }
+    pub fn is_zero(&self) -> bool {
+        matches!(self.0, Repr::Small(0))
+    }
+    pub fn is_positive(&self) -> bool {
+        match &self.0 {
+            Repr::Small(x) => *x > 0,
+            Repr::Big(x) => x.is_positive(),
+        }
+    }
+    pub fn is_negative(&self) -> bool {
+        match &self.0 {
+            Repr::Small(x) => *x < 0,
+            Repr::Big(x) => x.is_negative(),
+        }
+    }
+    pub fn signum(&self) -> Coord {
+        match &self.0 {
+            Repr::Small(x) => x.signum().into(),
+            Repr::Big(x) => x.signum().into(),
+        }
+    }
+    pub fn abs(&self) -> Coord {
+        match &self.0 {
+            Repr::Small(x) => x.checked_abs().map(Coord::from).unwrap_or_else(|| BigInt::from(*x).abs().into()),
+            Repr::Big(x) => x.abs().into(),
+        }
+    }
+    pub fn pow(&self, exponent: u32) -> Coord {
+        match &self.0 {
+            Repr::Small(x) => x.checked_pow(exponent).map(Coord::from).unwrap_or_else(|| BigInt::from(*x).pow(exponent).into()),
+            Repr::Big(x) => x.pow(exponent).into(),
+        }
+    }
+    /// The value, if it fits in an `i64`.
+    pub fn to_i64(&self) -> Option<i64> {
+        match &self.0 {
+            Repr::Small(x) => Some(*x),
+            Repr::Big(_) => None,
+        }
+    }
+    /// Appends the coordinate zigzag encoded, so that 0, -1, 1, -2, ... become 0, 1, 2, 3, ...,
+    /// as a varint of as many bytes as it takes: seven bits to a byte, lowest first, with the
+    /// top bit set on every byte but the last.
+    pub(crate) fn write_zigzag(&self, out: &mut Vec<u8>) {
+        let digits = match &self.0 {
+            Repr::Small(x) => {
+                let mut z = ((*x as i128) << 1 ^ (*x as i128) >> 127) as u128;
+                let mut digits = vec![(z & 0x7f) as u8];
+                while z >= 0x80 {
+                    z >>= 7;
+                    digits.push((z & 0x7f) as u8);
+                }
+                digits
+            }
+            Repr::Big(x) => {
+                let z = if x.is_negative() { (-x << 1u8) - 1 } else { x << 1u8 };
+                z.magnitude().to_radix_le(128)
+            }
+        };
+        let last = digits.len() - 1;
+        out.extend(digits.iter().enumerate().map(|(i, d)| if i < last { d | 0x80 } else { *d }));
+    }
+    /// Reads a coordinate `write_zigzag` wrote off the front of `input`. None if it runs out
+    /// first, or if the varint is longer than it has to be.
+    pub(crate) fn read_zigzag(input: &mut &[u8]) -> Option<Coord> {
+        let end = input.iter().position(|b| b & 0x80 == 0)?;
+        let (bytes, rest) = input.split_at(end + 1);
+        if end > 0 && bytes[end] == 0 {
+            return None;
+        }
+        *input = rest;
+        if end < 9 {
+            let z = bytes.iter().rev().fold(0u64, |z, b| z << 7 | (b & 0x7f) as u64);
+            return Some(((z >> 1) as i64 ^ -((z & 1) as i64)).into());
+        }
+        let z = BigUint::from_radix_le(&bytes.iter().map(|b| b & 0x7f).collect::<Vec<u8>>(), 128)?;
+        let half = BigInt::from(&z >> 1u8);
+        Some(if z.bit(0) { -half - 1 } else { half }.into())
+    }
+    /// The remainder of dividing by the positive `m`, always in `0..m`.
+    pub fn rem_euclid(&self, m: i64) -> i64 {
+        match &self.0 {
+            Repr::Small(x) => x.rem_euclid(m),
+            Repr::Big(x) => ((x % m + m) % m).to_i64().unwrap(),
+        }
+    }
+}
+
+impl Default for Coord {
+    fn default() -> Self {
+        Coord(Repr::Small(0))
+    }
+}
+
+impl From<BigInt> for Coord {
+    fn from(x: BigInt) -> Self {
+        match x.to_i64() {
+            Some(x) => Coord(Repr::Small(x)),
+            None => Coord(Repr::Big(x)),
+        }
+    }
+}
+
+impl From<Coord> for BigInt {
+    fn from(x: Coord) -> Self {
+        match x.0 {
+            Repr::Small(x) => x.into(),
+            Repr::Big(x) => x,
+        }
+    }
+}
+
+macro_rules! from_primitive {
+    ($($t:ty),*) => {$(
+        impl From<$t> for Coord {
+            fn from(x: $t) -> Self {
+                Coord(Repr::Small(x.into()))
+            }
+        }
+    )*};
+}
+
+from_primitive!(i8, i16, i32, i64, u8, u16, u32);
+
+impl From<usize> for Coord {
+    fn from(x: usize) -> Self {
+        BigInt::from(x).into()
+    }
+}
+
+impl PartialOrd for Coord {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Coord {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (&self.0, &other.0) {
+            (Repr::Small(a), Repr::Small(b)) => a.cmp(b),
+            _ => self.big().cmp(&other.big()),
+        }
+    }
+}
+
+impl std::fmt::Display for Coord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.0 {
+            Repr::Small(x) => x.fmt(f),
+            Repr::Big(x) => x.fmt(f),
+        }
+    }
+}
+
+impl FromStr for Coord {
+    type Err = ParseBigIntError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<i64>() {
+            Ok(x) => Ok(x.into()),
+            Err(_) => s.parse::<BigInt>().map(Coord::from),
+        }
+    }
+}
+
+/// Accepts plain JSON integers, and integers written as strings for those too big for JSON.
+impl<'de> Deserialize<'de> for Coord {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+        impl de::Visitor<'_> for Visitor {
+            type Value = Coord;
+            fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str("an integer or a string holding one")
+            }
+            fn visit_i64<E: de::Error>(self, x: i64) -> Result<Coord, E> {
+                Ok(x.into())
+            }
+            fn visit_u64<E: de::Error>(self, x: u64) -> Result<Coord, E> {
+                Ok(BigInt::from(x).into())
+            }
+            fn visit_str<E: de::Error>(self, s: &str) -> Result<Coord, E> {
+                s.parse().map_err(|_| E::invalid_value(de::Unexpected::Str(s), &self))
+            }
+        }
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+/// Written as a string, which holds coordinates of any size.
+impl Serialize for Coord {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+macro_rules! binary_op {
+    ($op:ident, $method:ident, $checked:ident) => {
+        impl<'a, 'b> $op<&'b Coord> for &'a Coord {
+            type Output = Coord;
+            fn $method(self, other: &'b Coord) -> Coord {
+                if let (Repr::Small(a), Repr::Small(b)) = (&self.0, &other.0) {
+                    if let Some(x) = a.$checked(*b) {
+                        return x.into();
+                    }
+                }
+                (&*self.big()).$method(&*other.big()).into()
+            }
+        }
+        impl<'a> $op<Coord> for &'a Coord {
+            type Output = Coord;
+            fn $method(self, other: Coord) -> Coord {
+                self.$method(&other)
+            }
+        }
+        impl<'b> $op<&'b Coord> for Coord {
+            type Output = Coord;
+            fn $method(self, other: &'b Coord) -> Coord {
+                (&self).$method(other)
+            }
+        }
+        impl $op<Coord> for Coord {
+            type Output = Coord;
+            fn $method(self, other: Coord) -> Coord {
+                (&self).$method(&other)
+            }
+        }
+        primitive_op!($op, $method, i32, i64);
+    };
+}
+
+macro_rules! primitive_op {
+    ($op:ident, $method:ident, $($t:ty),*) => {$(
+        impl<'a> $op<$t> for &'a Coord {
+            type Output = Coord;
+            fn $method(self, other: $t) -> Coord {
+                self.$method(&Coord::from(other))
+            }
+        }
+        impl $op<$t> for Coord {
+            type Output = Coord;
+            fn $method(self, other: $t) -> Coord {
+                (&self).$method(&Coord::from(other))
+            }
+        }
+    )*};
+}
+
+binary_op!(Add, add, checked_add);
+binary_op!(Sub, sub, checked_sub);
+binary_op!(Mul, mul, checked_mul);
+binary_op!(Div, div, checked_div);
+binary_op!(Rem, rem, checked_rem);
+
+macro_rules! assign_op {
+    ($op:ident, $method:ident, $binary:ident) => {
+        impl<'a> $op<&'a Coord> for Coord {
+            fn $method(&mut self, other: &'a Coord) {
+                *self = (&*self).$binary(other);
+            }
+        }
+        impl $op<Coord> for Coord {
+            fn $method(&mut self, other: Coord) {
+                *self = (&*self).$binary(&other);
+            }
+        }
+        impl $op<i32> for Coord {
+            fn $method(&mut self, other: i32) {
+                *self = (&*self).$binary(&Coord::from(other));
+            }
+        }
+        impl $op<i64> for Coord {
+            fn $method(&mut self, other: i64) {
+                *self = (&*self).$binary(&Coord::from(other));
+            }
+        }
+    };
+}
+
+assign_op!(AddAssign, add_assign, add);
+assign_op!(SubAssign, sub_assign, sub);
+
+impl Neg for &Coord {
+    type Output = Coord;
+    fn neg(self) -> Coord {
+        match &self.0 {
+            Repr::Small(x) => x.checked_neg().map(Coord::from).unwrap_or_else(|| (-BigInt::from(*x)).into()),
+            Repr::Big(x) => (-x).into(),
+        }
+    }
+}
+
+impl Neg for Coord {
+    type Output = Coord;
+    fn neg(self) -> Coord {
+        -&self
+    }
+}
+
+#[test]
+fn test_overflow_to_bigint() {
+    let max = Coord::from(i64::MAX);
+    let past: Coord = &max + 1;
+    assert_eq!(past.to_string(), "9223372036854775808");
+    assert!(past > max);
+    assert_eq!(&past - 1, max);
+    assert_eq!(past - 1, Coord::from(i64::MAX));
+    assert_eq!(-Coord::from(i64::MIN), Coord::from(BigInt::from(i64::MAX) + 1));
+    assert_eq!(Coord::from(i64::MIN).abs(), -Coord::from(i64::MIN));
+    assert_eq!(Coord::from(i64::MIN) / -1, -Coord::from(i64::MIN));
+    assert!(Coord::from(i64::MIN) - 1 < Coord::from(i64::MIN));
+    let far = Coord::from(10).pow(30);
+    assert_eq!("1000000000000000000000000000000".parse::<Coord>(), Ok(far.clone()));
+    assert_eq!(&far * &far / &far, far);
+    assert_eq!((&far + 7) % &far, Coord::from(7));
+    assert_eq!((-&far - &Coord::from(3)).rem_euclid(8), 5);
+    assert_eq!(Coord::from(-3).rem_euclid(8), 5);
+    assert!(far.is_positive() && (-&far).is_negative() && (&far - &far).is_zero());
+    let mut squares = std::collections::HashSet::new();
+    squares.insert(&far - &far + 3);
+    assert!(squares.contains(&Coord::from(3)));
+}
+
+#[test]
+fn zigzag_round_trip() {
+    let far = Coord::from(10).pow(30);
+    let max = Coord::from(i64::MAX);
+    let mut values: Vec<Coord> = vec![0.into(), (-1).into(), 1.into(), 63.into(), (-64).into(), 64.into(), max.clone(), &max + 1, Coord::from(i64::MIN), Coord::from(i64::MIN) - 1, far.clone(), -&far];
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+    for _ in 0..1000 {
+        let x = rng.next();
+        values.push(Coord::from((x as i64) >> (x % 64)));
+        values.push(&far * &Coord::from(x as i64));
+    }
+    for v in &values {
+        let mut out = Vec::new();
+        v.write_zigzag(&mut out);
+        let mut input = &out[..];
+        assert_eq!(Coord::read_zigzag(&mut input).as_ref(), Some(v));
+        assert!(input.is_empty());
+        // Cut short, the varint doesn't read at all.
+        assert_eq!(Coord::read_zigzag(&mut &out[..out.len() - 1]), None);
+    }
+    let bytes = |v: i64| {
+        let mut out = Vec::new();
+        Coord::from(v).write_zigzag(&mut out);
+        out
+    };
+    assert_eq!((bytes(0), bytes(-1), bytes(1), bytes(-64), bytes(64)), (vec![0], vec![1], vec![2], vec![127], vec![128, 1]));
+    assert_eq!(bytes(i64::MIN).len(), 10);
+    // A varint with bytes it doesn't need isn't read.
+    assert_eq!(Coord::read_zigzag(&mut &[0x82, 0x00][..]), None);
+}
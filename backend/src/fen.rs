@@ -0,0 +1,378 @@
+use crate::coord::Coord;
+
+use crate::{
+    board::Board,
+    moves::{Move, UNPLACED},
+    notation::{read_square, square_notation, LETTERS},
+    piece::{Color, Piece},
+    piece_row::PieceRow,
+    piece_rules::pawn_direction,
+};
+
+// A position in one line, in the spirit of FEN, with five fields separated by spaces:
+//
+//     ra8,nb8,bc8,qd8,ke8,bf8,ng8,rh8,Pe4',Ra1,Nb1,Bc1,Qd1,Ke1,Bf1,Ng1,Rh1 p@7,P@2-{4} b KQkq e3
+//
+// 1. The pieces on the board, each a letter and a square as moves write them: capitals for
+//    white, `P` for pawns and any other type as its name in braces, like `{Camel}`. A king
+//    written that way, `{King}`, isn't royal. A piece that has moved is marked with `'`.
+// 2. The infinite rows, each its pattern, `@`, its rank and the files it has lost in braces.
+// 3. Who moves, `w` or `b`.
+// 4. Castling rights as in FEN, which have to agree with which kings and rooks have moved.
+// 5. The square a pawn that just moved two squares passed, or `-`.
+// 6. The ranks white's and black's pawns promote on, like `10,-1`, left out when they are
+//    the standard 8 and 1.
+//
+// An empty list is written `-`. Pieces still standing where their row put them belong to the
+// row and aren't listed.
+
+fn symbol(kind: &str, color: Color, braced: bool) -> String {
+    match LETTERS.iter().find(|(_, name)| *name == kind) {
+        Some((letter, _)) if !braced => cased(*letter, color).unwrap_or(*letter).to_string(),
+        _ if kind == "pawn" => cased('P', color).unwrap_or('P').to_string(),
+        _ => {
+            let mut chars = kind.chars();
+            let first = chars.next().map(|c| cased(c, color).unwrap_or(c));
+            format!("{{{}}}", first.into_iter().chain(chars).collect::<String>())
+        }
+    }
+}
+
+/// `c` in the case that stands for `color`: upper for white, lower for black. None if `c`
+/// doesn't have exactly one of each, which leaves nothing to tell the colors apart by.
+fn cased(c: char, color: Color) -> Option<char> {
+    let (mut upper, mut lower) = (c.to_uppercase(), c.to_lowercase());
+    match (upper.next(), upper.next(), lower.next(), lower.next()) {
+        (Some(u), None, Some(l), None) if u != l && (c == u || c == l) => Some(if color == Color::White { u } else { l }),
+        _ => None,
+    }
+}
+
+/// Reads a piece symbol off the front of `s`: its type, color and whether it was in braces.
+fn read_symbol(s: &str) -> Result<(String, Color, bool, &str), String> {
+    let color = |c: char| {
+        let mut colors = [Color::White, Color::Black].iter().copied();
+        colors.find(|&color| cased(c, color) == Some(c)).ok_or_else(|| format!("{} has no case to tell its color by", c))
+    };
+    if let Some(rest) = s.strip_prefix('{') {
+        let end = rest.find('}').ok_or_else(|| format!("{} is never closed", s))?;
+        let name = &rest[..end];
+        let first = name.chars().next().ok_or("a piece type can't be empty")?;
+        let color = color(first)?;
+        let kind = cased(first, Color::Black).into_iter().collect::<String>() + &name[first.len_utf8()..];
+        return Ok((kind, color, true, &rest[end + 1..]));
+    }
+    let c = s.chars().next().ok_or("a piece is missing")?;
+    let kind = match c.to_ascii_uppercase() {
+        'P' => "pawn",
+        upper => LETTERS.iter().find(|(letter, _)| *letter == upper).map(|(_, name)| *name).ok_or_else(|| format!("{} is not a piece", c))?,
+    };
+    Ok((kind.to_string(), color(c)?, false, &s[1..]))
+}
+
+/// Splits a list on the commas that aren't inside parentheses or braces.
+fn split_list(s: &str) -> Vec<&str> {
+    if s == "-" {
+        return Vec::new();
+    }
+    let (mut out, mut depth, mut start) = (Vec::new(), 0, 0);
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' | '{' => depth += 1,
+            ')' | '}' => depth -= 1,
+            ',' if depth == 0 => {
+                out.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    out.push(&s[start..]);
+    out
+}
+
+fn or_dash(items: Vec<String>) -> String {
+    if items.is_empty() {
+        "-".to_string()
+    } else {
+        items.join(",")
+    }
+}
+
+/// FEN's castling rights: for each side, whether its royal king and the nearest castling
+/// partner on either side of it, like the rooks in the corners, still haven't moved.
+fn castling_rights(b: &Board) -> String {
+    let mut out = String::new();
+    for color in [Color::White, Color::Black] {
+        let king = match b.king(color).map(|i| &b.pieces[i]).filter(|k| !k.has_moved()) {
+            Some(king) => king,
+            None => continue,
+        };
+        for (df, letter) in [(1, "K"), (-1, "Q")] {
+            let rook = b.castling_partner(color, king.get_rank(), king.get_file(), df);
+            if rook.is_some_and(|(_, _, r)| !r.has_moved()) {
+                out += &if color == Color::White { letter.to_string() } else { letter.to_lowercase() };
+            }
+        }
+    }
+    if out.is_empty() {
+        "-".to_string()
+    } else {
+        out
+    }
+}
+
+pub(crate) fn fen_serialize(b: &Board) -> String {
+    // A royal king counts as part of a row with a king in its place, but loses being royal.
+    let royal = |i: usize| [Color::White, Color::Black].iter().any(|&c| b.king(c) == Some(i));
+    let mut listed: Vec<usize> = (0..b.pieces.len()).filter(|&i| !b.pieces[i].is_captured() && (!b.is_row_piece(i) || royal(i))).collect();
+    listed.sort_by_key(|&i| (b.pieces[i].get_rank(), b.pieces[i].get_file()));
+    let pieces = listed
+        .iter()
+        .map(|&i| {
+            let p = &b.pieces[i];
+            let moved = if p.has_moved() { "'" } else { "" };
+            format!("{}{}{}", symbol(p.get_type(), p.get_color(), b.is_royal_type(p.get_type()) && !royal(i)), square_notation(p.get_rank(), p.get_file()), moved)
+        })
+        .collect();
+    let rows = b
+        .rows
+        .iter()
+        .map(|row| {
+            let pattern: String = row.get_pattern().iter().map(|kind| symbol(kind, row.get_color(), false)).collect();
+            // A file the piece was only brought in from, and is still standing on, isn't lost.
+            let mut gaps: Vec<Coord> = row.files().into_iter().filter(|f| b.get_piece_at(row.get_rank(), f).map_or(true, |i| listed.contains(&i))).collect();
+            gaps.sort();
+            let gaps = if gaps.is_empty() {
+                String::new()
+            } else {
+                format!("-{{{}}}", gaps.iter().map(|f| f.to_string()).collect::<Vec<String>>().join(","))
+            };
+            format!("{}@{}{}", pattern, -row.get_rank() + 8, gaps)
+        })
+        .collect();
+    let to_move = if b.to_move() == Color::White { "w" } else { "b" };
+    let en_passant = b.en_passant_square().map_or("-".to_string(), |(rank, file)| square_notation(&rank, &file));
+    let mut out = format!("{} {} {} {} {}", or_dash(pieces), or_dash(rows), to_move, castling_rights(b), en_passant);
+    let promotion_ranks = (b.promotion_rank(Color::White), b.promotion_rank(Color::Black));
+    if promotion_ranks != (&0.into(), &7.into()) {
+        out += &format!(" {},{}", -promotion_ranks.0 + 8, -promotion_ranks.1 + 8);
+    }
+    out
+}
+
+fn row_deserialize(s: &str) -> Result<PieceRow, String> {
+    let (mut pattern_text, rest) = s.split_once('@').ok_or_else(|| format!("{} needs an @ before its rank", s))?;
+    let (rank, gaps) = match rest.split_once("-{") {
+        Some((rank, gaps)) => (rank, gaps.strip_suffix('}').ok_or_else(|| format!("{} is never closed", s))?),
+        None => (rest, ""),
+    };
+    let rank = -rank.parse::<Coord>().map_err(|_| format!("{} is not a rank", rank))? + 8;
+    let mut pattern = Vec::new();
+    let mut colors = Vec::new();
+    while !pattern_text.is_empty() {
+        let (kind, color, _, rest) = read_symbol(pattern_text)?;
+        pattern.push(kind);
+        colors.push(color);
+        pattern_text = rest;
+    }
+    if pattern.is_empty() || colors.iter().any(|c| c != &colors[0]) {
+        return Err(format!("{} needs pieces of one color to repeat", s));
+    }
+    let row = PieceRow::new(colors[0], rank, pattern);
+    for f in gaps.split(',').filter(|f| !f.is_empty()) {
+        row.set_moved(&f.trim().parse::<Coord>().map_err(|_| format!("{} is not a file", f))?);
+    }
+    Ok(row)
+}
+
+/// Sets up the position `s` describes, with the board's piece types. If a pawn has just moved
+/// two squares, that move is played on the board so it can be taken en passant and the move
+/// taken back. The board is left as it was if `s` isn't a position.
+pub(crate) fn fen_deserialize(target: &mut Board, s: &str) -> Result<(), String> {
+    let (pieces, rows, to_move, castling, en_passant, promotion_ranks) = match s.split_whitespace().collect::<Vec<&str>>()[..] {
+        [pieces, rows, to_move, castling, en_passant] => (pieces, rows, to_move, castling, en_passant, None),
+        [pieces, rows, to_move, castling, en_passant, ranks] => (pieces, rows, to_move, castling, en_passant, Some(ranks)),
+        _ => return Err("a position has five fields, or six with the promotion ranks".to_string()),
+    };
+    let mut b = Board::with_piece_set(target.piece_set.clone());
+    if let Some(ranks) = promotion_ranks {
+        let bad = || format!("{} isn't white's and black's promotion ranks", ranks);
+        let (white, black) = ranks.split_once(',').ok_or_else(bad)?;
+        for (color, rank) in [(Color::White, white), (Color::Black, black)] {
+            b.set_promotion_rank(color, -rank.parse::<Coord>().map_err(|_| bad())? + 8);
+        }
+    }
+    b.rows = split_list(rows).into_iter().map(row_deserialize).collect::<Result<Vec<PieceRow>, String>>()?;
+    for (i, row) in b.rows.iter().enumerate() {
+        if b.rows[..i].iter().any(|r| r.get_rank() == row.get_rank()) {
+            return Err(format!("two rows on rank {}", -row.get_rank() + 8));
+        }
+    }
+    let to_move = match to_move {
+        "w" => Color::White,
+        "b" => Color::Black,
+        _ => return Err(format!("{} is not w or b", to_move)),
+    };
+    let mut listed = Vec::new();
+    for text in split_list(pieces) {
+        let (kind, color, braced, rest) = read_symbol(text)?;
+        let ((rank, file), rest) = read_square(rest).ok_or_else(|| format!("{} has no square", text))?;
+        let moved = match rest {
+            "" => false,
+            "'" => true,
+            _ => return Err(format!("{} has {} after its square", text, rest)),
+        };
+        if listed.iter().any(|(p, _, _): &(Piece, bool, bool)| p.get_rank() == &rank && p.get_file() == &file) {
+            return Err(format!("two pieces on {}", square_notation(&rank, &file)));
+        }
+        listed.push((Piece::new(kind, color, rank, file), braced, moved));
+    }
+    // The pawn that just moved two squares goes back to where it came from, and the move is
+    // played again.
+    let mut double_step = None;
+    if en_passant != "-" {
+        let bad = || format!("no pawn can have just passed {}", en_passant);
+        let ((rank, file), _) = read_square(en_passant).filter(|(_, rest)| rest.is_empty()).ok_or_else(bad)?;
+        let dir = pawn_direction(to_move.opposite());
+        let (to, from) = ((&rank + dir, file.clone()), (&rank - dir, file));
+        let i = listed
+            .iter()
+            .position(|(p, _, _)| p.get_rank() == &to.0 && p.get_file() == &to.1 && b.piece_set.is(p.get_type(), |d| d.en_passant) && p.get_color() == to_move.opposite())
+            .ok_or_else(bad)?;
+        if listed.iter().any(|(p, _, _)| (p.get_rank(), p.get_file()) == (&from.0, &from.1) || (p.get_rank(), p.get_file()) == (&rank, &to.1)) {
+            return Err(bad());
+        }
+        let (pawn, _, _) = listed.remove(i);
+        let row = b.row_at(&from.0).filter(|row| row.get_color() == pawn.get_color() && row.type_at(&from.1) == pawn.get_type());
+        let from_row = match row {
+            // Still on its row, unless it is written as gone from there.
+            Some(row) if row.has_moved(&from.1) => {
+                row.set_unmoved(&from.1);
+                true
+            }
+            Some(_) => return Err(bad()),
+            None => false,
+        };
+        if !from_row {
+            listed.push((Piece::new(pawn.get_type().clone(), pawn.get_color(), from.0.clone(), from.1.clone()), false, false));
+        }
+        double_step = Some((from_row, from, to));
+    }
+    for (piece, braced, moved) in listed {
+        let color = piece.get_color();
+        let king = b.is_royal_type(piece.get_type());
+        let royal = b.king(color);
+        if king && !braced && royal.is_some() {
+            return Err(format!("{} has two royal kings", color.to_string()));
+        }
+        let i = b.place_piece(piece).unwrap();
+        if king && braced {
+            b.set_king(color, royal.unwrap_or(usize::MAX));
+        }
+        if moved {
+            // Moved before any move the board knows of, so taking moves back never undoes it.
+            b.pieces[i].set_has_moved(usize::MAX);
+        }
+    }
+    b.set_to_move(to_move);
+    if let Some((from_row, from, to)) = double_step {
+        let i = if from_row { UNPLACED } else { b.get_piece_at(&from.0, &from.1).unwrap() };
+        b.set_to_move(to_move.opposite());
+        b.do_move(Move::standard(i, &to.0, &to.1, &from.0, &from.1));
+    }
+    let rights = castling_rights(&b);
+    if castling != rights {
+        return Err(format!("the castling rights are {} by which kings and rooks have moved", rights));
+    }
+    *target = b;
+    Ok(())
+}
+
+#[cfg(test)]
+use crate::{
+    piece_rules::StandardChess,
+    position::Position,
+    setup::{Setup, REPEATING_SETUP},
+};
+
+/// The position `s` describes, on a board with the standard pieces.
+#[cfg(test)]
+fn fen_board(s: &str) -> Result<Board, String> {
+    let mut b = Board::new();
+    fen_deserialize(&mut b, s)?;
+    Ok(b)
+}
+
+#[test]
+fn fen_round_trip() {
+    let pm = StandardChess::new();
+    let mut b = Board::from_setup(&Setup::standard());
+    assert_eq!(
+        fen_serialize(&b),
+        "ra8,nb8,bc8,qd8,ke8,bf8,ng8,rh8,Ra1,Nb1,Bc1,Qd1,Ke1,Bf1,Ng1,Rh1 p@7,P@2 w KQkq -"
+    );
+    let play = |b: &mut Board, rank: i32, file: i32, to_rank: i32, to_file: i32| {
+        let m = Board::move_legal(b, &pm, &rank.into(), &file.into(), &to_rank.into(), &to_file.into(), None).unwrap();
+        b.do_move(m);
+        let fen = fen_serialize(b);
+        let mut read = fen_board(&fen).unwrap();
+        assert!(Position::of(&read) == Position::of(b), "{}", fen);
+        assert_eq!(fen_serialize(&read), fen);
+        // A pawn that just stepped twice can be taken en passant and the step taken back.
+        if b.en_passant_square().is_some() {
+            assert!(read.undo_move().is_some());
+        }
+        fen
+    };
+    assert_eq!(play(&mut b, 6, 4, 4, 4), "ra8,nb8,bc8,qd8,ke8,bf8,ng8,rh8,Pe4',Ra1,Nb1,Bc1,Qd1,Ke1,Bf1,Ng1,Rh1 p@7,P@2-{4} b KQkq e3");
+    play(&mut b, 1, 100, 3, 100);
+    play(&mut b, 4, 4, 3, 4);
+    play(&mut b, 1, 3, 3, 3);
+    // The pawn on e5 may take the one beside it en passant, either right away or after a reload.
+    assert!(Board::is_move_legal(&mut b, &pm, &3.into(), &4.into(), &2.into(), &3.into()));
+    let mut read = fen_board(&fen_serialize(&b)).unwrap();
+    assert!(Board::is_move_legal(&mut read, &pm, &3.into(), &4.into(), &2.into(), &3.into()));
+    assert!(play(&mut b, 7, 4, 6, 4).ends_with(" b kq -"));
+    assert!(play(&mut b, 0, 4, 1, 3).ends_with(" w - -"));
+
+    // Repeating back ranks, a moved row king that isn't royal, and a far pawn.
+    let mut b = Board::from_setup(&Setup::parse(REPEATING_SETUP).unwrap());
+    play(&mut b, 6, 11, 4, 11);
+    play(&mut b, 1, -100, 2, -100);
+    let fen = play(&mut b, 7, 12, 6, 11);
+    assert!(fen.contains("{King}(11,2)'"));
+    assert!(fen.contains(",Ke1 ") && fen.contains("RNBQKBNR@1-{4,12}") && fen.contains("P@2-{11}"));
+
+    // Promotion ranks other than the standard ones are written down.
+    let mut b = Board::new();
+    b.set_promotion_rank(Color::White, (-2).into());
+    b.set_promotion_rank(Color::Black, 9.into());
+    let fen = fen_serialize(&b);
+    assert!(fen.ends_with(" - 10,-1"), "{}", fen);
+    let read = fen_board(&fen).unwrap();
+    assert_eq!((read.promotion_rank(Color::White), read.promotion_rank(Color::Black)), (&(-2).into(), &9.into()));
+}
+
+#[test]
+fn fen_errors() {
+    assert!(fen_board("Ke1 P@2 w").is_err());
+    assert!(fen_board("Ke1 P@2 w - - 8").is_err());
+    assert!(fen_board("Ke1,Qe1 - w - -").is_err());
+    assert!(fen_board("Ke1,Kd1 - w - -").is_err());
+    assert!(fen_board("Ke1,Rh1 - w - -").is_err());
+    assert!(fen_board("Ke1,Rh1' - w K -").is_err());
+    assert!(fen_board("Ke1 P@2,p@2 w - -").is_err());
+    assert!(fen_board("Ke1 P@2 w - e3").is_err());
+    assert!(fen_board("Ke1,{Camel}(20,-4)' Pp@2 w - -").is_err());
+    let b = fen_board("Ke1,Rh1,{camel}(20,-4)',ke8 - b K -").unwrap();
+    assert_eq!(b.pieces[2].get_type(), "camel");
+    assert_eq!(b.to_move(), Color::Black);
+    assert!(b.rows.is_empty());
+    // Only the first letter of a type tells its color, and it needs a case to do that.
+    let fen = "ke8,{grassHopper}f6,{GrassHopper}c3,Ke1 - w - -";
+    let b = fen_board(fen).unwrap();
+    assert_eq!((b.pieces[2].get_type().as_str(), b.pieces[1].get_type().as_str(), b.pieces[1].get_color()), ("grassHopper", "grassHopper", Color::Black));
+    assert_eq!(fen_serialize(&b), fen);
+    assert!(fen_board("Ke1,{1up}c3,ke8 - w - -").is_err());
+}
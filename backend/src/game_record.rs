@@ -0,0 +1,476 @@
+use crate::{
+    board::Board,
+    fen::{fen_deserialize, fen_serialize},
+    notation::{move_notation, parse_move, NotationError},
+    piece::Color,
+    piece_rules::{GameStatus, PieceRules, StandardChess},
+    setup::Setup,
+};
+
+/// How long a line of movetext may get before it is broken.
+const LINE_WIDTH: usize = 80;
+const RESULTS: [&str; 4] = ["1-0", "0-1", "1/2-1/2", "*"];
+
+/// Why a game record couldn't be read or played through.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RecordError {
+    /// The text isn't a game record; says what was wrong with it.
+    Syntax(String),
+    /// The Rules or Setup tag doesn't hold a rules file or setup.
+    BadTag { name: String, error: String },
+    /// A move can't be played, counting moves from the start of the game.
+    BadMove { ply: usize, text: String, error: NotationError },
+}
+
+impl RecordError {
+    fn reason(&self) -> &'static str {
+        match self {
+            RecordError::Syntax(_) => "syntax",
+            RecordError::BadTag { .. } => "bad_tag",
+            RecordError::BadMove { .. } => "bad_move",
+        }
+    }
+
+    pub fn serialize(&self) -> String {
+        let extra = match self {
+            RecordError::BadMove { ply, error, .. } => format!(", \"ply\": {}, \"error\": {}", ply, error.serialize()),
+            _ => String::new(),
+        };
+        format!("{{\"reason\": \"{}\", \"message\": {}{}}}", self.reason(), serde_json::Value::from(self.to_string()), extra)
+    }
+}
+
+impl std::fmt::Display for RecordError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecordError::Syntax(s) => write!(f, "not a game record: {}", s),
+            RecordError::BadTag { name, error } => write!(f, "the {} tag is no good: {}", name, error),
+            RecordError::BadMove { ply, text, error } => write!(f, "can't play {} at ply {}: {}", text, ply + 1, error),
+        }
+    }
+}
+
+/// A move as the record writes it, with what was said about it and the lines that could have
+/// been played in its place.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct RecordedMove {
+    pub(crate) notation: String,
+    pub(crate) comment: Option<String>,
+    pub(crate) variations: Vec<Line>,
+}
+
+/// A run of moves, with a comment on the position it starts from.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Line {
+    pub(crate) comment: Option<String>,
+    pub(crate) moves: Vec<RecordedMove>,
+}
+
+/// A whole game in the style of PGN: tag pairs, then the moves in coordinate-aware notation
+/// with comments in braces and variations in parentheses. Besides the usual tags, `Rules`
+/// holds the rules file the game is played by and `Setup` the position it starts from, as a
+/// setup file or in one line as `fen_serialize` writes it, each left out for the standard one.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct GameRecord {
+    pub(crate) tags: Vec<(String, String)>,
+    /// Whether the first move is black's, which changes how the moves are numbered.
+    pub(crate) black_first: bool,
+    pub(crate) line: Line,
+    pub(crate) result: String,
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl GameRecord {
+    pub(crate) fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    /// Writes down the game played on `board` from its first move, under the given tags. The
+    /// Result tag is filled in from the board unless the game is still going and the tags
+    /// already say how it ended, as they would after a resignation, and so is the Setup tag
+    /// if the game didn't start from the standard setup. Fails if the board's moves don't say
+    /// where they went, as those loaded from old saves don't.
+    pub(crate) fn of(board: &Board, rules: &dyn PieceRules, tags: &[(String, String)]) -> Option<GameRecord> {
+        let mut start = board.clone();
+        while start.undo_move().is_some() {}
+        let black_first = start.to_move() == Color::Black;
+        let setup = fen_serialize(&start);
+        let mut standard = start.clone();
+        standard.set_up(&Setup::standard());
+        let setup = Some(setup).filter(|s| s != &fen_serialize(&standard));
+        let mut line = Line::default();
+        for m in &board.moves {
+            let motion = m.get_motions().first()?;
+            let promotion = m.get_promotion().map(|p| p.get_to().clone());
+            let m = Board::move_legal(&mut start, rules, motion.get_from_rank(), motion.get_from_file(), motion.get_rank(), motion.get_file(), promotion).ok()?;
+            let notation = move_notation(&mut start, rules, &m)?;
+            start.do_move(m);
+            line.moves.push(RecordedMove { notation, ..Default::default() });
+        }
+        let given = tags.iter().find(|(n, _)| n == "Result").map(|(_, v)| v.clone());
+        let result = match (Board::game_status(&mut start, rules), board.claimed_draw) {
+            (_, Some(_)) => "1/2-1/2".to_string(),
+            (GameStatus::Checkmate, _) if start.to_move() == Color::White => "0-1".to_string(),
+            (GameStatus::Checkmate, _) => "1-0".to_string(),
+            (GameStatus::Ongoing, _) => given.unwrap_or_else(|| "*".to_string()),
+            _ => "1/2-1/2".to_string(),
+        };
+        let mut tags: Vec<(String, String)> = tags.iter().filter(|(n, _)| n != "Result").cloned().collect();
+        tags.push(("Result".to_string(), result.clone()));
+        if let Some(setup) = setup.filter(|_| !tags.iter().any(|(n, _)| n == "Setup")) {
+            tags.push(("Setup".to_string(), setup));
+        }
+        Some(GameRecord { tags, black_first, line, result })
+    }
+
+    /// Sets up the board and rules the tags ask for and plays every move, variations included,
+    /// leaving the board where the main line ends.
+    pub(crate) fn replay(&self) -> Result<(Board, StandardChess), RecordError> {
+        let bad_tag = |name: &'static str| move |error: String| RecordError::BadTag { name: name.to_string(), error };
+        let rules = match self.tag("Rules") {
+            Some(s) => StandardChess::from_rules(s).map_err(bad_tag("Rules"))?,
+            None => StandardChess::new(),
+        };
+        let mut board = Board::with_piece_set(rules.piece_set());
+        match self.tag("Setup") {
+            Some(s) if s.trim_start().starts_with('{') => board.set_up(&Setup::parse(s).map_err(bad_tag("Setup"))?),
+            Some(s) => fen_deserialize(&mut board, s).map_err(bad_tag("Setup"))?,
+            None => board.set_up(&Setup::standard()),
+        }
+        play_line(&mut board, &rules, &self.line, 0)?;
+        Ok((board, rules))
+    }
+
+    pub(crate) fn parse(s: &str) -> Result<GameRecord, RecordError> {
+        let mut reader = Reader { s, at: 0 };
+        let mut tags = Vec::new();
+        while reader.skip_space() == Some('[') {
+            tags.push(reader.tag()?);
+        }
+        let mut lines = vec![Line::default()];
+        let mut black_first = false;
+        let mut result = None;
+        while let Some(c) = reader.skip_space() {
+            let main = lines.len() == 1;
+            let line = lines.last_mut().unwrap();
+            match c {
+                '{' | ';' => {
+                    let comment = reader.comment()?;
+                    let last = match line.moves.last_mut() {
+                        Some(m) => &mut m.comment,
+                        None => &mut line.comment,
+                    };
+                    *last = Some(match last.take() {
+                        Some(before) => format!("{} {}", before, comment),
+                        None => comment,
+                    });
+                }
+                '(' if !reader.at_square() => {
+                    if line.moves.is_empty() {
+                        return Err(reader.error("a variation has to follow a move"));
+                    }
+                    reader.at += 1;
+                    lines.push(Line::default());
+                }
+                ')' => {
+                    reader.at += 1;
+                    let variation = lines.pop().unwrap();
+                    match lines.last_mut().and_then(|line| line.moves.last_mut()) {
+                        Some(m) if !variation.moves.is_empty() => m.variations.push(variation),
+                        _ => return Err(reader.error("a variation that was never opened or is empty")),
+                    }
+                }
+                '}' => return Err(reader.error("unexpected }")),
+                _ => {
+                    let word = reader.word();
+                    if word.is_empty() {
+                        return Err(reader.error(&format!("unexpected {}", c)));
+                    }
+                    if RESULTS.contains(&word) && main {
+                        result = Some(word.to_string());
+                        break;
+                    }
+                    let digits = word.trim_start_matches(|c: char| c.is_ascii_digit());
+                    let notation = if digits.len() < word.len() && digits.starts_with('.') {
+                        if main && line.moves.is_empty() && digits.starts_with("...") {
+                            black_first = true;
+                        }
+                        digits.trim_start_matches('.')
+                    } else {
+                        word
+                    };
+                    // Numeric annotation glyphs like $1 are dropped.
+                    if !notation.is_empty() && !notation.starts_with('$') {
+                        line.moves.push(RecordedMove { notation: notation.to_string(), ..Default::default() });
+                    }
+                }
+            }
+        }
+        if lines.len() > 1 {
+            return Err(reader.error("a variation is never closed"));
+        }
+        if reader.skip_space().is_some() {
+            return Err(reader.error("something follows the result"));
+        }
+        let result = result.or_else(|| tags.iter().find(|(n, _)| n == "Result").map(|(_, v)| v.clone())).unwrap_or_else(|| "*".to_string());
+        Ok(GameRecord { tags, black_first, line: lines.pop().unwrap(), result })
+    }
+
+    pub(crate) fn write(&self) -> String {
+        let mut out = String::new();
+        for (name, value) in &self.tags {
+            out += &format!("[{} \"{}\"]\n", name, escape(value));
+        }
+        let mut words = Vec::new();
+        write_line(&self.line, usize::from(self.black_first), &mut words);
+        words.push(self.result.clone());
+        let mut width = 0;
+        out.push('\n');
+        for word in words {
+            if width > 0 && width + 1 + word.len() > LINE_WIDTH {
+                out.push('\n');
+                width = 0;
+            } else if width > 0 {
+                out.push(' ');
+                width += 1;
+            }
+            width += word.len();
+            out += &word;
+            // A comment to the end of the line starts the next one.
+            if let Some(end) = word.rfind('\n') {
+                width = word.len() - end - 1;
+            }
+        }
+        out.push('\n');
+        out
+    }
+}
+
+/// Plays a line, and the variations along it from the positions they branch off at.
+fn play_line(board: &mut Board, rules: &dyn PieceRules, line: &Line, ply: usize) -> Result<(), RecordError> {
+    for (i, recorded) in line.moves.iter().enumerate() {
+        for variation in &recorded.variations {
+            play_line(&mut board.clone(), rules, variation, ply + i)?;
+        }
+        let m = parse_move(board, rules, &recorded.notation).map_err(|error| RecordError::BadMove {
+            ply: ply + i,
+            text: recorded.notation.clone(),
+            error,
+        })?;
+        board.do_move(m);
+    }
+    Ok(())
+}
+
+/// A comment as it is written: in braces, or after a semicolon to the end of the line if it
+/// has a closing brace in it.
+fn comment_word(comment: &str) -> String {
+    if comment.contains('}') {
+        format!("; {}\n", comment.replace('\n', " "))
+    } else {
+        format!("{{{}}}", comment)
+    }
+}
+
+/// Adds a line's moves, comments and variations to `words`. `index` counts half moves from
+/// white's first, so that even ones are white's.
+fn write_line(line: &Line, index: usize, words: &mut Vec<String>) {
+    let mut interrupted = true;
+    if let Some(comment) = &line.comment {
+        words.push(comment_word(comment));
+    }
+    for (i, m) in line.moves.iter().enumerate() {
+        let index = index + i;
+        if index % 2 == 0 {
+            words.push(format!("{}.", index / 2 + 1));
+        } else if interrupted {
+            words.push(format!("{}...", index / 2 + 1));
+        }
+        words.push(m.notation.clone());
+        interrupted = false;
+        if let Some(comment) = &m.comment {
+            words.push(comment_word(comment));
+            interrupted = true;
+        }
+        for variation in &m.variations {
+            let mut inner = Vec::new();
+            write_line(variation, index, &mut inner);
+            if let Some(first) = inner.first_mut() {
+                first.insert(0, '(');
+            }
+            if let Some(last) = inner.last_mut() {
+                last.push(')');
+            }
+            words.extend(inner);
+            interrupted = true;
+        }
+    }
+}
+
+/// Reads a game record from the front.
+struct Reader<'a> {
+    s: &'a str,
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn rest(&self) -> &str {
+        &self.s[self.at..]
+    }
+
+    fn error(&self, what: &str) -> RecordError {
+        let line = self.s[..self.at].matches('\n').count() + 1;
+        RecordError::Syntax(format!("{} on line {}", what, line))
+    }
+
+    /// Skips whitespace and returns what comes next.
+    fn skip_space(&mut self) -> Option<char> {
+        let rest = self.rest();
+        self.at += rest.len() - rest.trim_start().len();
+        self.rest().chars().next()
+    }
+
+    /// Reads `[Name "value"]`.
+    fn tag(&mut self) -> Result<(String, String), RecordError> {
+        self.at += 1;
+        let name: String = self.rest().chars().take_while(|c| c.is_ascii_alphanumeric() || *c == '_').collect();
+        self.at += name.len();
+        if name.is_empty() || self.skip_space() != Some('"') {
+            return Err(self.error("a tag needs a name and a quoted value"));
+        }
+        self.at += 1;
+        let mut value = String::new();
+        let mut chars = self.rest().char_indices();
+        let end = loop {
+            match chars.next() {
+                Some((_, '\\')) => value.extend(chars.next().map(|(_, c)| c)),
+                Some((i, '"')) => break i,
+                Some((_, c)) => value.push(c),
+                None => return Err(self.error("a tag value is never closed")),
+            }
+        };
+        self.at += end + 1;
+        if self.skip_space() != Some(']') {
+            return Err(self.error("a tag is never closed"));
+        }
+        self.at += 1;
+        Ok((name, value))
+    }
+
+    /// Reads a comment in braces, or one from a semicolon to the end of the line.
+    fn comment(&mut self) -> Result<String, RecordError> {
+        let (close, unclosed) = if self.rest().starts_with('{') { ('}', true) } else { ('\n', false) };
+        let end = match self.rest().find(close) {
+            Some(end) => end,
+            None if unclosed => return Err(self.error("a comment is never closed")),
+            None => self.rest().len(),
+        };
+        let comment = self.rest()[1..end].trim().to_string();
+        self.at = (self.at + end + 1).min(self.s.len());
+        Ok(comment)
+    }
+
+    /// Whether the parenthesis coming up opens a square like (-1,4) rather than a variation.
+    fn at_square(&self) -> bool {
+        let rest = self.rest();
+        let inside = match rest.find(')') {
+            Some(end) => &rest[1..end],
+            None => return false,
+        };
+        match inside.split_once(',') {
+            Some((file, rank)) => [file, rank].iter().all(|x| {
+                let x = x.trim();
+                let digits = x.strip_prefix('-').unwrap_or(x);
+                !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+            }),
+            None => false,
+        }
+    }
+
+    /// Reads a move, move number or result, taking in any squares in parentheses.
+    fn word(&mut self) -> &'a str {
+        let start = self.at;
+        while let Some(c) = self.rest().chars().next() {
+            if c == '(' && self.at_square() {
+                self.at += self.rest().find(')').unwrap() + 1;
+            } else if c.is_whitespace() || "(){};".contains(c) {
+                break;
+            } else {
+                self.at += c.len_utf8();
+            }
+        }
+        &self.s[start..self.at]
+    }
+}
+
+#[cfg(test)]
+use crate::setup::REPEATING_SETUP;
+
+#[test]
+fn record_round_trip() {
+    let text = r#"[White "Ann \"the Rook\""]
+[Black "Bo"]
+[Date "2024.05.01"]
+
+{An open game.} 1. e4 e5 2. Nf3 {Developing.} 2... Nc6 (2... d6 3. d4 (3. Bc4 Be7)
+3... Nf6) 3. Bb5 a6 4. (1000,4) $1 (4. O-O Nf6) 4... (-7,5)! *
+"#;
+    let record = GameRecord::parse(text).unwrap();
+    assert_eq!(record.tag("White"), Some("Ann \"the Rook\""));
+    assert_eq!(record.line.comment.as_deref(), Some("An open game."));
+    assert_eq!(record.line.moves.len(), 8);
+    assert_eq!(record.line.moves[3].variations[0].moves[1].variations[0].moves[0].notation, "Bc4");
+    assert_eq!(record.line.moves[6].notation, "(1000,4)");
+    assert_eq!(record.result, "*");
+    let (board, rules) = record.replay().unwrap();
+    assert_eq!(board.moves.len(), 8);
+    // Writing it again gives text that reads back the same.
+    assert_eq!(GameRecord::parse(&record.write()).unwrap(), record);
+
+    // Exported from the board the moves come out the same, without the annotations.
+    let tags = vec![("White".to_string(), "Ann".to_string())];
+    let exported = GameRecord::of(&board, &rules, &tags).unwrap();
+    let notation: Vec<&str> = exported.line.moves.iter().map(|m| m.notation.as_str()).collect();
+    assert_eq!(notation, ["e4", "e5", "Nf3", "Nc6", "Bb5", "a6", "(1000,4)", "(-7,5)"]);
+    assert_eq!(exported.tag("Result"), Some("*"));
+    let written = exported.write();
+    assert!(written.contains("4. (1000,4) (-7,5) *"));
+    assert_eq!(GameRecord::parse(&written).unwrap().replay().unwrap().0.moves.len(), 8);
+}
+
+#[test]
+fn record_errors_and_results() {
+    let wrong = GameRecord::parse("1. e4 e5 2. Ke3 *").unwrap();
+    assert!(matches!(wrong.replay(), Err(RecordError::BadMove { ply: 2, .. })));
+    // Variations are checked too, from the position they branch off at.
+    let wrong = GameRecord::parse("1. e4 e5 (1... e4) 2. d4 *").unwrap();
+    assert!(matches!(wrong.replay(), Err(RecordError::BadMove { ply: 1, .. })));
+    assert!(matches!(GameRecord::parse("1. e4 (e5"), Err(RecordError::Syntax(_))));
+    assert!(matches!(GameRecord::parse("[White Ann] 1. e4"), Err(RecordError::Syntax(_))));
+    assert!(matches!(GameRecord::parse("1. e4 } *"), Err(RecordError::Syntax(_))));
+    // A comment to the end of the line may have a closing brace, and is written back that way.
+    let record = GameRecord::parse("1. e4 ; White {the best} opens\n e5 {Fine.} (1... c5 ; }\n) *").unwrap();
+    assert_eq!(record.line.moves[0].comment.as_deref(), Some("White {the best} opens"));
+    assert_eq!(GameRecord::parse(&record.write()).unwrap(), record);
+    let bad_setup = GameRecord::parse("[Setup \"{\\\"pieces\\\": 3}\"] *").unwrap();
+    assert!(matches!(bad_setup.replay(), Err(RecordError::BadTag { .. })));
+
+    // A game from another setup, with black to move first, ending in mate.
+    let setup = REPEATING_SETUP.replace("\"to_move\": \"white\"", "\"to_move\": \"black\"");
+    let tags = vec![("Setup".to_string(), setup)];
+    let mut record = GameRecord::parse("1... Nc6 2. Nc3 Nb4 3. Nb5 Nxc2+ *").unwrap();
+    record.tags = tags.clone();
+    let (board, rules) = record.replay().unwrap();
+    let exported = GameRecord::of(&board, &rules, &tags).unwrap();
+    assert!(exported.black_first);
+    // Left to itself the export writes the position the game started from in one line.
+    let own = GameRecord::of(&board, &rules, &[]).unwrap();
+    assert!(own.tag("Setup").is_some_and(|s| s.ends_with("rnbqkbnr@8-{4},RNBQKBNR@1-{4} b KQkq -")));
+    assert_eq!(GameRecord::parse(&own.write()).unwrap().replay().unwrap().0.moves.len(), 5);
+    assert!(exported.write().contains("1... Nc6 2. Nc3 Nb4 3. Nb5 Nxc2+ *"));
+    let (again, _) = GameRecord::parse(&exported.write()).unwrap().replay().unwrap();
+    assert_eq!(again.moves.len(), 5);
+}
@@ -0,0 +1,78 @@
+use crate::coord::Coord;
+
+/// Why a move was turned down.
+#[derive(Clone, Debug, PartialEq)]
+pub enum IllegalMove {
+    NoPiece,
+    NotYourTurn,
+    GameOver,
+    NotMoving,
+    OwnPiece,
+    /// Something stands in the way on this square.
+    Blocked { rank: Coord, file: Coord },
+    /// The piece can only go there by capturing.
+    MustCapture,
+    /// The piece can go there, but not to capture.
+    CannotCapture,
+    CannotMoveThatWay,
+    CastlingRightsLost,
+    CastlingThroughCheck,
+    /// The move would leave the mover's king attacked by the piece on this square.
+    LeavesKingInCheck { rank: Coord, file: Coord },
+    PromotionRequired,
+    /// The piece can't promote here, or not to this.
+    BadPromotion(String),
+}
+
+impl IllegalMove {
+    fn reason(&self) -> &'static str {
+        match self {
+            IllegalMove::NoPiece => "no_piece",
+            IllegalMove::NotYourTurn => "not_your_turn",
+            IllegalMove::GameOver => "game_over",
+            IllegalMove::NotMoving => "not_moving",
+            IllegalMove::OwnPiece => "own_piece",
+            IllegalMove::Blocked { .. } => "blocked",
+            IllegalMove::MustCapture => "must_capture",
+            IllegalMove::CannotCapture => "cannot_capture",
+            IllegalMove::CannotMoveThatWay => "cannot_move_that_way",
+            IllegalMove::CastlingRightsLost => "castling_rights_lost",
+            IllegalMove::CastlingThroughCheck => "castling_through_check",
+            IllegalMove::LeavesKingInCheck { .. } => "leaves_king_in_check",
+            IllegalMove::PromotionRequired => "promotion_required",
+            IllegalMove::BadPromotion(_) => "bad_promotion",
+        }
+    }
+
+    pub fn serialize(&self) -> String {
+        let extra = match self {
+            IllegalMove::Blocked { rank, file } => format!(", \"y\": \"{}\", \"x\": \"{}\"", rank, file),
+            IllegalMove::LeavesKingInCheck { rank, file } => format!(", \"y\": \"{}\", \"x\": \"{}\"", rank, file),
+            // The promotion came from whoever asked for it, so it has to be escaped.
+            IllegalMove::BadPromotion(p) => format!(", \"promotion\": {}", serde_json::Value::from(p.as_str())),
+            _ => String::new(),
+        };
+        format!("{{\"reason\": \"{}\", \"message\": {}{}}}", self.reason(), serde_json::Value::from(self.to_string()), extra)
+    }
+}
+
+impl std::fmt::Display for IllegalMove {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IllegalMove::NoPiece => write!(f, "there is no piece there"),
+            IllegalMove::NotYourTurn => write!(f, "it is not your turn"),
+            IllegalMove::GameOver => write!(f, "the game is over"),
+            IllegalMove::NotMoving => write!(f, "the piece has to go somewhere"),
+            IllegalMove::OwnPiece => write!(f, "one of your own pieces is there"),
+            IllegalMove::Blocked { rank, file } => write!(f, "the way is blocked at {}, {}", rank, file),
+            IllegalMove::MustCapture => write!(f, "the piece can only go there by capturing"),
+            IllegalMove::CannotCapture => write!(f, "the piece can't capture that way"),
+            IllegalMove::CannotMoveThatWay => write!(f, "the piece can't move that way"),
+            IllegalMove::CastlingRightsLost => write!(f, "the king or rook has already moved"),
+            IllegalMove::CastlingThroughCheck => write!(f, "the king can't castle out of or through check"),
+            IllegalMove::LeavesKingInCheck { .. } => write!(f, "that would leave your king in check"),
+            IllegalMove::PromotionRequired => write!(f, "the piece has to promote"),
+            IllegalMove::BadPromotion(p) => write!(f, "the piece can't promote to {} here", p),
+        }
+    }
+}
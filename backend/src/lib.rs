@@ -1,8 +1,22 @@
 pub mod board;
+mod board_binary;
+mod board_delta;
 mod board_serializer;
-mod pawn_rank;
+mod coord;
+mod fen;
+mod game_record;
+mod piece_row;
 mod piece;
+mod piece_definition;
 mod piece_rules;
 //mod error;
 mod piece_serializer;
-mod moves;
\ No newline at end of file
+mod position;
+mod setup;
+mod move_set;
+mod illegal_move;
+mod moves;
+mod notation;
+#[cfg(test)]
+mod test_util;
+mod wire_error;
\ No newline at end of file
@@ -0,0 +1,279 @@
+use crate::board::MAX_RECT_FILES;
+use crate::coord::Coord;
+
+fn floor_div(a: &Coord, b: &Coord) -> Coord {
+    let (q, r) = (a / b, a % b);
+    if r != 0.into() && r.is_negative() != b.is_negative() {
+        q - 1
+    } else {
+        q
+    }
+}
+
+fn ceil_div(a: &Coord, b: &Coord) -> Coord {
+    -floor_div(&-a, b)
+}
+
+/// The range of k with `lo <= origin + k * step <= hi`, or None for every k when step is 0.
+fn index_range(origin: &Coord, step: &Coord, lo: &Coord, hi: &Coord) -> Option<(Coord, Coord)> {
+    if step.is_positive() {
+        Some((ceil_div(&(lo - origin), step), floor_div(&(hi - origin), step)))
+    } else if step.is_negative() {
+        Some((ceil_div(&(hi - origin), step), floor_div(&(lo - origin), step)))
+    } else if origin >= lo && origin <= hi {
+        None
+    } else {
+        Some((1.into(), 0.into()))
+    }
+}
+
+/// Where a ray of moves stops.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RayEnd {
+    /// Nothing ever gets in the way.
+    Unbounded,
+    /// The ray stops at this square, which is only a target itself if `capture` is set.
+    Blocked {
+        rank: Coord,
+        file: Coord,
+        capture: bool,
+    },
+}
+
+/// The squares `origin + k * step` for k = 0, 1, 2, ... up to `end`, leaving out `skip`.
+///
+/// Sliding pieces step one square at a time and never skip anything. Leapers that repeat, like
+/// the knight, take bigger steps and jump over whatever sits in between.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ray {
+    rank: Coord,
+    file: Coord,
+    step_rank: Coord,
+    step_file: Coord,
+    end: RayEnd,
+    skip: Vec<(Coord, Coord)>,
+}
+
+impl Ray {
+    pub fn new(rank: Coord, file: Coord, step_rank: Coord, step_file: Coord, end: RayEnd) -> Self {
+        Self {
+            rank,
+            file,
+            step_rank,
+            step_file,
+            end,
+            skip: Vec::new(),
+        }
+    }
+    pub fn add_skip(mut self, rank: Coord, file: Coord) -> Self {
+        if self.index_of(&rank, &file).is_some() {
+            self.skip.push((rank, file));
+        }
+        self
+    }
+    pub fn square(&self, k: &Coord) -> (Coord, Coord) {
+        (&self.rank + k * &self.step_rank, &self.file + k * &self.step_file)
+    }
+    /// The k with `square(k) == (rank, file)`, whether or not that square is past the end.
+    fn index_of(&self, rank: &Coord, file: &Coord) -> Option<Coord> {
+        let (dr, df) = (rank - &self.rank, file - &self.file);
+        let k = if self.step_rank != 0.into() {
+            &dr / &self.step_rank
+        } else {
+            &df / &self.step_file
+        };
+        if k.is_negative() || dr != &k * &self.step_rank || df != &k * &self.step_file {
+            return None;
+        }
+        Some(k)
+    }
+    /// The last k on the ray, if there is one.
+    fn last_index(&self) -> Option<Coord> {
+        match &self.end {
+            RayEnd::Unbounded => None,
+            RayEnd::Blocked { rank, file, capture } => {
+                let k = self.index_of(rank, file).unwrap();
+                Some(if *capture { k } else { k - 1 })
+            }
+        }
+    }
+    fn skips(&self, k: &Coord) -> bool {
+        let square = self.square(k);
+        self.skip.contains(&square)
+    }
+    pub fn contains(&self, rank: &Coord, file: &Coord) -> bool {
+        match self.index_of(rank, file) {
+            Some(k) => self.last_index().map_or(true, |last| k <= last) && !self.skips(&k),
+            None => false,
+        }
+    }
+    /// The first square of the ray, if it has any.
+    pub fn first(&self) -> Option<(Coord, Coord)> {
+        let mut k: Coord = 0.into();
+        while self.skips(&k) {
+            k += 1;
+        }
+        match self.last_index() {
+            Some(last) if k > last => None,
+            _ => Some(self.square(&k)),
+        }
+    }
+    /// Whether the ray holds exactly one square.
+    pub fn is_single(&self) -> bool {
+        self.skip.is_empty() && self.last_index() == Some(0.into())
+    }
+    /// The part of the ray on the squares `from + t * (dr, df)` for `1 <= t <= n`.
+    pub fn clip(&self, from_rank: &Coord, from_file: &Coord, dr: i32, df: i32, n: &Coord) -> Option<Ray> {
+        // Solve origin + k * step == from + t * (dr, df).
+        let x = from_rank - &self.rank;
+        let y = from_file - &self.file;
+        let det = &self.step_file * dr - &self.step_rank * df;
+        let (lo, hi) = if det != 0.into() {
+            let k = &y * dr - &x * df;
+            let t = &self.step_rank * &y - &self.step_file * &x;
+            if &k % &det != 0.into() || &t % &det != 0.into() {
+                return None;
+            }
+            let (k, t) = (k / &det, t / &det);
+            if t < 1.into() || &t > n {
+                return None;
+            }
+            (k.clone(), k)
+        } else {
+            if &x * df != &y * dr {
+                return None;
+            }
+            // Collinear: t = t0 + k * m along the line of the segment.
+            let (t0, m) = if dr != 0 {
+                (-&x * dr, &self.step_rank * dr)
+            } else {
+                (-&y * df, &self.step_file * df)
+            };
+            index_range(&t0, &m, &1.into(), n)?
+        };
+        let lo = lo.max(0.into());
+        let hi = match self.last_index() {
+            Some(last) => hi.min(last),
+            None => hi,
+        };
+        if lo > hi {
+            return None;
+        }
+        let (rank, file) = self.square(&lo);
+        let (end_rank, end_file) = self.square(&(&hi + 1));
+        let mut ray = Ray::new(
+            rank,
+            file,
+            self.step_rank.clone(),
+            self.step_file.clone(),
+            RayEnd::Blocked { rank: end_rank, file: end_file, capture: false },
+        );
+        for (rank, file) in &self.skip {
+            ray = ray.add_skip(rank.clone(), file.clone());
+        }
+        ray.first().map(|_| ray)
+    }
+    fn squares_in(&self, min_rank: &Coord, min_file: &Coord, max_rank: &Coord, max_file: &Coord) -> Vec<(Coord, Coord)> {
+        let mut lo: Coord = 0.into();
+        let mut hi = self.last_index();
+        for range in [
+            index_range(&self.rank, &self.step_rank, min_rank, &(max_rank - 1)),
+            index_range(&self.file, &self.step_file, min_file, &(max_file - 1)),
+        ]
+        .iter()
+        .flatten()
+        {
+            lo = lo.max(range.0.clone());
+            hi = Some(hi.map_or(range.1.clone(), |hi| hi.min(range.1.clone())));
+        }
+        let mut out = Vec::new();
+        // Both steps can't be zero, so at least one range above bounded k.
+        let hi = hi.unwrap();
+        while lo <= hi {
+            if !self.skips(&lo) {
+                out.push(self.square(&lo));
+            }
+            lo += 1;
+        }
+        out
+    }
+    pub fn serialize(&self) -> String {
+        let end = match &self.end {
+            RayEnd::Unbounded => "null".to_string(),
+            RayEnd::Blocked { rank, file, capture } => {
+                format!("{{\"y\": \"{}\", \"x\": \"{}\", \"capture\": {}}}", rank, file, capture)
+            }
+        };
+        let skip = self
+            .skip
+            .iter()
+            .map(|(rank, file)| format!("[\"{}\", \"{}\"]", rank, file))
+            .collect::<Vec<String>>()
+            .join(",");
+        format!(
+            "{{\"y\": \"{}\", \"x\": \"{}\", \"dy\": \"{}\", \"dx\": \"{}\", \"end\": {}, \"skip\": [{}]}}",
+            self.rank, self.file, self.step_rank, self.step_file, end, skip
+        )
+    }
+}
+
+/// Everywhere a piece can move, as single squares plus rays that may run on forever.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MoveSet {
+    targets: Vec<(Coord, Coord)>,
+    rays: Vec<Ray>,
+}
+
+impl MoveSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn add_target(&mut self, rank: Coord, file: Coord) {
+        self.targets.push((rank, file));
+    }
+    pub fn add_ray(&mut self, ray: Ray) {
+        if ray.is_single() {
+            let (rank, file) = ray.square(&0.into());
+            self.add_target(rank, file);
+        } else {
+            self.rays.push(ray);
+        }
+    }
+    pub fn get_targets(&self) -> &Vec<(Coord, Coord)> {
+        &self.targets
+    }
+    pub fn get_rays(&self) -> &Vec<Ray> {
+        &self.rays
+    }
+    /// Every square of the set with `min <= rank < max` and `min <= file < max`, sorted. None
+    /// for windows more than `MAX_RECT_FILES` across either way, since a ray crossing one
+    /// can have a square in it on every rank or file.
+    pub fn squares_in(&self, min_rank: &Coord, min_file: &Coord, max_rank: &Coord, max_file: &Coord) -> Option<Vec<(Coord, Coord)>> {
+        let limit = Coord::from(MAX_RECT_FILES);
+        if max_rank - min_rank > limit || max_file - min_file > limit {
+            return None;
+        }
+        let mut out: Vec<(Coord, Coord)> = self
+            .targets
+            .iter()
+            .filter(|(r, f)| r >= min_rank && r < max_rank && f >= min_file && f < max_file)
+            .cloned()
+            .collect();
+        for ray in &self.rays {
+            out.extend(ray.squares_in(min_rank, min_file, max_rank, max_file));
+        }
+        out.sort();
+        out.dedup();
+        Some(out)
+    }
+    pub fn serialize(&self) -> String {
+        let targets = self
+            .targets
+            .iter()
+            .map(|(rank, file)| format!("[\"{}\", \"{}\"]", rank, file))
+            .collect::<Vec<String>>()
+            .join(",");
+        let rays = self.rays.iter().map(|x| x.serialize()).collect::<Vec<String>>().join(",");
+        format!("{{\"targets\": [{}], \"rays\": [{}]}}", targets, rays)
+    }
+}
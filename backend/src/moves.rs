@@ -1,42 +1,84 @@
-use num_bigint::BigInt;
-use serde_json::Value;
+use crate::coord::Coord;
+use serde::{Deserialize, Serialize};
+
+use crate::wire_error::WireError;
+
+/// The piece index a move gives a pawn that is still on its infinite rank. Such pawns only get
+/// a real index once a move that takes or captures them is made.
+pub(crate) const UNPLACED: usize = usize::MAX;
 
 #[derive(Clone)]
 pub struct Motion {
     piece: usize,
-    from_rank: BigInt,
-    from_file: BigInt,
-    to_rank: BigInt,
-    to_file: BigInt,
+    /// Whether the piece was still on its infinite row and this move brought it in.
+    from_row: bool,
+    from_rank: Coord,
+    from_file: Coord,
+    to_rank: Coord,
+    to_file: Coord,
 }
 
 impl Motion {
     pub(crate) fn get_piece(&self) -> usize {
         self.piece
     }
-    pub(crate) fn get_rank(&self) -> &BigInt {
+    pub(crate) fn get_rank(&self) -> &Coord {
         &self.to_rank
     }
-    pub(crate) fn get_file(&self) -> &BigInt {
+    pub(crate) fn get_file(&self) -> &Coord {
         &self.to_file
     }
-    pub(crate) fn get_from_rank(&self) -> &BigInt {
+    pub(crate) fn get_from_rank(&self) -> &Coord {
         &self.from_rank
     }
-    pub(crate) fn get_from_file(&self) -> &BigInt {
+    pub(crate) fn get_from_file(&self) -> &Coord {
         &self.from_file
     }
+    pub(crate) fn is_from_row(&self) -> bool {
+        self.from_row
+    }
 }
 
 #[derive(Clone)]
 pub struct Captures {
     piece: usize,
+    rank: Coord,
+    file: Coord,
+    from_row: bool,
 }
 
 impl Captures {
     pub(crate) fn get_piece(&self) -> usize {
         self.piece
     }
+    pub(crate) fn get_rank(&self) -> &Coord {
+        &self.rank
+    }
+    pub(crate) fn get_file(&self) -> &Coord {
+        &self.file
+    }
+    pub(crate) fn is_from_row(&self) -> bool {
+        self.from_row
+    }
+}
+
+#[derive(Clone)]
+pub struct Promotion {
+    piece: usize,
+    from: String,
+    to: String,
+}
+
+impl Promotion {
+    pub(crate) fn get_piece(&self) -> usize {
+        self.piece
+    }
+    pub(crate) fn get_from(&self) -> &String {
+        &self.from
+    }
+    pub(crate) fn get_to(&self) -> &String {
+        &self.to
+    }
 }
 
 #[derive(Clone)]
@@ -44,45 +86,98 @@ pub struct Move {
     piece: usize,
     motions: Vec<Motion>,
     captures: Vec<Captures>,
+    promotion: Option<Promotion>,
 }
 impl Move {
     pub fn new(piece: usize) -> Move {
-        Self { piece, motions: Vec::new(), captures: Vec::new() }
+        Self { piece, motions: Vec::new(), captures: Vec::new(), promotion: None }
     }
-    pub fn standard(piece: usize, to_rank: &BigInt, to_file: &BigInt, from_rank: &BigInt, from_file: &BigInt) -> Move {
-        let mut move_ = Move::new(piece);
+    pub fn standard(piece: usize, to_rank: &Coord, to_file: &Coord, from_rank: &Coord, from_file: &Coord) -> Move {
+        let move_ = Move::new(piece);
         move_.add_motion(piece, to_rank, to_file, from_rank, from_file)
     }
-    pub fn capture(piece: usize, to_rank: &BigInt, to_file: &BigInt, from_rank: &BigInt, from_file: &BigInt, captured: usize) -> Move {
-        Self::standard(piece, to_rank, to_file, from_rank, from_file).add_capture(captured)
+    /// A move that captures something off the square it lands on, as en passant does.
+    pub fn capture(
+        piece: usize,
+        to_rank: &Coord,
+        to_file: &Coord,
+        from_rank: &Coord,
+        from_file: &Coord,
+        captured: usize,
+        captured_rank: &Coord,
+    ) -> Move {
+        Self::standard(piece, to_rank, to_file, from_rank, from_file).add_capture(captured, captured_rank, to_file)
+    }
+    pub fn add_capture(mut self, piece: usize, rank: &Coord, file: &Coord) -> Self {
+        self.captures.push(Captures { piece, rank: rank.clone(), file: file.clone(), from_row: false });
+        self
     }
-    pub fn add_capture(mut self, piece: usize) -> Self {
-        self.captures.push(Captures { piece });
+    pub fn add_motion(mut self, piece: usize, to_rank: &Coord, to_file: &Coord, from_rank: &Coord, from_file: &Coord) -> Self {
+        self.motions.push(Motion { piece, from_row: false, to_rank: to_rank.clone(), to_file: to_file.clone(), from_rank: from_rank.clone(), from_file: from_file.clone()  });
         self
     }
-    pub fn add_motion(mut self, piece: usize, to_rank: &BigInt, to_file: &BigInt, from_rank: &BigInt, from_file: &BigInt) -> Self {
-        self.motions.push(Motion { piece, to_rank: to_rank.clone(), to_file: to_file.clone(), from_rank: from_rank.clone(), from_file: from_file.clone()  });
+    pub fn add_promotion(mut self, piece: usize, from: String, to: String) -> Self {
+        self.promotion = Some(Promotion { piece, from, to });
         self
     }
     pub fn get_piece(&self) -> usize {
         self.piece
     }
-    pub fn serialize(&self) -> String {
-        let mut result1 = Vec::new();
-        let mut result2 = Vec::new();
-
-        for motion in &self.motions {
-            result1.push(format!("[{}, {}, {}]", motion.piece, motion.to_rank, motion.to_file));
+    /// Swaps every `UNPLACED` index for the one `place` gives the pawn standing on the square
+    /// it moves or is captured from.
+    pub(crate) fn place(mut self, mut place: impl FnMut(&Coord, &Coord) -> usize) -> Self {
+        for motion in self.motions.iter_mut().filter(|m| m.piece == UNPLACED) {
+            let i = place(&motion.from_rank, &motion.from_file);
+            if self.piece == UNPLACED {
+                self.piece = i;
+            }
+            if let Some(promotion) = self.promotion.as_mut().filter(|p| p.piece == UNPLACED) {
+                promotion.piece = i;
+            }
+            motion.piece = i;
+            motion.from_row = true;
+        }
+        for capture in self.captures.iter_mut().filter(|c| c.piece == UNPLACED) {
+            capture.piece = place(&capture.rank, &capture.file);
+            capture.from_row = true;
         }
-        for capture in &self.captures {
-            result2.push(format!("[{}]", capture.piece));
+        self
+    }
+    /// The move as it is saved, the `ply`th of the game.
+    pub(crate) fn data(&self, ply: usize) -> MoveData {
+        MoveData {
+            ply: Some(ply),
+            motions: self
+                .motions
+                .iter()
+                .map(|x| MotionData::Full(x.piece, x.from_rank.clone(), x.from_file.clone(), x.to_rank.clone(), x.to_file.clone(), x.from_row))
+                .collect(),
+            captures: self.captures.iter().map(|x| CaptureData::Full(x.piece, x.rank.clone(), x.file.clone(), x.from_row)).collect(),
+            promotion: self.promotion.as_ref().map(|p| (p.piece, p.from.clone(), p.to.clone())),
+            piece: self.piece,
         }
-        format!("{{\"motions\": [{}], \"captures\": [{}], \"piece\": {}}}", result1.join(","), result2.join(","), self.piece)
     }
-    pub fn deserialize(s: &String) -> Option<Move> {
-        let v: Value = serde_json::from_str(s).ok()?;
-        let mut m = Move::new(v["piece"].as_u64()? as usize);
-        Some(m)
+    /// The move as it was before it was made, with `UNPLACED` again for the pieces it brought
+    /// in from their rows. Making that brings them in under the same indices as before, as
+    /// long as nothing has been placed since.
+    pub(crate) fn unplaced(&self) -> Move {
+        let mut m = self.clone();
+        let mover = m.motions.first().is_some_and(|x| x.from_row);
+        for motion in m.motions.iter_mut().filter(|x| x.from_row) {
+            motion.piece = UNPLACED;
+            motion.from_row = false;
+        }
+        for capture in m.captures.iter_mut().filter(|x| x.from_row) {
+            capture.piece = UNPLACED;
+            capture.from_row = false;
+        }
+        if mover {
+            m.piece = UNPLACED;
+            if let Some(p) = m.promotion.as_mut() {
+                p.piece = UNPLACED;
+            }
+        }
+        m
     }
 
     pub(crate) fn get_motions(&self) -> &Vec<Motion> {
@@ -92,4 +187,73 @@ impl Move {
     pub(crate) fn get_captures(&self) -> &Vec<Captures> {
         &self.captures
     }
+
+    pub(crate) fn get_promotion(&self) -> Option<&Promotion> {
+        self.promotion.as_ref()
+    }
+}
+
+/// A move as it is saved: every motion with where it starts and ends, every capture, the
+/// promotion, and which pieces it brought in from their rows.
+#[derive(Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct MoveData {
+    /// Which move of the game this is, counting from 1. Boards saved before there were
+    /// versions don't say.
+    #[serde(default)]
+    pub(crate) ply: Option<usize>,
+    pub(crate) motions: Vec<MotionData>,
+    pub(crate) captures: Vec<CaptureData>,
+    #[serde(default)]
+    pub(crate) promotion: Option<(usize, String, String)>,
+    pub(crate) piece: usize,
+}
+
+#[derive(Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub(crate) enum MotionData {
+    /// The piece, the rank and file it leaves and the ones it goes to, and whether it was
+    /// still on its row.
+    Full(usize, Coord, Coord, Coord, Coord, bool),
+    /// Motions saved before they said where they started: the piece and where it went.
+    Old(usize, Coord, Coord),
+}
+
+#[derive(Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub(crate) enum CaptureData {
+    /// The piece, the rank and file it is taken on, and whether it was still on its row.
+    Full(usize, Coord, Coord, bool),
+    /// Captures saved before they said where they happened: only the piece.
+    Old([usize; 1]),
+}
+
+impl MoveData {
+    /// The `ply`th move of the game. Moves saved before motions said where they started come
+    /// back with only the piece that moved, and can't be taken back.
+    pub(crate) fn into_move(self, ply: usize) -> Result<Move, WireError> {
+        if self.ply.is_some_and(|x| x != ply) {
+            return Err(WireError::Invalid(format!("move {} says it is move {}", ply, self.ply.unwrap_or_default())));
+        }
+        let mut m = Move::new(self.piece);
+        let old = self.motions.iter().any(|x| matches!(x, MotionData::Old(..)))
+            || self.captures.iter().any(|x| matches!(x, CaptureData::Old(_)));
+        if old {
+            return Ok(m);
+        }
+        for x in self.motions {
+            if let MotionData::Full(piece, from_rank, from_file, to_rank, to_file, from_row) = x {
+                m.motions.push(Motion { piece, from_row, from_rank, from_file, to_rank, to_file });
+            }
+        }
+        for x in self.captures {
+            if let CaptureData::Full(piece, rank, file, from_row) = x {
+                m.captures.push(Captures { piece, rank, file, from_row });
+            }
+        }
+        if let Some((piece, from, to)) = self.promotion {
+            m = m.add_promotion(piece, from, to);
+        }
+        Ok(m)
+    }
 }
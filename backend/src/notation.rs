@@ -0,0 +1,370 @@
+use crate::coord::Coord;
+
+use crate::{
+    board::Board,
+    illegal_move::IllegalMove,
+    moves::Move,
+    piece_rules::{GameStatus, PieceRules},
+};
+#[cfg(test)]
+use crate::{
+    piece::{Color, Piece},
+    piece_rules::StandardChess,
+    setup::{Setup, REPEATING_SETUP},
+};
+
+/// The letters of the standard pieces. Anything else is written out in braces, as `{camel}`,
+/// and pawns aren't written at all.
+pub(crate) const LETTERS: [(char, &str); 5] = [('K', "king"), ('Q', "queen"), ('R', "rook"), ('B', "bishop"), ('N', "knight")];
+
+/// Why a move written in notation couldn't be turned into one on the board.
+#[derive(Clone, Debug, PartialEq)]
+pub enum NotationError {
+    /// The text isn't a move at all; holds the part that couldn't be read.
+    Malformed(String),
+    /// No piece of the side to move could make a move like that.
+    NoSuchMove,
+    /// Several pieces could make the move and the text doesn't say which.
+    Ambiguous,
+    /// The only piece the text can mean isn't allowed to make the move.
+    Illegal(IllegalMove),
+}
+
+impl NotationError {
+    fn reason(&self) -> &'static str {
+        match self {
+            NotationError::Malformed(_) => "malformed",
+            NotationError::NoSuchMove => "no_such_move",
+            NotationError::Ambiguous => "ambiguous",
+            NotationError::Illegal(_) => "illegal",
+        }
+    }
+
+    pub fn serialize(&self) -> String {
+        match self {
+            NotationError::Illegal(e) => e.serialize(),
+            // The text came from whoever typed it, so it has to be escaped.
+            _ => format!("{{\"reason\": \"{}\", \"message\": {}}}", self.reason(), serde_json::Value::from(self.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for NotationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NotationError::Malformed(s) => write!(f, "can't read the move at \"{}\"", s),
+            NotationError::NoSuchMove => write!(f, "no piece can make that move"),
+            NotationError::Ambiguous => write!(f, "more than one piece can make that move"),
+            NotationError::Illegal(e) => e.fmt(f),
+        }
+    }
+}
+
+/// The value of a rank or file that is on the standard board, which is written as a letter or
+/// a digit.
+fn on_board(x: &Coord) -> Option<u8> {
+    if x >= &0.into() && x < &8.into() {
+        Some(x.rem_euclid(8) as u8)
+    } else {
+        None
+    }
+}
+
+fn file_letter(file: &Coord) -> Option<char> {
+    on_board(file).map(|f| (b'a' + f) as char)
+}
+
+/// Ranks are counted the way chess players count them, with white's back rank as 1.
+fn rank_digit(rank: &Coord) -> Option<char> {
+    on_board(rank).map(|r| (b'8' - r) as char)
+}
+
+/// A square the usual way if it is on the standard board, and otherwise as (file,rank) with
+/// the rank counted the same way, so that a1 is (0,1) and the square left of it (-1,1).
+pub(crate) fn square_notation(rank: &Coord, file: &Coord) -> String {
+    match (file_letter(file), rank_digit(rank)) {
+        (Some(f), Some(r)) => format!("{}{}", f, r),
+        _ => format!("({},{})", file, -rank + 8),
+    }
+}
+
+fn piece_letter(kind: &str) -> String {
+    match LETTERS.iter().find(|(_, name)| *name == kind) {
+        Some((letter, _)) => letter.to_string(),
+        None if kind == "pawn" => String::new(),
+        None => format!("{{{}}}", kind),
+    }
+}
+
+/// Writes a legal move of the side to move, with just enough of where it comes from to tell
+/// it apart from the moves of every other piece of the same type.
+pub(crate) fn move_notation(board: &mut Board, rules: &dyn PieceRules, m: &Move) -> Option<String> {
+    let motion = m.get_motions().first()?;
+    let (from_rank, from_file) = (motion.get_from_rank().clone(), motion.get_from_file().clone());
+    let (to_rank, to_file) = (motion.get_rank().clone(), motion.get_file().clone());
+    let piece = board.piece_at(&from_rank, &from_file)?.1.into_owned();
+    let kind = piece.get_type();
+    let castles = board.piece_set.is(kind, |d| d.castles) && m.get_motions().len() == 2 && from_rank == to_rank && (&to_file - &from_file).abs() == 2.into();
+    let mut out = if castles {
+        if to_file > from_file { "O-O".to_string() } else { "O-O-O".to_string() }
+    } else {
+        let capture = !m.get_captures().is_empty();
+        let promotion = m.get_promotion().map(|p| p.get_to().clone());
+        let mut others: Vec<(Coord, Coord)> = rules
+            .movers_to(board, &to_rank, &to_file, piece.get_color())
+            .into_iter()
+            .filter(|(r, f)| (r, f) != (&from_rank, &from_file))
+            .filter(|(r, f)| board.piece_at(r, f).is_some_and(|(_, p)| p.get_type() == kind))
+            .collect();
+        others.retain(|(r, f)| Board::move_legal(board, rules, r, f, &to_rank, &to_file, promotion.clone()).is_ok());
+        let full = square_notation(&from_rank, &from_file);
+        let origin = if others.is_empty() && !(kind == "pawn" && capture) {
+            String::new()
+        } else if !others.iter().any(|(_, f)| f == &from_file) && file_letter(&from_file).is_some() {
+            file_letter(&from_file).unwrap().to_string()
+        } else if !others.iter().any(|(r, _)| r == &from_rank) && rank_digit(&from_rank).is_some() {
+            rank_digit(&from_rank).unwrap().to_string()
+        } else {
+            full.clone()
+        };
+        let separator = match (capture, origin == full) {
+            (true, _) => "x",
+            (false, true) => "-",
+            (false, false) => "",
+        };
+        let mut out = format!("{}{}{}{}", piece_letter(kind), origin, separator, square_notation(&to_rank, &to_file));
+        if let Some(p) = promotion {
+            out += &format!("={}", piece_letter(&p));
+        }
+        out
+    };
+    let color = piece.get_color();
+    board.do_move(m.clone());
+    let king = board.king(color.opposite()).map(|k| (board.pieces[k].get_rank().clone(), board.pieces[k].get_file().clone()));
+    if king.is_some_and(|(rank, file)| Board::is_attacked(board, rules, &rank, &file, color)) {
+        out.push(if Board::game_status(board, rules) == GameStatus::Checkmate { '#' } else { '+' });
+    }
+    board.undo_move();
+    Some(out)
+}
+
+/// Part of where a move goes from or to.
+enum Part {
+    Square(Coord, Coord),
+    File(Coord),
+    Rank(Coord),
+}
+
+fn malformed(s: &str) -> NotationError {
+    NotationError::Malformed(s.to_string())
+}
+
+/// Reads a square, file letter or rank digit off the front of `s`.
+fn read_part(s: &str) -> Result<(Part, &str), NotationError> {
+    let digit = |c: Option<char>| c.filter(|c| ('1'..='8').contains(c)).map(|c| -Coord::from(c as u8 - b'0') + 8);
+    let mut chars = s.chars();
+    match chars.next() {
+        Some('(') => {
+            let end = s.find(')').ok_or_else(|| malformed(s))?;
+            let (file, rank) = s[1..end].split_once(',').ok_or_else(|| malformed(s))?;
+            let file = file.trim().parse::<Coord>().map_err(|_| malformed(s))?;
+            let rank = rank.trim().parse::<Coord>().map_err(|_| malformed(s))?;
+            Ok((Part::Square(-rank + 8, file), &s[end + 1..]))
+        }
+        Some(c @ 'a'..='h') => {
+            let file = Coord::from(c as u8 - b'a');
+            match digit(chars.next()) {
+                Some(rank) => Ok((Part::Square(rank, file), &s[2..])),
+                None => Ok((Part::File(file), &s[1..])),
+            }
+        }
+        c => digit(c).map(|rank| (Part::Rank(rank), &s[1..])).ok_or_else(|| malformed(s)),
+    }
+}
+
+/// Reads a square off the front of `s`, as `square_notation` writes it.
+pub(crate) fn read_square(s: &str) -> Option<((Coord, Coord), &str)> {
+    match read_part(s) {
+        Ok((Part::Square(rank, file), rest)) => Some(((rank, file), rest)),
+        _ => None,
+    }
+}
+
+/// Reads a piece type off the front of `s`: a letter, a name in braces, or pawn if neither.
+fn read_kind(s: &str) -> Result<(String, &str), NotationError> {
+    if let Some(rest) = s.strip_prefix('{') {
+        let end = rest.find('}').ok_or_else(|| malformed(s))?;
+        return Ok((rest[..end].to_string(), &rest[end + 1..]));
+    }
+    match LETTERS.iter().find(|(letter, _)| s.starts_with(*letter)) {
+        Some((_, name)) => Ok((name.to_string(), &s[1..])),
+        None => Ok(("pawn".to_string(), s)),
+    }
+}
+
+/// Finds the legal move of the side to move that `text` describes. Check and mate marks and
+/// annotations like `!?` are ignored, as is whether a capture is marked.
+pub(crate) fn parse_move(board: &mut Board, rules: &dyn PieceRules, text: &str) -> Result<Move, NotationError> {
+    let text = text.trim().trim_end_matches(&['+', '#', '!', '?'][..]);
+    let legal = |board: &mut Board, from: &(Coord, Coord), to: &(Coord, Coord), promotion: Option<String>| {
+        Board::move_legal(board, rules, &from.0, &from.1, &to.0, &to.1, promotion).map_err(NotationError::Illegal)
+    };
+    let castle_to = match text {
+        "O-O" | "0-0" => Some(2),
+        "O-O-O" | "0-0-0" => Some(-2),
+        _ => None,
+    };
+    if let Some(df) = castle_to {
+        let king = board.king(board.to_move()).ok_or(NotationError::NoSuchMove)?;
+        let from = (board.pieces[king].get_rank().clone(), board.pieces[king].get_file().clone());
+        let to = (from.0.clone(), &from.1 + df);
+        return legal(board, &from, &to, None);
+    }
+    let (body, promotion) = match text.split_once('=') {
+        Some((body, p)) => match read_kind(p)? {
+            (kind, "") if kind != "pawn" => (body, Some(kind)),
+            _ => return Err(malformed(p)),
+        },
+        None => (text, None),
+    };
+    let (kind, mut rest) = read_kind(body)?;
+    let mut parts = Vec::new();
+    while !rest.is_empty() {
+        rest = rest.strip_prefix(&['x', '-'][..]).unwrap_or(rest);
+        let (part, after) = read_part(rest)?;
+        parts.push(part);
+        rest = after;
+    }
+    let to = match parts.pop() {
+        Some(Part::Square(rank, file)) => (rank, file),
+        _ => return Err(malformed(body)),
+    };
+    let is_kind = |board: &Board, (r, f): &(Coord, Coord)| board.piece_at(r, f).is_some_and(|(_, p)| p.get_type() == &kind);
+    let mut candidates = rules.movers_to(board, &to.0, &to.1, board.to_move());
+    for part in &parts {
+        match part {
+            Part::Square(rank, file) => {
+                let from = (rank.clone(), file.clone());
+                if !is_kind(board, &from) {
+                    return Err(NotationError::NoSuchMove);
+                }
+                return legal(board, &from, &to, promotion);
+            }
+            Part::File(file) => candidates.retain(|(_, f)| f == file),
+            Part::Rank(rank) => candidates.retain(|(r, _)| r == rank),
+        }
+    }
+    candidates.retain(|from| is_kind(board, from));
+    let mut moves = Vec::new();
+    let mut error = NotationError::NoSuchMove;
+    for from in &candidates {
+        match legal(board, from, &to, promotion.clone()) {
+            Ok(m) => moves.push(m),
+            Err(e) if candidates.len() == 1 => error = e,
+            Err(_) => {}
+        }
+    }
+    match moves.len() {
+        0 => Err(error),
+        1 => Ok(moves.pop().unwrap()),
+        _ => Err(NotationError::Ambiguous),
+    }
+}
+
+#[cfg(test)]
+fn play(b: &mut Board, pm: &StandardChess, moves: &[&str]) {
+    for text in moves {
+        let m = parse_move(b, pm, text).unwrap_or_else(|e| panic!("{}: {}", text, e));
+        assert_eq!(move_notation(b, pm, &m).as_deref(), Some(*text));
+        b.do_move(m);
+    }
+}
+
+#[test]
+fn squares() {
+    assert_eq!(square_notation(&7.into(), &0.into()), "a1");
+    assert_eq!(square_notation(&0.into(), &7.into()), "h8");
+    assert_eq!(square_notation(&9.into(), &(-1).into()), "(-1,-1)");
+    assert_eq!(square_notation(&3.into(), &8.into()), "(8,5)");
+    let huge = "123456789012345678901234567890".parse::<Coord>().unwrap();
+    let text = square_notation(&huge, &huge);
+    match read_part(&text) {
+        Ok((Part::Square(rank, file), "")) => assert!(rank == huge && file == huge),
+        _ => panic!("{} didn't read back", text),
+    }
+}
+
+#[test]
+fn standard_moves() {
+    let pm = StandardChess::new();
+    let mut b = Board::from_setup(&Setup::standard());
+    play(&mut b, &pm, &["e4", "d5", "exd5", "Nf6", "Nf3", "Nxd5", "Bc4", "Nb6", "O-O", "Nc6", "Bxf7+", "Kxf7"]);
+    // Both knights can go to d2 and only the file tells them apart.
+    let mut b = Board::from_setup(&Setup::standard());
+    play(&mut b, &pm, &["d4", "e5", "Nf3", "e4", "Nfd2"]);
+    // The king can step off the back rank, so fool's mate is only check.
+    let mut b = Board::from_setup(&Setup::standard());
+    play(&mut b, &pm, &["f3", "e5", "g4"]);
+    let mut mate = b.clone();
+    mate.place_piece(Piece::new("rook".to_string(), Color::Black, 8.into(), 20.into()));
+    play(&mut b, &pm, &["Qh4+"]);
+    play(&mut mate, &pm, &["Qh4#"]);
+}
+
+#[test]
+fn disambiguation() {
+    let pm = StandardChess::new();
+    let mut b = Board::new();
+    let mut place = |kind: &str, color: Color, rank: i32, file: i32| {
+        b.place_piece(Piece::new(kind.to_string(), color, rank.into(), file.into()));
+    };
+    place("king", Color::White, 20, 20);
+    place("king", Color::Black, -20, 20);
+    for (rank, file) in [(7, 1), (11, 1), (7, -1), (10, -2)] {
+        place("knight", Color::White, rank, file);
+    }
+    place("rook", Color::White, 3, 0);
+    place("rook", Color::White, 5, 0);
+    place("rook", Color::White, 4, 100);
+    let pm = &pm;
+    let notation = |b: &mut Board, from: (i32, i32), to: (i32, i32)| {
+        let m = Board::move_legal(b, pm, &from.0.into(), &from.1.into(), &to.0.into(), &to.1.into(), None).unwrap();
+        let text = move_notation(b, pm, &m).unwrap();
+        let back = parse_move(b, pm, &text).unwrap();
+        assert_eq!(back.get_motions()[0].get_from_rank(), &from.0.into());
+        assert_eq!(back.get_motions()[0].get_from_file(), &from.1.into());
+        text
+    };
+    // Four knights reach (0,-1). The one on b1 shares its file with one and its rank with
+    // another, and the one off the board can only be named in full.
+    assert_eq!(notation(&mut b, (7, 1), (9, 0)), "Nb1-(0,-1)");
+    assert_eq!(notation(&mut b, (10, -2), (9, 0)), "N(-2,-2)-(0,-1)");
+    // Two rooks on a file are told apart by rank, and the far one needs no help.
+    assert_eq!(notation(&mut b, (3, 0), (4, 0)), "R5a4");
+    assert_eq!(notation(&mut b, (4, 100), (4, 50)), "R(50,4)");
+    assert_eq!(parse_move(&mut b, pm, "R(0,4)").err(), Some(NotationError::Ambiguous));
+    assert_eq!(parse_move(&mut b, pm, "Nh3").err(), Some(NotationError::NoSuchMove));
+    assert!(matches!(parse_move(&mut b, pm, "N?!c3"), Err(NotationError::Malformed(_))));
+    let m = parse_move(&mut b, pm, "N(-2,-2)x(0,-1)").unwrap();
+    assert_eq!(m.get_motions()[0].get_from_file(), &(-2).into());
+}
+
+#[test]
+fn promotion_and_rows() {
+    let pm = StandardChess::new();
+    let mut b = Board::new();
+    b.place_piece(Piece::new("king".to_string(), Color::White, 20.into(), 20.into()));
+    b.place_piece(Piece::new("king".to_string(), Color::Black, (-20).into(), 20.into()));
+    b.place_piece(Piece::new("pawn".to_string(), Color::White, 1.into(), 30.into()));
+    // Pawns come off the infinite ranks however far out they are, and a pawn capture that
+    // two of them could make names the one that does in full.
+    play(&mut b, &pm, &["(1000,4)", "(-7,5)", "(30,8)=Q", "(-7,4)", "K(20,-13)", "(-7,3)", "(-6,2)x(-7,3)"]);
+    let mut b = Board::from_setup(&Setup::standard());
+    play(&mut b, &pm, &["h4", "g5", "hxg5", "h6", "gxh6", "Nc6", "h7", "Ne5"]);
+    assert_eq!(parse_move(&mut b, &pm, "hxg8").err(), Some(NotationError::Illegal(IllegalMove::PromotionRequired)));
+    assert!(matches!(parse_move(&mut b, &pm, "hxg8=K"), Err(NotationError::Illegal(IllegalMove::BadPromotion(_)))));
+    play(&mut b, &pm, &["hxg8=N"]);
+    // The repeating back ranks have knights all the way out.
+    let mut b = Board::from_setup(&Setup::parse(REPEATING_SETUP).unwrap());
+    play(&mut b, &pm, &["Nc3", "N(-6,6)"]);
+}
@@ -1,11 +1,23 @@
-use num_bigint::BigInt;
+use crate::coord::Coord;
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Color {
     Black,
     White,
 }
 
+impl Color {
+    pub fn opposite(self) -> Color {
+        match self {
+            Color::Black => Color::White,
+            Color::White => Color::Black,
+        }
+    }
+}
+
+#[allow(clippy::to_string_trait_impl)]
 impl ToString for Color {
     fn to_string(&self) -> String {
         match self {
@@ -19,15 +31,15 @@ impl ToString for Color {
 #[derive(Clone)]
 pub struct Piece {
     piece: String,
-    rank: BigInt,
-    file: BigInt,
+    rank: Coord,
+    file: Coord,
     captured: bool,
     ply_moved: usize,
     color: Color,
 }
 
 impl Piece {
-    pub fn new(piece: String, color: Color, rank: BigInt, file: BigInt) -> Self {
+    pub fn new(piece: String, color: Color, rank: Coord, file: Coord) -> Self {
         Self {
             piece,
             color,
@@ -43,41 +55,34 @@ impl Piece {
     pub fn set_type(&mut self, piece: String) {
         self.piece = piece;
     }
-    #[cfg(not(features = "server"))]
     pub fn get_color(&self) -> Color {
         self.color
     }
-    pub fn get_rank(&self) -> &BigInt {
+    pub fn get_rank(&self) -> &Coord {
         &self.rank
     }
-    pub fn get_file(&self) -> &BigInt {
+    pub fn get_file(&self) -> &Coord {
         &self.file
     }
     pub fn is_captured(&self) -> bool {
         self.captured
     }
-    #[cfg(not(features = "server"))]
     pub fn has_moved(&self) -> bool {
         self.ply_moved != 0
     }
-    #[cfg(not(features = "server"))]
     pub fn when_moved(&self) -> usize {
         self.ply_moved 
     }
-    #[cfg(not(features = "server"))]
     pub fn set_has_moved(&mut self, ply: usize) {
         self.ply_moved = ply;
     }
-    #[cfg(not(features = "server"))]
     pub(crate) fn capture(&mut self) {
         self.captured = true;
     }
-    #[cfg(not(features = "server"))]
     pub(crate) fn uncapture(&mut self) {
         self.captured = false;
     }
-    #[cfg(not(features = "server"))]
-    pub(crate) fn goto(&mut self, rank: &BigInt, file: &BigInt, ply: usize) {
+    pub(crate) fn goto(&mut self, rank: &Coord, file: &Coord, ply: usize) {
         self.rank = rank.clone();
         self.file = file.clone();
         if self.ply_moved  == 0 {
@@ -0,0 +1,202 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, OnceLock},
+};
+
+use serde::Deserialize;
+
+/// Whether a movement component may be used to move to an empty square, to capture, or both.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    #[default]
+    Both,
+    Move,
+    Capture,
+}
+
+impl Mode {
+    pub fn allows(self, capture: bool) -> bool {
+        match self {
+            Mode::Both => true,
+            Mode::Move => !capture,
+            Mode::Capture => capture,
+        }
+    }
+}
+
+/// One way a piece can move. Offsets are `[forward, sideways]` as seen by the piece's owner, so
+/// a pawn's `[1, 0]` goes down the board for black and up it for white.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Component {
+    /// Jumps straight to `origin + offset`, whatever is in between.
+    #[serde(default)]
+    pub leap: Vec<[i32; 2]>,
+    /// Slides any number of steps of `offset` over empty squares.
+    #[serde(default)]
+    pub ride: Vec<[i32; 2]>,
+    /// The most steps a ride may take.
+    #[serde(default)]
+    pub range: Option<u32>,
+    /// Lets the longer side of each leap be stretched by any multiple of this, the way the
+    /// knight wraps around every eight squares. On a tie the sideways side is stretched.
+    #[serde(default)]
+    pub period: Option<i32>,
+    #[serde(default)]
+    pub mode: Mode,
+    /// Only usable by a piece that hasn't moved yet.
+    #[serde(default)]
+    pub initial: bool,
+    /// Also allows every reflection of each offset and its sides swapped.
+    #[serde(default)]
+    pub symmetric: bool,
+}
+
+type Symmetry = fn((i32, i32)) -> (i32, i32);
+
+/// The eight ways of flipping and swapping an offset, or just the offset itself.
+fn symmetries(symmetric: bool) -> Vec<Symmetry> {
+    if !symmetric {
+        return vec![|(a, b)| (a, b)];
+    }
+    vec![
+        |(a, b)| (a, b),
+        |(a, b)| (a, -b),
+        |(a, b)| (-a, b),
+        |(a, b)| (-a, -b),
+        |(a, b)| (b, a),
+        |(a, b)| (b, -a),
+        |(a, b)| (-b, a),
+        |(a, b)| (-b, -a),
+    ]
+}
+
+impl Component {
+    /// Every leap as its offset from the piece and the step it may be stretched by, which is
+    /// zero for ordinary leaps, both turned to face `forward` along the ranks.
+    pub fn leaps(&self, forward: i32) -> Vec<((i32, i32), (i32, i32))> {
+        let mut out = Vec::new();
+        for &[a, b] in &self.leap {
+            let stretch = match self.period {
+                Some(p) if b.abs() >= a.abs() => (0, b.signum() * p),
+                Some(p) => (a.signum() * p, 0),
+                None => (0, 0),
+            };
+            for f in symmetries(self.symmetric) {
+                let ((a, b), (sa, sb)) = (f((a, b)), f(stretch));
+                let leap = ((a * forward, b), (sa * forward, sb));
+                if !out.contains(&leap) {
+                    out.push(leap);
+                }
+            }
+        }
+        out
+    }
+
+    /// Every direction a ride may take, turned to face `forward` along the ranks.
+    pub fn rides(&self, forward: i32) -> Vec<(i32, i32)> {
+        let mut out = Vec::new();
+        for &[a, b] in &self.ride {
+            for f in symmetries(self.symmetric) {
+                let (a, b) = f((a, b));
+                if !out.contains(&(a * forward, b)) {
+                    out.push((a * forward, b));
+                }
+            }
+        }
+        out
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.leap.iter().chain(self.ride.iter()).any(|o| o == &[0, 0]) {
+            return Err("offsets must not be [0, 0]".into());
+        }
+        if self.period.is_some_and(|p| p <= 0) {
+            return Err("period must be positive".into());
+        }
+        if self.range == Some(0) {
+            return Err("range must be at least 1".into());
+        }
+        Ok(())
+    }
+}
+
+/// Standard chess, written down the same way as any other rules file.
+pub(crate) const STANDARD_RULES: &str = include_str!("../rules/standard.json");
+
+/// How a piece type moves, plus the special moves that don't fit into components.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PieceDefinition {
+    #[serde(default)]
+    pub moves: Vec<Component>,
+    /// Can be checked and mated, like the king. One placed on the board is its side's royal
+    /// piece; those repeating in a row are ordinary pieces.
+    #[serde(default)]
+    pub royal: bool,
+    /// Castles with an unmoved piece in the corner, like the king.
+    #[serde(default)]
+    pub castles: bool,
+    /// The piece in the corner that castling pieces castle with, like the rook.
+    #[serde(default)]
+    pub castling_partner: bool,
+    /// Captures and can be captured en passant, like the pawn.
+    #[serde(default)]
+    pub en_passant: bool,
+    /// Promotes on reaching the far side of the board, like the pawn.
+    #[serde(default)]
+    pub promotes: bool,
+}
+
+/// A whole rules file: every piece type by name and what promoting pieces may become.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PieceSet {
+    #[serde(default)]
+    pub promotion: Vec<String>,
+    pub pieces: HashMap<String, PieceDefinition>,
+}
+
+impl PieceSet {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let set: PieceSet = serde_json::from_str(s).map_err(|e| e.to_string())?;
+        for (name, definition) in &set.pieces {
+            for component in &definition.moves {
+                component.validate().map_err(|e| format!("{}: {}", name, e))?;
+            }
+        }
+        if let Some(p) = set.promotion.iter().find(|p| !set.pieces.contains_key(*p)) {
+            return Err(format!("cannot promote to unknown piece {}", p));
+        }
+        Ok(set)
+    }
+
+    /// The standard pieces, read once and shared.
+    pub fn standard() -> Arc<Self> {
+        static STANDARD: OnceLock<Arc<PieceSet>> = OnceLock::new();
+        STANDARD.get_or_init(|| Arc::new(Self::parse(STANDARD_RULES).unwrap())).clone()
+    }
+
+    /// Whether pieces of type `kind` have the flag `flag` picks out of their definition.
+    pub fn is(&self, kind: &str, flag: fn(&PieceDefinition) -> bool) -> bool {
+        self.pieces.get(kind).is_some_and(flag)
+    }
+}
+
+#[test]
+fn test_piece_set() {
+    let set = PieceSet::parse(include_str!("../rules/standard.json")).unwrap();
+    assert_eq!(set.pieces.len(), 6);
+    let knight = &set.pieces["knight"].moves[0];
+    let leaps = knight.leaps(1);
+    assert_eq!(leaps.len(), 8);
+    assert!(leaps.contains(&((1, 2), (0, 8))));
+    assert!(leaps.contains(&((-2, 1), (-8, 0))));
+    let pawn = &set.pieces["pawn"].moves[0];
+    assert_eq!(pawn.rides(-1), vec![(-1, 0)]);
+
+    assert!(PieceSet::parse("{\"pieces\": {\"x\": {\"moves\": [{\"leap\": [[0, 0]]}]}}}").is_err());
+    assert!(PieceSet::parse("{\"pieces\": {\"x\": {\"moves\": [{\"jump\": [[1, 0]]}]}}}").is_err());
+    assert!(PieceSet::parse("{\"promotion\": [\"y\"], \"pieces\": {\"x\": {}}}").is_err());
+}
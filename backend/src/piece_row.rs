@@ -0,0 +1,156 @@
+use std::{cell::RefCell, collections::HashSet};
+
+use crate::coord::Coord;
+use crate::piece::Color;
+#[cfg(test)]
+use crate::{
+    board::Board,
+    illegal_move::IllegalMove,
+    piece_rules::{assert_move_sets_match, play, GameStatus, PieceRules, StandardChess},
+};
+
+/// A rank filled forever in both directions with one side's pieces, repeating `pattern` every
+/// `period()` files: file `f` holds `pattern[f mod period]`. The infinite pawn ranks are rows
+/// whose pattern is just `["pawn"]`.
+#[derive(Clone)]
+pub struct PieceRow {
+    color: Color,
+    rank: Coord,
+    pattern: Vec<String>,
+    /// PieceRow tracks the movement of the infinite number of pieces from a rank
+    moved: RefCell<HashSet<Coord>>,
+}
+
+impl PieceRow {
+    pub fn new(color: Color, rank: Coord, pattern: Vec<String>) -> Self {
+        assert!(!pattern.is_empty(), "a row needs at least one piece to repeat");
+        Self {
+            color,
+            rank,
+            pattern,
+            moved: RefCell::new(HashSet::new()),
+        }
+    }
+    pub fn pawns(color: Color, rank: Coord) -> Self {
+        Self::new(color, rank, vec!["pawn".to_string()])
+    }
+    pub fn get_color(&self) -> Color {
+        self.color
+    }
+    pub fn get_rank(&self) -> &Coord {
+        &self.rank
+    }
+    pub fn get_pattern(&self) -> &[String] {
+        &self.pattern
+    }
+    pub fn period(&self) -> i64 {
+        self.pattern.len() as i64
+    }
+    /// Where `file` falls in the pattern, counting from file 0.
+    pub fn slot(&self, file: &Coord) -> usize {
+        file.rem_euclid(self.period()) as usize
+    }
+    /// The type of piece the row starts with on `file`, whether or not it's still there.
+    pub fn type_at(&self, file: &Coord) -> &String {
+        &self.pattern[self.slot(file)]
+    }
+    /// The type of piece the row still holds on `file`.
+    pub fn piece_at(&self, file: &Coord) -> Option<&String> {
+        if self.has_moved(file) {
+            None
+        } else {
+            Some(self.type_at(file))
+        }
+    }
+    pub fn has_moved(&self, file: &Coord) -> bool {
+        self.moved.borrow().contains(file)
+    }
+    pub fn set_moved(&self, file: &Coord) {
+        self.moved.borrow_mut().insert(file.clone());
+    }
+    /// Puts the piece on `file` back as if it had never been touched.
+    pub fn set_unmoved(&self, file: &Coord) {
+        self.moved.borrow_mut().remove(file);
+    }
+    /// The pieces the row still holds on the `count` files from `first` on, in order: the
+    /// pattern laid out from where `first` falls in it, less the files the row has lost.
+    pub fn pieces_from(&self, first: &Coord, count: i64) -> Vec<(Coord, &String)> {
+        let mut lost: Vec<i64> = self
+            .moved
+            .borrow()
+            .iter()
+            .filter_map(|f| (f - first).to_i64())
+            .filter(|k| (0..count).contains(k))
+            .collect();
+        lost.sort_unstable();
+        let slot = self.slot(first);
+        let mut lost = lost.into_iter().peekable();
+        let mut out = Vec::new();
+        for k in 0..count.max(0) {
+            if lost.next_if_eq(&k).is_none() {
+                out.push((first + k, &self.pattern[(slot + k as usize) % self.pattern.len()]));
+            }
+        }
+        out
+    }
+    pub fn files(&self) -> Vec<Coord> {
+        self.moved.borrow().iter().cloned().collect()
+    }
+}
+
+#[test]
+fn test_repeating_rows() {
+    use crate::board_serializer::{board_deserialize, board_serialize};
+    use crate::setup::{Setup, REPEATING_SETUP};
+    let pm = StandardChess::new();
+    let mut b = Board::from_setup(&Setup::parse(REPEATING_SETUP).unwrap());
+    let kind = |b: &Board, rank: i32, file: i32| b.implicit_piece(&rank.into(), &file.into()).map(|(c, k)| (c, k.clone()));
+    assert_eq!(kind(&b, 7, 8), Some((Color::White, "rook".to_string())));
+    assert_eq!(kind(&b, 0, -3), Some((Color::Black, "bishop".to_string())));
+    assert_eq!(kind(&b, 7, -4), Some((Color::White, "king".to_string())));
+    // The royal kings replace the row's kings on file 4.
+    assert_eq!(kind(&b, 7, 4), None);
+    assert_eq!(b.king(Color::White), b.get_piece_at(&7.into(), &4.into()));
+    assert_move_sets_match(&mut b, &pm, -10, 18);
+    assert_eq!(Board::game_status(&mut b, &pm), GameStatus::Ongoing);
+
+    // A knight from far along the row comes onto the board when it moves.
+    play(&mut b, &pm, &[(7, 1001, 5, 1002)]);
+    assert_eq!(kind(&b, 7, 1001), None);
+    assert_eq!(b.pieces.last().unwrap().get_type(), "knight");
+
+    // A bishop from the row gives check once the pawns in between are gone. Kings in the rows
+    // are ordinary pieces, so it is only check on the royal one.
+    let mut check = b.clone();
+    play(&mut check, &pm, &[(1, 20, 2, 20), (6, 11, 4, 11), (1, 6, 2, 6)]);
+    let why = |b: &mut Board, rank: i32, file: i32, to_rank: i32, to_file: i32| {
+        Board::move_legal(b, &pm, &rank.into(), &file.into(), &to_rank.into(), &to_file.into(), None).err()
+    };
+    assert_eq!(pm.attackers_of(&check, &7.into(), &12.into(), Color::Black), vec![(0.into(), 5.into())]);
+    assert_eq!(why(&mut check, 6, 0, 5, 0), None);
+    play(&mut check, &pm, &[(6, 3, 4, 3), (1, -2, 3, -2)]);
+    assert_eq!(why(&mut check, 6, 0, 5, 0), Some(IllegalMove::LeavesKingInCheck { rank: 0.into(), file: (-3).into() }));
+    assert!(Board::is_move_legal(&mut check, &pm, &7.into(), &2.into(), &6.into(), &3.into()));
+    assert_eq!(Board::game_status(&mut check, &pm), GameStatus::Ongoing);
+
+    // Castling takes the rook straight off the row, and taking it back puts it there again.
+    let mut castle = b.clone();
+    play(&mut castle, &pm, &[(1, 0, 2, 0), (6, 4, 4, 4), (2, 0, 3, 0), (7, 6, 5, 5), (3, 0, 4, 0), (7, 5, 4, 2), (4, 0, 5, 0)]);
+    play(&mut castle, &pm, &[(7, 4, 7, 6)]);
+    let rook = castle.get_piece_at(&7.into(), &5.into()).unwrap();
+    assert_eq!(castle.pieces[rook].get_type(), "rook");
+    assert_eq!(kind(&castle, 7, 7), None);
+    castle.undo_move();
+    assert_eq!(kind(&castle, 7, 7), Some((Color::White, "rook".to_string())));
+
+    // A king from the row that comes onto the board stays an ordinary piece after a reload.
+    play(&mut b, &pm, &[(1, 0, 2, 0), (6, 12, 4, 12), (2, 0, 3, 0), (7, 12, 6, 12)]);
+    let royal = b.king(Color::White);
+    let mut loaded = Board::new();
+    board_deserialize(&mut loaded, &board_serialize(&b, GameStatus::Ongoing)).unwrap();
+    assert_eq!(loaded.king(Color::White), royal);
+    assert_eq!(kind(&loaded, 7, 12), None);
+    assert_eq!(kind(&loaded, 7, 20), Some((Color::White, "king".to_string())));
+    assert_eq!(kind(&loaded, 0, 1001), Some((Color::Black, "knight".to_string())));
+    assert_eq!(loaded.rows.len(), 4);
+}
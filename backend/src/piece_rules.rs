@@ -1,214 +1,590 @@
-use std::collections::HashMap;
+use std::{borrow::Cow, sync::Arc};
 
-use num_bigint::BigInt;
-use num_traits::Signed;
+use crate::coord::Coord;
 
 #[cfg(test)]
-use crate::piece::Piece;
+use crate::board::{MAX_RECT_FILES, WHITE_PAWN_RANK};
 use crate::{
     board::{Board, STANDARD_BOARD_SIZE},
-    piece::Color, moves::Move, piece_serializer,
+    illegal_move::IllegalMove,
+    piece::{Color, Piece}, moves::Move,
+    move_set::{MoveSet, Ray, RayEnd},
+    piece_definition::{Component, Mode, PieceDefinition, PieceSet},
+    piece_row::PieceRow,
 };
 
-pub trait PieceRules {
-    fn can_move(&self, board: &mut Board, i: usize, to_rank: &BigInt, to_file: &BigInt) -> Option<Move>;
+/// How far around each interesting square on a line of check we look for a piece from the
+/// infinite rows to step in, before making room for the ones that are blocked. Two board
+/// widths leaves room for pieces that are pinned.
+const INTERPOSITION_WINDOW: i32 = 2 * STANDARD_BOARD_SIZE;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GameStatus {
+    Ongoing,
+    Checkmate,
+    Stalemate,
+    /// Drawn because the same position came up too often.
+    Repetition,
+    /// Drawn because nobody captured anything or moved a pawn for too long.
+    FiftyMoves,
+}
+
+impl std::fmt::Display for GameStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            GameStatus::Ongoing => "ongoing",
+            GameStatus::Checkmate => "checkmate",
+            GameStatus::Stalemate => "stalemate",
+            GameStatus::Repetition => "repetition",
+            GameStatus::FiftyMoves => "fifty moves",
+        })
+    }
+}
+
+/// How often a position has to come up before either side may claim a draw, and before the
+/// game is drawn whether anyone claims it or not.
+const CLAIM_REPETITIONS: usize = 3;
+const AUTOMATIC_REPETITIONS: usize = 5;
+/// The same for the number of moves by either side without a capture or a pawn move.
+const CLAIM_HALFMOVES: usize = 100;
+const AUTOMATIC_HALFMOVES: usize = 150;
+
+pub(crate) fn pawn_direction(color: Color) -> i32 {
+    if color == Color::Black {
+        1
+    } else {
+        -1
+    }
+}
+
+/// The k with `x == k * step`, if there is one. `step` must not be 0.
+fn steps(x: &Coord, step: i32) -> Option<Coord> {
+    let k = x / step;
+    if &k * step == *x {
+        Some(k)
+    } else {
+        None
+    }
+}
+
+/// Every t in `center - radius ..= center + radius` that also lies in `1 ..= n`.
+fn window(center: &Coord, radius: i32, n: &Coord) -> Vec<Coord> {
+    let one: Coord = 1.into();
+    let mut t = (center - radius).max(one);
+    let end = (center + radius).min(n.clone());
+    let mut out = Vec::new();
+    while t <= end {
+        out.push(t.clone());
+        t += 1;
+    }
+    out
+}
+
+/// Everything `Board` needs to decide what is legal and how a game ends. Variants implement
+/// this beside `StandardChess` and are picked when a board is set up.
+pub trait PieceRules: Send + Sync {
+    /// The piece types played with, which a board for these rules is set up with.
+    fn piece_set(&self) -> Arc<PieceSet>;
+    /// The move that takes the piece on (rank, file) to the square, whoever's turn it is and
+    /// whatever it does to its king.
+    fn can_move(&self, board: &Board, rank: &Coord, file: &Coord, to_rank: &Coord, to_file: &Coord) -> Result<Move, IllegalMove>;
+    /// Whether moving `piece` to `to_rank` needs a promotion.
+    fn promotes(&self, board: &Board, piece: &Piece, to_rank: &Coord) -> bool;
+    /// The move with the chosen promotion attached, if that choice is allowed.
+    fn add_promotion(&self, board: &Board, m: Move, promotion: Option<String>) -> Result<Move, IllegalMove>;
+    /// The move, unless it leaves its own king in check or is otherwise ruled out by the
+    /// position, like castling through check.
+    /// Trying the move out may change the board, but it is left exactly as it was.
+    fn would_be_in_check(&self, board: &mut Board, m: Move) -> Result<Move, IllegalMove>;
+    /// Every legal move of the piece on (rank, file), described finitely even where there are
+    /// infinitely many.
+    fn move_set(&self, board: &mut Board, rank: &Coord, file: &Coord) -> MoveSet;
+    /// Whether the side to move can carry on, or how the game ended.
+    fn game_status(&self, board: &mut Board) -> GameStatus;
+    /// The draw the side to move may claim right now, if any.
+    fn claimable_draw(&self, board: &Board) -> Option<GameStatus>;
+    /// The squares of every piece of color `by` that attacks the square, whether or not
+    /// anything stands there.
+    fn attackers_of(&self, board: &Board, rank: &Coord, file: &Coord, by: Color) -> Vec<(Coord, Coord)>;
+    fn is_attacked(&self, board: &Board, rank: &Coord, file: &Coord, by: Color) -> bool {
+        !self.attackers_of(board, rank, file, by).is_empty()
+    }
+    /// The squares of every piece of color `by` that could move to the square, whatever that
+    /// does to its king. Of the pieces on the infinite rows only the nearest that could are
+    /// included.
+    fn movers_to(&self, board: &Board, rank: &Coord, file: &Coord, by: Color) -> Vec<(Coord, Coord)>;
+}
+
+/// A ride's direction, its number of steps, and the first square in its way if any.
+type Slide = ((i32, i32), Coord, Option<(Coord, Coord)>);
+
+/// How a movement component gets a piece to a square.
+enum Reach {
+    Yes,
+    /// It would, but this square is in the way.
+    Blocked(Coord, Coord),
+    No,
 }
 
 #[derive(Clone)]
 pub struct StandardChess {
-    map: HashMap<String, fn(&mut Board, usize, &BigInt, &BigInt) -> Option<Move>>,
+    set: Arc<PieceSet>,
 }
 
-fn maybe_capture(b: &mut Board, i: usize, to_rank: &BigInt, to_file: &BigInt) -> Move {
+fn maybe_capture(b: &Board, i: usize, to_rank: &Coord, to_file: &Coord) -> Move {
     let move_ = Move::new(i);
-    if let Some(x) = b.get_piece_at(to_rank, to_file) {
-        return move_.add_capture(x);
+    if let Some((x, _)) = b.piece_at(to_rank, to_file) {
+        return move_.add_capture(x, to_rank, to_file);
     }
     move_
 }
 
+/// The piece making the move, found where its first motion starts.
+fn mover<'a>(board: &'a Board, m: &Move) -> Option<Cow<'a, Piece>> {
+    let motion = m.get_motions().first()?;
+    board.piece_at(motion.get_from_rank(), motion.get_from_file()).map(|(_, p)| p)
+}
+
 impl StandardChess {
     pub fn new() -> Self {
-        let map = HashMap::new();
-        let mut me = Self { map };
-        me.add_piecetype("pawn".into(), is_pawn_move_legal);
-        me.add_piecetype("knight".into(), is_knight_move_legal);
-        me.add_piecetype("bishop".into(), is_bishop_move_legal);
-        me.add_piecetype("rook".into(), is_rook_move_legal);
-        me.add_piecetype("queen".into(), is_queen_move_legal);
-        me.add_piecetype("king".into(), is_king_move_legal);
-
-        me
+        Self { set: PieceSet::standard() }
     }
-    fn add_piecetype(&mut self, name: String, pt: fn(&mut Board, usize, &BigInt, &BigInt) -> Option<Move>) {
-        self.map.insert(name, pt);
+    /// Rules for the pieces described by a rules file, in the format of `rules/standard.json`.
+    pub fn from_rules(s: &str) -> Result<Self, String> {
+        Ok(Self { set: Arc::new(PieceSet::parse(s)?) })
     }
     #[cfg(test)]
-    fn build_piece(
+    #[allow(clippy::ptr_arg)]
+    pub(crate) fn build_piece(
         &self,
         piece: &String,
         color: Color,
-        rank: BigInt,
-        file: BigInt,
+        rank: Coord,
+        file: Coord,
     ) -> Option<Piece> {
         Some(Piece::new(piece.clone(), color, rank, file))
     }
 
-    fn is_in_check(&self, board: &mut Board, m: &Move) -> bool {
-        let c = board.pieces[m.get_piece()].get_color();
-        let king = if c == Color::White {board.white_king} else {board.black_king};
-        for p in 0..board.pieces.len() {
-            if board.pieces[p].get_color() != c && !board.pieces[p].is_captured() {
-                if Board::move_legal_at_all(board, &self, &board.pieces[p].get_rank().clone(), &board.pieces[p].get_file().clone(), &board.pieces[king].get_rank().clone(), &board.pieces[king].get_file().clone()).is_some() {
-                    return true;
+    fn definition(&self, piece: &Piece) -> Option<&PieceDefinition> {
+        self.set.pieces.get(piece.get_type())
+    }
+
+    /// The direction and number of steps with which `component` slides `piece` to
+    /// (to_rank, to_file), and the first square in the way if there's no clear path.
+    fn ride(&self, board: &Board, piece: &Piece, component: &Component, to_rank: &Coord, to_file: &Coord) -> Option<Slide> {
+        if component.initial && piece.has_moved() {
+            return None;
+        }
+        let rank = piece.get_rank();
+        let file = piece.get_file();
+        let (dr, df) = (to_rank - rank, to_file - file);
+        let mut blocked = None;
+        for (a, b) in component.rides(pawn_direction(piece.get_color())) {
+            let k = match if a != 0 { steps(&dr, a) } else { steps(&df, b) } {
+                Some(k) if k >= 1.into() && dr == &k * a && df == &k * b => k,
+                _ => continue,
+            };
+            if component.range.is_some_and(|r| k > r.into()) {
+                continue;
+            }
+            match board.get_collision(rank, file, a, b, &k) {
+                None => return Some(((a, b), k, None)),
+                Some(square) => {
+                    blocked = blocked.or(Some(((a, b), k, Some(square))));
                 }
             }
         }
-        false
+        blocked
     }
-    
-    pub(crate) fn would_be_in_check(&self,
-        board: &mut Board,
-        m: Move,
-    ) -> Option<Move> {
-        let mut b = board.clone();
-        b.do_move_ref(&m);
-        if self.is_in_check(&mut b, &m) {
-            None
-        } else {
-            Some(m)
+
+    /// Whether `component` takes `piece` to (to_rank, to_file), whatever stands there.
+    fn reach(&self, board: &Board, piece: &Piece, component: &Component, to_rank: &Coord, to_file: &Coord) -> Reach {
+        if component.initial && piece.has_moved() {
+            return Reach::No;
+        }
+        let dr = to_rank - piece.get_rank();
+        let df = to_file - piece.get_file();
+        let leaps = component.leaps(pawn_direction(piece.get_color()));
+        for ((a, b), (sa, sb)) in leaps {
+            let (x, y) = (&dr - a, &df - b);
+            let k = match (sa, sb) {
+                (0, 0) => Some(0.into()),
+                (0, sb) => steps(&y, sb),
+                (sa, _) => steps(&x, sa),
+            };
+            if k.is_some_and(|k| !k.is_negative() && x == &k * sa && y == &k * sb) {
+                return Reach::Yes;
+            }
+        }
+        match self.ride(board, piece, component, to_rank, to_file) {
+            Some((_, _, None)) => Reach::Yes,
+            Some((_, _, Some((rank, file)))) => Reach::Blocked(rank, file),
+            None => Reach::No,
         }
     }
-}
-fn is_pawn_move_legal(board: &mut Board, i: usize, to_rank: &BigInt, to_file: &BigInt) -> Option<Move> {
-    let dir = if board.pieces[i].get_color() == Color::Black {
-        1
-    } else {
-        -1
-    };
-    if board.pieces[i].get_file() == to_file && to_rank == &(board.pieces[i].get_rank() + dir) {
-        //single move
-        if let None = board.get_piece_at(to_rank, to_file) {
-            return Some(Move::standard(i, to_rank, to_file, board.pieces[i].get_rank(), board.pieces[i].get_file()));
-        }
-    }
-    if board.pieces[i].get_file() == to_file
-        && to_rank == &(board.pieces[i].get_rank() + dir * 2)
-        && !board.pieces[i].has_moved()
-    {
-        //double move at start
-        if let None = board.get_piece_at(to_rank, to_file) {
-            if let None = board.get_piece_at(&(board.pieces[i].get_rank() + dir), to_file) {
-                return Some(Move::standard(i, to_rank, to_file, board.pieces[i].get_rank(), board.pieces[i].get_file()));
-            }
-        }
-    }
-    if (board.pieces[i].get_file() - to_file).abs() == 1.into()
-        && to_rank == &(board.pieces[i].get_rank() + dir)
-    {
-        //take diagonally
-        if let Some(x) = board.get_piece_at(to_rank, to_file) {
-            return Some(Move::capture(i, to_rank, to_file, board.pieces[i].get_rank(), board.pieces[i].get_file(), x));
-        }
-        let rank = board.pieces[i].get_rank().clone(); //bad
-                                                       //en passant!
-        if let Some(p) = board.get_piece_at(&rank, to_file) {
-            if board.pieces[p].get_type() == "pawn"
-                && board.pieces[p].get_color() != board.pieces[i].get_color()
-                && board.last_move().map_or(false, |x| x == p) {
-                    return Some(Move::capture(i, to_rank, to_file, board.pieces[i].get_rank(), board.pieces[i].get_file(), p))
+
+    fn reaches(&self, board: &Board, piece: &Piece, component: &Component, to_rank: &Coord, to_file: &Coord) -> bool {
+        matches!(self.reach(board, piece, component, to_rank, to_file), Reach::Yes)
+    }
+
+    /// Whether `piece` could capture on (rank, file) if something of the other side stood there.
+    fn attacks(&self, board: &Board, piece: &Piece, rank: &Coord, file: &Coord) -> bool {
+        let definition = match self.definition(piece) {
+            Some(definition) => definition,
+            None => return false,
+        };
+        definition
+            .moves
+            .iter()
+            .any(|component| component.mode.allows(true) && self.reaches(board, piece, component, rank, file))
+    }
+
+    /// The piece types repeating in `row` that these rules know how to move, each once.
+    fn row_types<'a>(&'a self, row: &'a PieceRow) -> Vec<(&'a String, &'a PieceDefinition)> {
+        let mut out: Vec<(&String, &PieceDefinition)> = Vec::new();
+        for kind in row.get_pattern() {
+            if let Some(definition) = self.set.pieces.get(kind) {
+                if !out.iter().any(|(k, _)| *k == kind) {
+                    out.push((kind, definition));
                 }
+            }
         }
+        out
     }
-    None
-}
-fn is_knight_move_legal(board: &mut Board, i: usize, to_rank: &BigInt, to_file: &BigInt) -> Option<Move> {
-    let p = board.pieces.get(i).unwrap();
-    let df = p.get_file() - to_file;
-    let dr = p.get_rank() - to_rank;
-    let mut move_ = maybe_capture(board, i, to_rank, to_file);
-    if dr.abs() == 1.into() {
-        if (df.abs() - 2) % STANDARD_BOARD_SIZE == 0.into() {
-            return Some(move_.add_motion(i, to_rank, to_file, board.pieces[i].get_rank(), board.pieces[i].get_file()));
-        }
-    } else if df.abs() == 1.into() {
-        if (dr.abs() - 2) % STANDARD_BOARD_SIZE == 0.into() {
-            return Some(move_.add_motion(i, to_rank, to_file, board.pieces[i].get_rank(), board.pieces[i].get_file()));
-        }
-    }
-    None 
-}  
-fn is_rook_move_legal(board: &mut Board, i: usize, to_rank: &BigInt, to_file: &BigInt) -> Option<Move> {
-    if board.pieces[i].get_file() != to_file && to_rank != board.pieces[i].get_rank() {
-        return None;
-    }
-    let mut move_ = maybe_capture(board, i, to_rank, to_file);
-    if board.get_collision(
-        &board.pieces[i].get_rank().clone(),
-        &board.pieces[i].get_file().clone(),
-        to_rank,
-        to_file,
-    ) {
-        Some(move_.add_motion(i, to_rank, to_file, board.pieces[i].get_rank(), board.pieces[i].get_file()))
-    } else {
-        None
+
+    /// The squares of the pieces still on `color`'s infinite rows that might move to
+    /// (rank, file), or capture there if `captures_only` is set.
+    fn row_pieces_reaching(&self, board: &Board, color: Color, rank: &Coord, file: &Coord, captures_only: bool) -> Vec<(Coord, Coord)> {
+        let forward = pawn_direction(color);
+        let mut out = Vec::new();
+        for row in board.rows_of(color) {
+            let x = rank - row.get_rank();
+            let mut files = Vec::new();
+            for (kind, definition) in self.row_types(row) {
+                for component in definition.moves.iter().filter(|m| m.mode.allows(true) || !captures_only) {
+                    for ((a, b), (sa, sb)) in component.leaps(forward) {
+                        if sa != 0 {
+                            if let Some(k) = steps(&(&x - a), sa).filter(|k| !k.is_negative()) {
+                                files.push((file - b - k * sb, kind));
+                            }
+                        } else if x == a.into() {
+                            // A leap stretched along the row reaches from anywhere; the nearest
+                            // piece of the kind will do.
+                            let tries = if sb == 0 { 1 } else { row.period() * (row.files().len() as i64 + 1) };
+                            let nearest = (0..tries)
+                                .map(|k| file - b - Coord::from(k) * sb)
+                                .find(|f| row.piece_at(f) == Some(kind));
+                            files.extend(nearest.map(|f| (f, kind)));
+                        }
+                    }
+                    for (a, b) in component.rides(forward) {
+                        let k = if a != 0 {
+                            steps(&x, a)
+                        } else if x.is_zero() {
+                            // Along the row only the nearest piece can get there.
+                            board.first_blocker(rank, file, 0, -b).map(|(k, _)| k)
+                        } else {
+                            None
+                        };
+                        if let Some(k) = k.filter(|k| k >= &1.into() && component.range.map_or(true, |r| k <= &r.into())) {
+                            files.push((file - k * b, kind));
+                        }
+                    }
+                }
+            }
+            for (f, kind) in files {
+                let square = (row.get_rank().clone(), f);
+                if row.piece_at(&square.1) == Some(kind) && !out.contains(&square) {
+                    out.push(square);
+                }
+            }
+        }
+        out
     }
-}
-fn is_bishop_move_legal(board: &mut Board, i: usize, to_rank: &BigInt, to_file: &BigInt) -> Option<Move> {
-    if (board.pieces[i].get_rank() - to_rank).abs() != (board.pieces[i].get_file() - to_file).abs()
-    {
-        return None;
+
+    /// The ranks a piece from `row` can get to in one move. Rides that can go on forever are
+    /// only followed for a board width.
+    fn row_ranks(&self, row: &PieceRow) -> Vec<Coord> {
+        let mut out = Vec::new();
+        let forward = pawn_direction(row.get_color());
+        let row_rank = row.get_rank();
+        for (_, definition) in self.row_types(row) {
+            for component in &definition.moves {
+                for ((a, _), (sa, _)) in component.leaps(forward) {
+                    out.push(row_rank + a);
+                    out.push(row_rank + a + sa);
+                }
+                for (a, _) in component.rides(forward) {
+                    for k in 1..=component.range.map_or(STANDARD_BOARD_SIZE, |r| r as i32) {
+                        out.push(row_rank + k * a);
+                    }
+                }
+            }
+        }
+        out.sort();
+        out.dedup();
+        out
     }
-    let mut move_ = maybe_capture(board, i, to_rank, to_file);
-    if board.get_collision(
-        &board.pieces[i].get_rank().clone(),
-        &board.pieces[i].get_file().clone(),
-        to_rank,
-        to_file,
-    ) {
-        Some(move_.add_motion(i, to_rank, to_file, board.pieces[i].get_rank(), board.pieces[i].get_file()))
-    } else {
-        None
+
+    /// How many squares along a line it takes for the ways `row`'s pieces can step onto it to
+    /// repeat: the period of the row, times the longest stride its pieces make across ranks.
+    fn row_stride(&self, row: &PieceRow) -> i32 {
+        let forward = pawn_direction(row.get_color());
+        let mut stride = 1;
+        for (_, definition) in self.row_types(row) {
+            for component in &definition.moves {
+                for (_, (sa, _)) in component.leaps(forward) {
+                    stride = stride.max(sa.abs());
+                }
+                for (a, _) in component.rides(forward) {
+                    stride = stride.max(a.abs());
+                }
+            }
+        }
+        stride * row.period() as i32
     }
-}
-fn is_queen_move_legal(board: &mut Board, i: usize, to_rank: &BigInt, to_file: &BigInt) -> Option<Move> {
-    is_bishop_move_legal(board, i, to_rank, to_file).or(is_rook_move_legal(board, i, to_rank, to_file))
-}
-fn is_king_move_legal(board: &mut Board, i: usize, to_rank: &BigInt, to_file: &BigInt) -> Option<Move> {
-    let qm = is_queen_move_legal(board, i, to_rank, to_file);
-    if qm.is_some()
-        && (board.pieces[i].get_rank() - to_rank).abs() <= 1.into()
-        && (board.pieces[i].get_file() - to_file).abs() <= 1.into()
-    {
-        return qm;
+
+    /// The squares of the pieces giving check to `c`'s king.
+    fn checkers(&self, board: &Board, c: Color) -> Vec<(Coord, Coord)> {
+        match board.king(c) {
+            Some(king) => {
+                let king = &board.pieces[king];
+                self.attackers_of(board, king.get_rank(), king.get_file(), c.opposite())
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// The squares a move has to land on to answer a check from the piece on `checker`, as the
+    /// squares `from + t * (dr, df)` for `1 <= t <= n`: the checker itself, and if it slid
+    /// there, everything between it and the king.
+    fn check_line(&self, board: &Board, king: usize, checker: &(Coord, Coord)) -> (Coord, Coord, i32, i32, Coord) {
+        let kr = board.pieces[king].get_rank().clone();
+        let kf = board.pieces[king].get_file().clone();
+        if let Some((_, piece)) = board.piece_at(&checker.0, &checker.1) {
+            if let Some(definition) = self.definition(&piece) {
+                for component in definition.moves.iter().filter(|m| m.mode.allows(true)) {
+                    if let Some(((a, b), k, None)) = self.ride(board, &piece, component, &kr, &kf) {
+                        return (kr, kf, -a, -b, k);
+                    }
+                }
+            }
+        }
+        (&checker.0 - 1, checker.1.clone(), 1, 0, 1.into())
     }
-    // Castling
-    if board.pieces[i].has_moved() {
-        return None;
+
+    /// Whether `piece` is its side's royal king, the only piece that castles.
+    fn is_royal(board: &Board, piece: &Piece) -> bool {
+        board.king(piece.get_color()).is_some_and(|k| {
+            let king = &board.pieces[k];
+            king.get_rank() == piece.get_rank() && king.get_file() == piece.get_file()
+        })
     }
-    if to_rank != board.pieces[i].get_rank() {
-        return None;
+
+    fn own_pieces(board: &Board, c: Color) -> Vec<usize> {
+        (0..board.pieces.len())
+            .filter(|&p| board.pieces[p].get_color() == c && !board.pieces[p].is_captured())
+            .collect()
     }
-    if to_file == &6.into() {
-        if let Some(z) = board.get_piece_at(to_rank, &7.into()) {
-            if !board.pieces[z].has_moved()
-                && board.get_piece_at(to_rank, &6.into()).is_none()
-                && board.get_piece_at(to_rank, &5.into()).is_none() {
-                    return Some(Move::standard(i, to_rank, to_file, board.pieces[i].get_rank(), board.pieces[i].get_file()).add_motion(z, to_rank, &5.into(), board.pieces[z].get_rank(), board.pieces[z].get_file()))
+
+    /// Squares on the line of check where a piece from the infinite rows might step in: near
+    /// both ends and where the line crosses the ranks those pieces can reach. What the rows
+    /// can do along the line repeats every `row_stride` squares, and each piece or gap on the
+    /// board spoils at most one of those chances, so the windows are wide enough to get past
+    /// all of them.
+    fn row_interpositions(&self, board: &Board, c: Color, checker: &(Coord, Coord)) -> Vec<(Coord, Coord)> {
+        let king = board.king(c).unwrap();
+        let (from_rank, from_file, dr, df, n) = self.check_line(board, king, checker);
+        let stride = match board.rows_of(c).map(|row| self.row_stride(row)).max() {
+            Some(stride) => stride,
+            None => return Vec::new(),
+        };
+        let gaps: usize = board.rows.iter().map(|row| row.files().len()).sum();
+        let blockers = (board.pieces.iter().filter(|p| !p.is_captured()).count() + gaps) as i32;
+        let slack = stride * (INTERPOSITION_WINDOW + blockers);
+        let mut ts = window(&1.into(), slack, &n);
+        ts.extend(window(&n, slack, &n));
+        if dr != 0 {
+            for row in board.rows_of(c) {
+                for rank in self.row_ranks(row) {
+                    if let Some(t) = steps(&(rank - &from_rank), dr) {
+                        ts.extend(window(&t, slack, &n));
+                    }
                 }
+            }
         }
+        ts.sort();
+        ts.dedup();
+        ts.iter().map(|t| (&from_rank + t * dr, &from_file + t * df)).collect()
     }
-    if to_file == &2.into() {
-        if let Some(z) = board.get_piece_at(to_rank, &0.into()) {
-            if !board.pieces[z].has_moved()
-                && board.get_piece_at(to_rank, &1.into()).is_none()
-                && board.get_piece_at(to_rank, &2.into()).is_none()
-                && board.get_piece_at(to_rank, &3.into()).is_none() {
-                    return Some(Move::standard(i, to_rank, to_file, board.pieces[i].get_rank(), board.pieces[i].get_file()).add_motion(z, to_rank, &3.into(), board.pieces[z].get_rank(), board.pieces[z].get_file()))
+
+    /// Where `piece` could move if its king's safety didn't matter. Rides get a ray per
+    /// direction up to the first blocker, stretchable leaps a ray per family, and everything
+    /// else the single squares it might reach.
+    fn pseudo_move_set(&self, board: &Board, piece: &Piece) -> MoveSet {
+        let mut out = MoveSet::new();
+        let definition = match self.definition(piece) {
+            Some(definition) => definition,
+            None => return out,
+        };
+        let c = piece.get_color();
+        let rank = piece.get_rank();
+        let file = piece.get_file();
+        for component in definition.moves.iter().filter(|m| !m.initial || !piece.has_moved()) {
+            for ((a, b), step) in component.leaps(pawn_direction(c)) {
+                if step == (0, 0) {
+                    out.add_target(rank + a, file + b);
+                } else {
+                    self.add_leaps(&mut out, board, c, component.mode, (rank + a, file + b), step);
+                }
+            }
+            let range = component.range.map(Coord::from);
+            let in_range = |k: &Coord| range.as_ref().map_or(true, |r| k <= r);
+            for (dr, df) in component.rides(pawn_direction(c)) {
+                let blocker = board.first_blocker(rank, file, dr, df);
+                if component.mode == Mode::Capture {
+                    if let Some((k, _)) = blocker.filter(|(k, color)| *color != c && in_range(k)) {
+                        out.add_target(rank + &k * dr, file + &k * df);
+                    }
+                    continue;
+                }
+                let end = match (blocker, &range) {
+                    (Some((k, color)), _) if in_range(&k) => RayEnd::Blocked {
+                        rank: rank + &k * dr,
+                        file: file + &k * df,
+                        capture: color != c && component.mode == Mode::Both,
+                    },
+                    (_, Some(r)) => RayEnd::Blocked {
+                        rank: rank + (r + 1) * dr,
+                        file: file + (r + 1) * df,
+                        capture: false,
+                    },
+                    (_, None) => RayEnd::Unbounded,
+                };
+                let ray = Ray::new(rank + dr, file + df, dr.into(), df.into(), end);
+                if ray.first().is_some() {
+                    out.add_ray(ray);
+                }
+            }
+        }
+        if definition.castles && Self::is_royal(board, piece) {
+            for castle in [-2, 2] {
+                out.add_target(rank.clone(), file + castle);
+            }
+        }
+        out
+    }
+
+    /// A stretchable leap's squares `origin + k * step`, jumping over whatever is in between,
+    /// keeping those that `mode` allows and leaving out squares held by its own side.
+    fn add_leaps(&self, out: &mut MoveSet, board: &Board, c: Color, mode: Mode, origin: (Coord, Coord), step: (i32, i32)) {
+        let ray = Ray::new(origin.0, origin.1, step.0.into(), step.1.into(), RayEnd::Unbounded);
+        // Squares on the ray are empty unless listed here, or all belong to one side if the
+        // ray runs along one of its infinite rows.
+        let mut everywhere = None;
+        let mut listed: Vec<(Coord, Coord, Option<Color>)> = Vec::new();
+        for p in board.pieces.iter().filter(|p| !p.is_captured()) {
+            if ray.contains(p.get_rank(), p.get_file()) {
+                listed.push((p.get_rank().clone(), p.get_file().clone(), Some(p.get_color())));
+            }
+        }
+        let is_listed = |listed: &Vec<(Coord, Coord, Option<Color>)>, rank: &Coord, file: &Coord| {
+            listed.iter().any(|(r, f, _)| r == rank && f == file)
+        };
+        for row in &board.rows {
+            let row_rank = row.get_rank().clone();
+            let (origin_rank, origin_file) = ray.square(&0.into());
+            if step.0 != 0 {
+                // Crossing a row, the one square there might hold one of its pieces.
+                if let Some(k) = steps(&(&row_rank - &origin_rank), step.0).filter(|k| !k.is_negative()) {
+                    let file = origin_file + k * step.1;
+                    if !row.has_moved(&file) && !is_listed(&listed, &row_rank, &file) {
+                        listed.push((row_rank, file, Some(row.get_color())));
+                    }
+                }
+            } else if origin_rank == row_rank {
+                // Running along a row, only the gaps its pieces left are empty.
+                everywhere = Some(row.get_color());
+                for f in row.files() {
+                    if ray.contains(&row_rank, &f) && !is_listed(&listed, &row_rank, &f) {
+                        listed.push((row_rank.clone(), f, None));
+                    }
+                }
+            }
+        }
+        let allowed = |occupant: Option<Color>| occupant != Some(c) && mode.allows(occupant.is_some());
+        if allowed(everywhere) {
+            let mut ray = ray;
+            for (rank, file, occupant) in listed {
+                if !allowed(occupant) {
+                    ray = ray.add_skip(rank, file);
                 }
+            }
+            out.add_ray(ray);
+        } else {
+            for (rank, file, occupant) in listed {
+                if allowed(occupant) {
+                    out.add_target(rank, file);
+                }
+            }
+        }
+    }
+
+    fn is_castling(&self, board: &Board, m: &Move) -> bool {
+        mover(board, m).is_some_and(|p| self.definition(&p).is_some_and(|d| d.castles))
+            && m.get_motions().first().is_some_and(|motion| {
+                (motion.get_file() - motion.get_from_file()).abs() == 2.into()
+            })
+    }
+
+    /// Capturing a piece that just moved past the square, where both it and the capturing
+    /// piece take part in en passant.
+    fn en_passant(&self, board: &Board, i: usize, piece: &Piece, to_rank: &Coord, to_file: &Coord) -> Option<Move> {
+        let definition = self.definition(piece)?;
+        let captures = definition
+            .moves
+            .iter()
+            .any(|component| component.mode.allows(true) && self.reaches(board, piece, component, to_rank, to_file));
+        if !captures {
+            return None;
+        }
+        let c = piece.get_color();
+        let passed = to_rank - pawn_direction(c);
+        let p = board.get_piece_at(&passed, to_file)?;
+        if board.pieces[p].get_color() != c
+            && self.definition(&board.pieces[p]).is_some_and(|d| d.en_passant)
+            && board.last_move() == Some(p) {
+            return Some(Move::capture(i, to_rank, to_file, piece.get_rank(), piece.get_file(), p, &passed));
+        }
+        None
+    }
+    /// Castling, if that's what moving the king to (to_rank, to_file) would be: two files along
+    /// its rank towards its nearest castling partner, like the rook, which jumps to the file
+    /// the king crosses. Nothing may stand between them.
+    fn castle(&self, board: &Board, i: usize, king: &Piece, to_rank: &Coord, to_file: &Coord) -> Option<Result<Move, IllegalMove>> {
+        if to_rank != king.get_rank() {
+            return None;
         }
+        let df = match (to_file - king.get_file()).to_i64() {
+            Some(2) => 1,
+            Some(-2) => -1,
+            _ => return None,
+        };
+        let (k, z, rook) = board.castling_partner(king.get_color(), to_rank, king.get_file(), df)?;
+        if king.has_moved() || rook.has_moved() {
+            return Some(Err(IllegalMove::CastlingRightsLost));
+        }
+        // A partner right beside the king is in its way too.
+        let blocker = match board.first_blocker(to_rank, king.get_file(), 0, df) {
+            Some((b, _)) if b < k || k < 3.into() => Some(b),
+            _ => None,
+        };
+        if let Some(b) = blocker {
+            return Some(Err(IllegalMove::Blocked { rank: to_rank.clone(), file: king.get_file() + &b * df }));
+        }
+        Some(Ok(Move::standard(i, to_rank, to_file, king.get_rank(), king.get_file())
+            .add_motion(z, to_rank, &(king.get_file() + df), rook.get_rank(), rook.get_file())))
     }
-    None
 }
 #[test]
 fn test_pawns() {
@@ -334,6 +710,51 @@ fn test_rooks() {
         &0.into()
     ));
 }
+#[cfg(test)]
+fn castling_board(pm: &StandardChess, attacker: Option<(&str, i32, i32)>) -> Board {
+    // Castle on rank 10 so the infinite pawn ranks stay out of the way.
+    let mut b = Board::new();
+    b.place_piece(pm.build_piece(&"king".into(), Color::White, 10.into(), 4.into()).unwrap());
+    b.place_piece(pm.build_piece(&"rook".into(), Color::White, 10.into(), 0.into()).unwrap());
+    b.place_piece(pm.build_piece(&"rook".into(), Color::White, 10.into(), 7.into()).unwrap());
+    if let Some((piece, rank, file)) = attacker {
+        b.place_piece(pm.build_piece(&piece.into(), Color::Black, rank.into(), file.into()).unwrap());
+    }
+    b
+}
+
+#[test]
+fn test_castling() {
+    let pm = StandardChess::new();
+    let can_castle = |attacker, file: i32| {
+        let mut b = castling_board(&pm, attacker);
+        Board::is_move_legal(&mut b, &pm, &10.into(), &4.into(), &10.into(), &file.into())
+    };
+    assert!(can_castle(None, 6));
+    assert!(can_castle(None, 2));
+    // out of check
+    assert!(!can_castle(Some(("knight", 12, 5)), 6));
+    assert!(!can_castle(Some(("knight", 12, 5)), 2));
+    // through an attacked square
+    assert!(!can_castle(Some(("rook", 12, 5)), 6));
+    assert!(!can_castle(Some(("rook", 12, 3)), 2));
+    // into check
+    assert!(!can_castle(Some(("rook", 12, 6)), 6));
+    assert!(!can_castle(Some(("rook", 12, 2)), 2));
+    // the rook may be attacked, and so may the square next to it on the queen side
+    assert!(can_castle(Some(("rook", 12, 7)), 6));
+    assert!(can_castle(Some(("rook", 12, 1)), 2));
+    // pawns only attack diagonally
+    assert!(!can_castle(Some(("pawn", 9, 4)), 6));
+    assert!(can_castle(Some(("pawn", 9, 6)), 2));
+
+    // only with a castling partner, however unmoved the piece in the corner is
+    let mut b = Board::new();
+    b.place_piece(pm.build_piece(&"king".into(), Color::White, 10.into(), 4.into()).unwrap());
+    b.place_piece(pm.build_piece(&"queen".into(), Color::White, 10.into(), 7.into()).unwrap());
+    assert!(!Board::is_move_legal(&mut b, &pm, &10.into(), &4.into(), &10.into(), &6.into()));
+}
+
 #[test]
 fn test_bishops() {
     let mut b = Board::new();
@@ -385,18 +806,730 @@ fn test_en_passant() {
         pm.build_piece(&"bishop".into(), Color::White, 0.into(), 2.into())
             .unwrap(),
     );
-    let m = Board::move_legal(&mut b, &pm, &6.into(), &3.into(), &4.into(), &3.into());
+    let m = Board::move_legal(&mut b, &pm, &6.into(), &3.into(), &4.into(), &3.into(), None);
     b.do_move(m.unwrap());
     b.place_piece(
         pm.build_piece(&"pawn".into(), Color::Black, 4.into(), 4.into())
             .unwrap(),
     );
-    assert!(is_pawn_move_legal(&mut b, 2, &5.into(), &3.into()).is_some())
+    assert!(pm.can_move(&b, &4.into(), &4.into(), &5.into(), &3.into()).is_ok())
+}
+
+#[test]
+fn test_promotion() {
+    let pm = StandardChess::new();
+    let mut b = Board::new();
+    // The black pawn that would be in the way has already left.
+    b.row_at(&1.into()).unwrap().set_moved(&3.into());
+    b.place_piece(pm.build_piece(&"pawn".into(), Color::White, 1.into(), 3.into()).unwrap());
+    let promote = |b: &mut Board, to_file: i32, p: Option<&str>| {
+        Board::move_legal(b, &pm, &1.into(), &3.into(), &0.into(), &to_file.into(), p.map(|p| p.to_string()))
+    };
+
+    assert!(Board::is_move_legal(&mut b, &pm, &1.into(), &3.into(), &0.into(), &3.into()));
+    assert_eq!(promote(&mut b, 3, None).err(), Some(IllegalMove::PromotionRequired));
+    assert_eq!(promote(&mut b, 3, Some("king")).err(), Some(IllegalMove::BadPromotion("king".into())));
+    assert_eq!(promote(&mut b, 3, Some("pawn")).err(), Some(IllegalMove::BadPromotion("pawn".into())));
+    let m = promote(&mut b, 3, Some("knight")).unwrap();
+    b.do_move(m);
+    assert_eq!(b.pieces[0].get_type(), "knight");
+    assert_eq!(b.pieces[0].get_rank(), &0.into());
+    b.undo_move();
+    assert_eq!(b.pieces[0].get_type(), "pawn");
+    assert_eq!(b.pieces[0].get_rank(), &1.into());
+
+    // Only a promoting move may choose a piece.
+    b.place_piece(pm.build_piece(&"pawn".into(), Color::White, 3.into(), 5.into()).unwrap());
+    assert!(Board::move_legal(&mut b, &pm, &3.into(), &5.into(), &2.into(), &5.into(), Some("queen".into())).is_err());
+    assert!(Board::move_legal(&mut b, &pm, &3.into(), &5.into(), &2.into(), &5.into(), None).is_ok());
+
+    // Pawns promote on the opponent's back rank, wherever the setup puts it.
+    let setup = crate::setup::Setup::parse(r#"{
+        "pawns": [{ "color": "black", "rank": -1 }, { "color": "white", "rank": 8 }],
+        "pieces": [
+            { "piece": "king", "color": "black", "rank": -2, "file": 4 },
+            { "piece": "king", "color": "white", "rank": 9, "file": 4 }
+        ]
+    }"#).unwrap();
+    let mut b = Board::from_setup(&setup);
+    assert_eq!((b.promotion_rank(Color::White), b.promotion_rank(Color::Black)), (&(-2).into(), &9.into()));
+    b.place_piece(pm.build_piece(&"pawn".into(), Color::White, 1.into(), 20.into()).unwrap());
+    b.place_piece(pm.build_piece(&"pawn".into(), Color::White, (-1).into(), 30.into()).unwrap());
+    assert!(Board::move_legal(&mut b, &pm, &1.into(), &20.into(), &0.into(), &20.into(), None).is_ok());
+    assert_eq!(
+        Board::move_legal(&mut b, &pm, &(-1).into(), &30.into(), &(-2).into(), &30.into(), None).err(),
+        Some(IllegalMove::PromotionRequired)
+    );
+}
+
+/// Checks that every piece's move set agrees with asking about each square in a window, for
+/// the pieces on the board and the ones on the infinite rows inside the window.
+#[cfg(test)]
+pub(crate) fn assert_move_sets_match(b: &mut Board, pm: &StandardChess, lo: i32, hi: i32) {
+    let mut squares: Vec<(Coord, Coord)> = b
+        .pieces
+        .iter()
+        .filter(|p| !p.is_captured())
+        .map(|p| (p.get_rank().clone(), p.get_file().clone()))
+        .collect();
+    for file in lo..hi {
+        for row in &b.rows {
+            if row.piece_at(&file.into()).is_some() {
+                squares.push((row.get_rank().clone(), file.into()));
+            }
+        }
+    }
+    for (rank, file) in squares {
+        let set = pm.move_set(b, &rank, &file);
+        let found = set.squares_in(&lo.into(), &lo.into(), &hi.into(), &hi.into()).unwrap();
+        let mut expected = Vec::new();
+        for to_rank in lo..hi {
+            for to_file in lo..hi {
+                if Board::is_move_legal(b, pm, &rank, &file, &to_rank.into(), &to_file.into()) {
+                    expected.push((to_rank.into(), to_file.into()));
+                }
+            }
+        }
+        let (_, piece) = b.piece_at(&rank, &file).unwrap();
+        assert_eq!(found, expected, "{} at {}, {}", piece.get_type(), rank, file);
+    }
+}
+
+#[test]
+fn test_move_sets() {
+    let pm = StandardChess::new();
+    let mut b = standard_board();
+    assert_move_sets_match(&mut b, &pm, -12, 20);
+    play(&mut b, &pm, &[(6, 4, 4, 4), (1, 3, 3, 3), (7, 5, 3, 1)]);
+    assert_move_sets_match(&mut b, &pm, -12, 20);
+    play(&mut b, &pm, &[(1, 2, 2, 2)]);
+    assert_move_sets_match(&mut b, &pm, -12, 20);
+
+    // pinned pieces
+    let mut b = Board::new();
+    b.place_piece(pm.build_piece(&"king".into(), Color::White, 10.into(), 4.into()).unwrap());
+    b.place_piece(pm.build_piece(&"rook".into(), Color::White, 10.into(), 6.into()).unwrap());
+    b.place_piece(pm.build_piece(&"rook".into(), Color::Black, 10.into(), 12.into()).unwrap());
+    b.place_piece(pm.build_piece(&"knight".into(), Color::White, 12.into(), 4.into()).unwrap());
+    b.place_piece(pm.build_piece(&"queen".into(), Color::Black, 15.into(), 4.into()).unwrap());
+    b.place_piece(pm.build_piece(&"bishop".into(), Color::White, 11.into(), 3.into()).unwrap());
+    b.place_piece(pm.build_piece(&"bishop".into(), Color::Black, 13.into(), 1.into()).unwrap());
+    assert_move_sets_match(&mut b, &pm, -4, 20);
+}
+
+#[test]
+fn test_move_sets_in_check() {
+    let pm = StandardChess::new();
+    let mut b = Board::new();
+    b.place_piece(pm.build_piece(&"king".into(), Color::White, 20.into(), 0.into()).unwrap());
+    b.place_piece(pm.build_piece(&"rook".into(), Color::Black, 20.into(), 30.into()).unwrap());
+    b.place_piece(pm.build_piece(&"knight".into(), Color::White, 21.into(), 35.into()).unwrap());
+    b.place_piece(pm.build_piece(&"rook".into(), Color::White, 40.into(), 15.into()).unwrap());
+    b.place_piece(pm.build_piece(&"bishop".into(), Color::White, 30.into(), 20.into()).unwrap());
+    b.place_piece(pm.build_piece(&"queen".into(), Color::White, 25.into(), 5.into()).unwrap());
+    assert_move_sets_match(&mut b, &pm, -2, 42);
+
+    // The knight lands on every eighth square between the king and the rook.
+    let knight = pm.move_set(&mut b, &21.into(), &35.into());
+    assert_eq!(knight.get_rays().len(), 1);
+    assert_eq!(knight.squares_in(&20.into(), &0.into(), &21.into(), &31.into()).unwrap().len(), 4);
+
+    // A knight check can only be answered by taking the knight.
+    let mut b = Board::new();
+    b.place_piece(pm.build_piece(&"king".into(), Color::White, 20.into(), 0.into()).unwrap());
+    b.place_piece(pm.build_piece(&"knight".into(), Color::Black, 21.into(), 10.into()).unwrap());
+    b.place_piece(pm.build_piece(&"rook".into(), Color::White, 30.into(), 10.into()).unwrap());
+    b.place_piece(pm.build_piece(&"knight".into(), Color::White, 22.into(), 20.into()).unwrap());
+    assert_move_sets_match(&mut b, &pm, -2, 42);
+}
+
+#[test]
+fn test_move_sets_unbounded() {
+    let pm = StandardChess::new();
+    let mut b = Board::new();
+    b.place_piece(pm.build_piece(&"rook".into(), Color::White, 20.into(), 0.into()).unwrap());
+    let set = pm.move_set(&mut b, &20.into(), &0.into());
+    assert!(set.get_targets().is_empty());
+    assert_eq!(set.get_rays().len(), 4);
+    let far: Coord = Coord::from(10).pow(30);
+    let squares = set.squares_in(&20.into(), &far, &21.into(), &(&far + 3)).unwrap();
+    assert_eq!(squares.len(), 3);
+    // Up the board the rook runs into the white pawns.
+    assert_eq!(set.squares_in(&(-100).into(), &0.into(), &20.into(), &1.into()).unwrap().len(), 13);
+    // A window can be as wide as the pieces one, but no wider.
+    let wide = |width: i32| set.squares_in(&20.into(), &1.into(), &21.into(), &(width + 1).into());
+    assert_eq!(wide(MAX_RECT_FILES).map(|x| x.len()), Some(MAX_RECT_FILES as usize));
+    assert!(wide(MAX_RECT_FILES + 1).is_none());
+    assert!(set.squares_in(&(-1_000_000_000_000i64).into(), &0.into(), &21.into(), &1.into()).is_none());
+}
+
+#[test]
+fn test_custom_pieces() {
+    let pm = StandardChess::from_rules(r#"{
+        "pieces": {
+            "pawn": { "moves": [{ "ride": [[1, 0]], "range": 1, "mode": "move" }] },
+            "king": { "moves": [{ "leap": [[1, 0], [1, 1]], "symmetric": true }], "royal": true },
+            "nightrider": { "moves": [{ "ride": [[1, 2]], "symmetric": true }] },
+            "checker": {
+                "moves": [
+                    { "leap": [[1, 1], [1, -1]], "mode": "move" },
+                    { "leap": [[2, 2], [2, -2]], "mode": "capture" }
+                ]
+            },
+            "camel": { "moves": [{ "leap": [[1, 3]], "period": 4, "symmetric": true, "mode": "capture" }] }
+        }
+    }"#).unwrap();
+    let mut b = Board::with_piece_set(pm.piece_set());
+    b.place_piece(pm.build_piece(&"king".into(), Color::White, 20.into(), 0.into()).unwrap());
+    b.place_piece(pm.build_piece(&"nightrider".into(), Color::White, 20.into(), 10.into()).unwrap());
+    b.place_piece(pm.build_piece(&"checker".into(), Color::White, 24.into(), 4.into()).unwrap());
+    b.place_piece(pm.build_piece(&"camel".into(), Color::White, 16.into(), 8.into()).unwrap());
+    b.place_piece(pm.build_piece(&"king".into(), Color::Black, 30.into(), 30.into()).unwrap());
+    b.place_piece(pm.build_piece(&"pawn".into(), Color::Black, 22.into(), 6.into()).unwrap());
+    b.place_piece(pm.build_piece(&"pawn".into(), Color::Black, 22.into(), 14.into()).unwrap());
+
+    let legal = |b: &mut Board, from: (i32, i32), to: (i32, i32)| {
+        Board::is_move_legal(b, &pm, &from.0.into(), &from.1.into(), &to.0.into(), &to.1.into())
+    };
+    // The nightrider keeps going until something is in the way.
+    assert!(legal(&mut b, (20, 10), (17, 4)));
+    assert!(legal(&mut b, (20, 10), (22, 14)));
+    assert!(!legal(&mut b, (20, 10), (23, 16)));
+    assert!(!legal(&mut b, (20, 10), (23, 4)));
+    assert!(!legal(&mut b, (20, 10), (22, 12)));
+    // Checkers step diagonally forward but only capture by jumping.
+    assert!(legal(&mut b, (24, 4), (23, 5)));
+    assert!(!legal(&mut b, (24, 4), (25, 5)));
+    assert!(legal(&mut b, (24, 4), (22, 6)));
+    assert!(!legal(&mut b, (24, 4), (22, 2)));
+    // The camel can't go anywhere without capturing, however far it may leap, but the pawns
+    // on the infinite ranks are still there to be taken.
+    assert!(!legal(&mut b, (16, 8), (15, 11)));
+    assert!(!legal(&mut b, (16, 8), (5, 7)));
+    assert!(legal(&mut b, (16, 8), (1, 7)));
+    assert_move_sets_match(&mut b, &pm, 0, 34);
+}
+
+#[test]
+fn test_renamed_pieces() {
+    // Standard chess with other names: what the pieces do goes by their definitions.
+    let pm = StandardChess::from_rules(r#"{
+        "pieces": {
+            "soldier": {
+                "moves": [
+                    { "ride": [[1, 0]], "range": 1, "mode": "move" },
+                    { "ride": [[1, 0]], "range": 2, "mode": "move", "initial": true },
+                    { "leap": [[1, 1], [1, -1]], "mode": "capture" }
+                ],
+                "en_passant": true,
+                "promotes": true
+            },
+            "tower": { "moves": [{ "ride": [[1, 0]], "symmetric": true }], "castling_partner": true },
+            "emperor": { "moves": [{ "leap": [[1, 0], [1, 1]], "symmetric": true }], "royal": true, "castles": true }
+        },
+        "promotion": ["tower"]
+    }"#).unwrap();
+    let mut b = Board::with_piece_set(pm.piece_set());
+    b.place_piece(pm.build_piece(&"emperor".into(), Color::White, 10.into(), 4.into()).unwrap());
+    b.place_piece(pm.build_piece(&"tower".into(), Color::White, 10.into(), 0.into()).unwrap());
+    b.place_piece(pm.build_piece(&"tower".into(), Color::White, 10.into(), 7.into()).unwrap());
+    b.place_piece(pm.build_piece(&"soldier".into(), Color::White, 4.into(), 4.into()).unwrap());
+    b.place_piece(pm.build_piece(&"emperor".into(), Color::Black, 30.into(), 30.into()).unwrap());
+    b.place_piece(pm.build_piece(&"soldier".into(), Color::Black, 2.into(), 5.into()).unwrap());
+    assert_eq!(b.king(Color::White), Some(0));
+    assert_eq!(b.king(Color::Black), Some(4));
+
+    play(&mut b, &pm, &[(10, 0, 11, 0)]);
+    assert_eq!(b.halfmove_clock(), 1);
+    play(&mut b, &pm, &[(2, 5, 4, 5)]);
+    assert_eq!(b.halfmove_clock(), 0);
+    assert_eq!(b.en_passant_square(), Some((3.into(), 5.into())));
+    assert!(Board::is_move_legal(&mut b, &pm, &4.into(), &4.into(), &3.into(), &5.into()));
+    assert!(Board::is_move_legal(&mut b, &pm, &10.into(), &4.into(), &10.into(), &6.into()));
+    assert_eq!(
+        Board::move_legal(&mut b, &pm, &10.into(), &4.into(), &10.into(), &2.into(), None).err(),
+        Some(IllegalMove::CannotMoveThatWay)
+    );
+    // There is no queen to promote to, but the soldier can still reach the last rank.
+    b.place_piece(pm.build_piece(&"soldier".into(), Color::White, 1.into(), 20.into()).unwrap());
+    assert!(Board::is_move_legal(&mut b, &pm, &1.into(), &20.into(), &0.into(), &20.into()));
+}
+
+/// A variant where nothing may ever be captured, built on top of standard chess.
+#[cfg(test)]
+struct Pacifist(StandardChess);
+
+#[cfg(test)]
+impl PieceRules for Pacifist {
+    fn piece_set(&self) -> Arc<PieceSet> {
+        self.0.piece_set()
+    }
+    fn can_move(&self, board: &Board, rank: &Coord, file: &Coord, to_rank: &Coord, to_file: &Coord) -> Result<Move, IllegalMove> {
+        let m = self.0.can_move(board, rank, file, to_rank, to_file)?;
+        if m.get_captures().is_empty() {
+            Ok(m)
+        } else {
+            Err(IllegalMove::CannotCapture)
+        }
+    }
+    fn promotes(&self, board: &Board, piece: &Piece, to_rank: &Coord) -> bool {
+        self.0.promotes(board, piece, to_rank)
+    }
+    fn add_promotion(&self, board: &Board, m: Move, promotion: Option<String>) -> Result<Move, IllegalMove> {
+        self.0.add_promotion(board, m, promotion)
+    }
+    fn would_be_in_check(&self, board: &mut Board, m: Move) -> Result<Move, IllegalMove> {
+        self.0.would_be_in_check(board, m)
+    }
+    fn move_set(&self, board: &mut Board, rank: &Coord, file: &Coord) -> MoveSet {
+        self.0.move_set(board, rank, file)
+    }
+    fn game_status(&self, board: &mut Board) -> GameStatus {
+        self.0.game_status(board)
+    }
+    fn claimable_draw(&self, board: &Board) -> Option<GameStatus> {
+        self.0.claimable_draw(board)
+    }
+    fn attackers_of(&self, board: &Board, rank: &Coord, file: &Coord, by: Color) -> Vec<(Coord, Coord)> {
+        self.0.attackers_of(board, rank, file, by)
+    }
+    fn movers_to(&self, board: &Board, rank: &Coord, file: &Coord, by: Color) -> Vec<(Coord, Coord)> {
+        self.0.movers_to(board, rank, file, by)
+    }
+}
+
+#[test]
+fn test_variant_rules() {
+    let standard = StandardChess::new();
+    let pacifist = Pacifist(StandardChess::new());
+    let mut b = Board::new();
+    b.place_piece(standard.build_piece(&"rook".into(), Color::White, 20.into(), 0.into()).unwrap());
+    b.place_piece(standard.build_piece(&"rook".into(), Color::Black, 20.into(), 5.into()).unwrap());
+    for rules in [&standard as &dyn PieceRules, &pacifist] {
+        assert!(Board::is_move_legal(&mut b, rules, &20.into(), &0.into(), &20.into(), &4.into()));
+    }
+    assert!(Board::is_move_legal(&mut b, &standard, &20.into(), &0.into(), &20.into(), &5.into()));
+    assert!(!Board::is_move_legal(&mut b, &pacifist, &20.into(), &0.into(), &20.into(), &5.into()));
+}
+
+#[cfg(test)]
+pub(crate) fn standard_board() -> Board {
+    Board::from_setup(&crate::setup::Setup::standard())
+}
+
+#[test]
+fn test_illegal_reasons() {
+    let pm = StandardChess::new();
+    let mut b = standard_board();
+    let why = |b: &mut Board, rank: i32, file: i32, to_rank: i32, to_file: i32| {
+        Board::move_legal(b, &pm, &rank.into(), &file.into(), &to_rank.into(), &to_file.into(), None).err()
+    };
+    assert_eq!(why(&mut b, 4, 4, 3, 4), Some(IllegalMove::NoPiece));
+    assert_eq!(why(&mut b, 1, 4, 3, 4), Some(IllegalMove::NotYourTurn));
+    assert_eq!(why(&mut b, 7, 4, 7, 4), Some(IllegalMove::NotMoving));
+    assert_eq!(why(&mut b, 7, 0, 6, 0), Some(IllegalMove::OwnPiece));
+    assert_eq!(why(&mut b, 7, 0, 5, 0), Some(IllegalMove::Blocked { rank: 6.into(), file: 0.into() }));
+    assert_eq!(why(&mut b, 6, 4, 5, 5), Some(IllegalMove::MustCapture));
+    assert_eq!(why(&mut b, 7, 1, 4, 1), Some(IllegalMove::CannotMoveThatWay));
+    play(&mut b, &pm, &[(6, 5, 5, 5), (1, 4, 3, 4), (6, 6, 4, 6), (0, 3, 4, 7)]);
+    assert_eq!(why(&mut b, 6, 0, 5, 0), Some(IllegalMove::LeavesKingInCheck { rank: 4.into(), file: 7.into() }));
+
+    let mut b = castling_board(&pm, Some(("rook", 12, 5)));
+    assert_eq!(why(&mut b, 10, 4, 10, 6), Some(IllegalMove::CastlingThroughCheck));
+    b.place_piece(pm.build_piece(&"knight".into(), Color::White, 10.into(), 1.into()).unwrap());
+    assert_eq!(why(&mut b, 10, 4, 10, 2), Some(IllegalMove::Blocked { rank: 10.into(), file: 1.into() }));
+    let mut b = castling_board(&pm, None);
+    play(&mut b, &pm, &[(10, 4, 10, 5), (1, 0, 2, 0), (10, 5, 10, 4), (2, 0, 3, 0)]);
+    assert_eq!(why(&mut b, 10, 4, 10, 6), Some(IllegalMove::CastlingRightsLost));
+}
+
+#[cfg(test)]
+pub(crate) fn play(b: &mut Board, pm: &StandardChess, moves: &[(i32, i32, i32, i32)]) {
+    for &(rank, file, to_rank, to_file) in moves {
+        let m = Board::move_legal(b, pm, &rank.into(), &file.into(), &to_rank.into(), &to_file.into(), None);
+        b.do_move(m.unwrap());
+    }
+}
+
+#[test]
+fn test_fools_mate() {
+    let pm = StandardChess::new();
+    let mut b = standard_board();
+    assert_eq!(Board::game_status(&mut b, &pm), GameStatus::Ongoing);
+    play(&mut b, &pm, &[(6, 5, 5, 5), (1, 4, 3, 4), (6, 6, 4, 6), (0, 3, 4, 7)]);
+    // The king can step off the back rank, so this is only check on an infinite board.
+    assert_eq!(Board::game_status(&mut b.clone(), &pm), GameStatus::Ongoing);
+    b.place_piece(pm.build_piece(&"rook".into(), Color::Black, 8.into(), 20.into()).unwrap());
+    assert_eq!(Board::game_status(&mut b, &pm), GameStatus::Checkmate);
+}
+
+#[test]
+fn test_checkmate_interpositions() {
+    let pm = StandardChess::new();
+    let mut b = Board::new();
+    b.place_piece(pm.build_piece(&"king".into(), Color::White, 20.into(), 0.into()).unwrap());
+    for rank in 19..=21 {
+        b.place_piece(pm.build_piece(&"rook".into(), Color::Black, rank.into(), 30.into()).unwrap());
+    }
+    assert_eq!(Board::game_status(&mut b.clone(), &pm), GameStatus::Checkmate);
+
+    // A rook can step into the line of check from far below.
+    let mut rook = b.clone();
+    rook.place_piece(pm.build_piece(&"rook".into(), Color::White, 40.into(), 15.into()).unwrap());
+    assert_eq!(Board::game_status(&mut rook, &pm), GameStatus::Ongoing);
+
+    // A knight can only land on every eighth square of the line.
+    let mut knight = b.clone();
+    knight.place_piece(pm.build_piece(&"knight".into(), Color::White, 21.into(), 35.into()).unwrap());
+    assert_eq!(Board::game_status(&mut knight, &pm), GameStatus::Ongoing);
+
+    // Capturing the checker is also a way out.
+    let mut capture = b.clone();
+    capture.place_piece(pm.build_piece(&"bishop".into(), Color::White, 30.into(), 20.into()).unwrap());
+    assert_eq!(Board::game_status(&mut capture, &pm), GameStatus::Ongoing);
 }
 
 impl PieceRules for StandardChess {
-    fn can_move(&self, board: &mut Board, i: usize, to_rank: &BigInt, to_file: &BigInt) -> Option<Move> {
-        let piece = board.pieces.get(i).unwrap();
-        self.map.get(piece.get_type()).unwrap()(board, i, to_rank, to_file)
+    fn piece_set(&self) -> Arc<PieceSet> {
+        self.set.clone()
+    }
+    fn can_move(&self, board: &Board, rank: &Coord, file: &Coord, to_rank: &Coord, to_file: &Coord) -> Result<Move, IllegalMove> {
+        let (i, piece) = board.piece_at(rank, file).ok_or(IllegalMove::NoPiece)?;
+        let definition = self.definition(&piece).ok_or(IllegalMove::CannotMoveThatWay)?;
+        let c = piece.get_color();
+        let target = board.piece_at(to_rank, to_file);
+        if target.as_ref().is_some_and(|(_, t)| t.get_color() == c) {
+            return Err(IllegalMove::OwnPiece);
+        }
+        let capture = target.is_some();
+        // Keep the most telling reason in case nothing gets there.
+        let mut error = IllegalMove::CannotMoveThatWay;
+        for component in &definition.moves {
+            match self.reach(board, &piece, component, to_rank, to_file) {
+                Reach::Yes if component.mode.allows(capture) => {
+                    return Ok(maybe_capture(board, i, to_rank, to_file).add_motion(i, to_rank, to_file, rank, file));
+                }
+                Reach::Yes if !matches!(error, IllegalMove::Blocked { .. }) => {
+                    error = if capture { IllegalMove::CannotCapture } else { IllegalMove::MustCapture };
+                }
+                Reach::Blocked(rank, file) if component.mode.allows(capture) => {
+                    error = IllegalMove::Blocked { rank, file };
+                }
+                _ => {}
+            }
+        }
+        if definition.en_passant && !capture {
+            if let Some(m) = self.en_passant(board, i, &piece, to_rank, to_file) {
+                return Ok(m);
+            }
+        }
+        if definition.castles && board.king(c) == Some(i) {
+            if let Some(result) = self.castle(board, i, &piece, to_rank, to_file) {
+                return result;
+            }
+        }
+        Err(error)
+    }
+    /// Whether moving `piece` to `to_rank` needs a promotion.
+    fn promotes(&self, board: &Board, piece: &Piece, to_rank: &Coord) -> bool {
+        if !self.definition(piece).is_some_and(|d| d.promotes) {
+            return false;
+        }
+        let color = piece.get_color();
+        let last = board.promotion_rank(color);
+        if pawn_direction(color) > 0 {
+            to_rank >= last
+        } else {
+            to_rank <= last
+        }
+    }
+
+    /// Attaches the chosen promotion to a move, which is only legal if a piece that reaches the
+    /// promotion rank picks one of the rules' promotion pieces and no other move picks anything.
+    fn add_promotion(&self, board: &Board, m: Move, promotion: Option<String>) -> Result<Move, IllegalMove> {
+        let piece = mover(board, &m).ok_or(IllegalMove::NoPiece)?;
+        let to_rank = m.get_motions()[0].get_rank().clone();
+        let promotes = self.promotes(board, &piece, &to_rank);
+        match promotion {
+            Some(p) if promotes && self.set.promotion.contains(&p) => {
+                let (i, from) = (m.get_piece(), piece.get_type().clone());
+                Ok(m.add_promotion(i, from, p))
+            }
+            Some(p) => Err(IllegalMove::BadPromotion(p)),
+            None if promotes => Err(IllegalMove::PromotionRequired),
+            None => Ok(m),
+        }
+    }
+
+    fn would_be_in_check(&self, board: &mut Board, m: Move) -> Result<Move, IllegalMove> {
+        let c = mover(board, &m).ok_or(IllegalMove::NoPiece)?.get_color();
+        if self.is_castling(board, &m) {
+            // The king may not castle out of check or through an attacked square; the
+            // square it lands on is covered by the check test below.
+            let motion = &m.get_motions()[0];
+            let transit = (motion.get_file() + motion.get_from_file()) / 2;
+            if self.is_attacked(board, motion.get_from_rank(), motion.get_from_file(), c.opposite())
+                || self.is_attacked(board, motion.get_from_rank(), &transit, c.opposite())
+            {
+                return Err(IllegalMove::CastlingThroughCheck);
+            }
+        }
+        let made = board.make_move(m.clone());
+        let checker = self.checkers(board, c).into_iter().next();
+        board.unmake_move(&made);
+        match checker {
+            Some((rank, file)) => Err(IllegalMove::LeavesKingInCheck { rank, file }),
+            None => Ok(m),
+        }
+    }
+
+    fn move_set(&self, board: &mut Board, rank: &Coord, file: &Coord) -> MoveSet {
+        let mut out = MoveSet::new();
+        let piece = match board.piece_at(rank, file) {
+            Some((_, piece)) => piece,
+            None => return out,
+        };
+        let c = piece.get_color();
+        if c != board.to_move() {
+            return out;
+        }
+        let pseudo = self.pseudo_move_set(board, &piece);
+        let legal = |board: &mut Board, (to_rank, to_file): &(Coord, Coord)| {
+            Board::is_move_legal(board, self, rank, file, to_rank, to_file)
+        };
+        for target in pseudo.get_targets() {
+            if legal(board, target) {
+                out.add_target(target.0.clone(), target.1.clone());
+            }
+        }
+        let checkers = self.checkers(board, c);
+        match checkers.len() {
+            // Out of check every square of a ray is as legal as any other: moving anywhere along
+            // it either breaks a pin or it doesn't, and a piece can't uncover check by landing.
+            0 => {
+                for ray in pseudo.get_rays() {
+                    if ray.first().is_some_and(|first| legal(board, &first)) {
+                        out.add_ray(ray.clone());
+                    }
+                }
+            }
+            // In check everything has to land on the line from the king to the checker.
+            1 => {
+                let king = board.king(c).unwrap();
+                let (from_rank, from_file, sr, sf, n) = self.check_line(board, king, &checkers[0]);
+                for ray in pseudo.get_rays() {
+                    if let Some(ray) = ray.clip(&from_rank, &from_file, sr, sf, &n) {
+                        if legal(board, &ray.first().unwrap()) {
+                            out.add_ray(ray);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+        out
+    }
+    /// Decides whether the side to move has been checkmated or stalemated.
+    ///
+    /// Move sets are finite, so this comes down to whether any of our pieces has one that
+    /// isn't empty. Besides the pieces on the board there are the ones on the infinite rows,
+    /// of which only a few could possibly matter.
+    fn game_status(&self, board: &mut Board) -> GameStatus {
+        if let Some(draw) = board.claimed_draw {
+            return draw;
+        }
+        let c = board.to_move();
+        let checkers = self.checkers(board, c);
+        let mut squares: Vec<(Coord, Coord)> = Self::own_pieces(board, c)
+            .into_iter()
+            .map(|p| (board.pieces[p].get_rank().clone(), board.pieces[p].get_file().clone()))
+            .collect();
+        if checkers.is_empty() {
+            // Far enough out each row has a piece of every kind in its pattern that nothing
+            // can block or pin, and closer in only the pieces beside something that changed
+            // can do anything those can't.
+            let far_file = board.span() + 1;
+            let mut near: Vec<Coord> = board.pieces.iter().filter(|p| !p.is_captured()).map(|p| p.get_file().clone()).collect();
+            near.extend(board.rows.iter().flat_map(|row| row.files()));
+            for row in board.rows_of(c) {
+                let near_files = near.iter().flat_map(|f| (-STANDARD_BOARD_SIZE..=STANDARD_BOARD_SIZE).map(move |d| f + d));
+                for file in (0..row.period()).map(|k| &far_file + k).chain(near_files) {
+                    if row.piece_at(&file).is_some() {
+                        squares.push((row.get_rank().clone(), file));
+                    }
+                }
+            }
+        } else if checkers.len() == 1 {
+            for (rank, file) in self.row_interpositions(board, c, &checkers[0]) {
+                for square in self.row_pieces_reaching(board, c, &rank, &file, false) {
+                    if !squares.contains(&square) {
+                        squares.push(square);
+                    }
+                }
+            }
+        }
+        let has_move = squares.iter().any(|(rank, file)| {
+            let moves = self.move_set(board, rank, file);
+            !moves.get_targets().is_empty() || !moves.get_rays().is_empty()
+        });
+        if has_move {
+            if board.repetitions() >= AUTOMATIC_REPETITIONS {
+                GameStatus::Repetition
+            } else if board.halfmove_clock() >= AUTOMATIC_HALFMOVES {
+                GameStatus::FiftyMoves
+            } else {
+                GameStatus::Ongoing
+            }
+        } else if checkers.is_empty() {
+            GameStatus::Stalemate
+        } else {
+            GameStatus::Checkmate
+        }
+    }
+    fn attackers_of(&self, board: &Board, rank: &Coord, file: &Coord, by: Color) -> Vec<(Coord, Coord)> {
+        let mut out = Vec::new();
+        for piece in board.pieces.iter().filter(|p| p.get_color() == by && !p.is_captured()) {
+            if self.attacks(board, piece, rank, file) {
+                out.push((piece.get_rank().clone(), piece.get_file().clone()));
+            }
+        }
+        // Pieces still on an infinite row aren't among the pieces, but can attack all the same.
+        for (row_rank, row_file) in self.row_pieces_reaching(board, by, rank, file, true) {
+            let attacks = board.piece_at(&row_rank, &row_file).is_some_and(|(_, piece)| self.attacks(board, &piece, rank, file));
+            if attacks {
+                out.push((row_rank, row_file));
+            }
+        }
+        out
+    }
+    fn movers_to(&self, board: &Board, rank: &Coord, file: &Coord, by: Color) -> Vec<(Coord, Coord)> {
+        let mut squares: Vec<(Coord, Coord)> = board
+            .pieces
+            .iter()
+            .filter(|p| p.get_color() == by && !p.is_captured())
+            .map(|p| (p.get_rank().clone(), p.get_file().clone()))
+            .collect();
+        squares.extend(self.row_pieces_reaching(board, by, rank, file, false));
+        squares
+            .into_iter()
+            .filter(|(r, f)| self.can_move(board, r, f, rank, file).is_ok())
+            .collect()
     }
+    fn claimable_draw(&self, board: &Board) -> Option<GameStatus> {
+        if board.repetitions() >= CLAIM_REPETITIONS {
+            Some(GameStatus::Repetition)
+        } else if board.halfmove_clock() >= CLAIM_HALFMOVES {
+            Some(GameStatus::FiftyMoves)
+        } else {
+            None
+        }
+    }
+}
+
+#[test]
+fn test_repetition() {
+    let pm = StandardChess::new();
+    let mut b = standard_board();
+    let shuffle = [(7, 6, 5, 5), (0, 6, 2, 5), (5, 5, 7, 6), (2, 5, 0, 6)];
+    // The knights have moved after the first round, so that position only comes up once.
+    play(&mut b, &pm, &shuffle);
+    assert_eq!(b.repetitions(), 1);
+    // Looking at pawns doesn't change the position.
+    b.get_piece_at(&WHITE_PAWN_RANK.into(), &30.into());
+    play(&mut b, &pm, &shuffle);
+    assert_eq!(b.repetitions(), 2);
+    assert_eq!(pm.claimable_draw(&b), None);
+    play(&mut b, &pm, &shuffle);
+    assert_eq!(b.repetitions(), 3);
+    assert_eq!(pm.claimable_draw(&b), Some(GameStatus::Repetition));
+    assert_eq!(pm.game_status(&mut b), GameStatus::Ongoing);
+    play(&mut b, &pm, &shuffle);
+    play(&mut b, &pm, &shuffle);
+    assert_eq!(pm.game_status(&mut b), GameStatus::Repetition);
+
+    // A pawn move means none of that can come up again.
+    play(&mut b, &pm, &[(6, 0, 5, 0), (0, 6, 2, 5)]);
+    assert_eq!(b.repetitions(), 1);
+    assert_eq!(b.halfmove_clock(), 1);
+
+    // Claiming a draw ends the game until it is taken back.
+    b.undo_move();
+    b.undo_move();
+    assert_eq!(Board::claim_draw(&mut b, &pm), Some(GameStatus::Repetition));
+    assert_eq!(pm.game_status(&mut b), GameStatus::Repetition);
+    assert!(!Board::is_move_legal(&mut b, &pm, &7.into(), &6.into(), &5.into(), &5.into()));
+    b.undo_move();
+    assert!(Board::is_move_legal(&mut b, &pm, &7.into(), &6.into(), &5.into(), &5.into()));
+}
+
+#[test]
+fn test_fifty_moves() {
+    let pm = StandardChess::new();
+    let mut b = Board::new();
+    b.place_piece(pm.build_piece(&"king".into(), Color::White, 20.into(), 0.into()).unwrap());
+    b.place_piece(pm.build_piece(&"king".into(), Color::Black, 40.into(), 0.into()).unwrap());
+    b.place_piece(pm.build_piece(&"rook".into(), Color::White, 25.into(), 1.into()).unwrap());
+    b.place_piece(pm.build_piece(&"rook".into(), Color::Black, 35.into(), 1.into()).unwrap());
+    let mut file = 1;
+    let mut shuffle = |b: &mut Board, n: usize| {
+        for _ in 0..n {
+            play(b, &pm, &[(25, file, 25, file + 1), (35, file, 35, file + 1)]);
+            file += 1;
+        }
+    };
+    shuffle(&mut b, 49);
+    assert_eq!(b.halfmove_clock(), 98);
+    assert_eq!(pm.claimable_draw(&b), None);
+    shuffle(&mut b, 1);
+    assert_eq!(pm.claimable_draw(&b), Some(GameStatus::FiftyMoves));
+    assert_eq!(pm.game_status(&mut b), GameStatus::Ongoing);
+    shuffle(&mut b, 25);
+    assert_eq!(pm.game_status(&mut b), GameStatus::FiftyMoves);
+}
+
+#[test]
+fn test_attackers() {
+    let pm = StandardChess::new();
+    let mut b = Board::new();
+    b.place_piece(pm.build_piece(&"rook".into(), Color::White, 2.into(), 20.into()).unwrap());
+    b.place_piece(pm.build_piece(&"knight".into(), Color::White, 4.into(), 19.into()).unwrap());
+    b.place_piece(pm.build_piece(&"bishop".into(), Color::White, 20.into(), 2.into()).unwrap());
+    b.place_piece(pm.build_piece(&"queen".into(), Color::Black, 2.into(), 30.into()).unwrap());
+    let sq = |rank: i32, file: i32| (Coord::from(rank), Coord::from(file));
+    assert_eq!(Board::attackers_of(&b, &pm, &12.into(), &(-6).into(), Color::White), vec![sq(20, 2)]);
+    assert!(!Board::is_attacked(&b, &pm, &12.into(), &(-6).into(), Color::Black));
+    // The queen is blocked by the rook, but the black pawns on the rank behind attack the
+    // square without ever having been looked at.
+    let mut attackers = Board::attackers_of(&b, &pm, &2.into(), &19.into(), Color::Black);
+    attackers.sort();
+    assert_eq!(attackers, vec![sq(1, 18), sq(1, 20)]);
+    assert_eq!(b.pieces.len(), 4);
+    assert_eq!(Board::attackers_of(&b, &pm, &2.into(), &19.into(), Color::White), vec![sq(2, 20)]);
+    assert_eq!(Board::attackers_of(&b, &pm, &2.into(), &21.into(), Color::Black).len(), 3);
+    assert!(Board::attackers_of(&b, &pm, &3.into(), &19.into(), Color::Black).is_empty());
+    assert_eq!(Board::attackers_of(&b, &pm, &6.into(), &20.into(), Color::White), vec![sq(2, 20), sq(4, 19)]);
+}
+
+#[test]
+fn test_far_moves() {
+    let pm = StandardChess::new();
+    let mut b = Board::new();
+    b.place_piece(pm.build_piece(&"rook".into(), Color::White, 20.into(), 0.into()).unwrap());
+    b.place_piece(pm.build_piece(&"king".into(), Color::White, 30.into(), 0.into()).unwrap());
+    let far: Coord = Coord::from(10).pow(30);
+    let legal = |b: &mut Board, to_rank: &Coord, to_file: &Coord| Board::move_legal(b, &pm, &20.into(), &0.into(), to_rank, to_file, None);
+    assert!(legal(&mut b, &20.into(), &far).is_ok());
+    assert!(legal(&mut b, &20.into(), &-&far).is_ok());
+    // Up the board the white pawns are in the way, however far the rook wants to go.
+    assert_eq!(legal(&mut b, &-&far, &0.into()).err(), Some(IllegalMove::Blocked { rank: 6.into(), file: 0.into() }));
+
+    let near: Coord = Coord::from(10).pow(29);
+    b.place_piece(pm.build_piece(&"king".into(), Color::Black, 20.into(), near.clone()).unwrap());
+    assert_eq!(legal(&mut b, &20.into(), &far).err(), Some(IllegalMove::Blocked { rank: 20.into(), file: near.clone() }));
+    assert_eq!(Board::attackers_of(&b, &pm, &20.into(), &near, Color::White), vec![(20.into(), 0.into())]);
+    // The black king is in check from 10^29 squares away and has to deal with it.
+    b.place_piece(pm.build_piece(&"bishop".into(), Color::Black, 40.into(), 10.into()).unwrap());
+    play(&mut b, &pm, &[(30, 0, 31, 0)]);
+    assert!(Board::move_legal(&mut b, &pm, &40.into(), &10.into(), &39.into(), &11.into(), None).is_err());
+    assert!(Board::move_legal(&mut b, &pm, &20.into(), &near, &21.into(), &near, None).is_ok());
 }
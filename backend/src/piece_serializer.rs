@@ -1,32 +1,70 @@
-use num_bigint::BigInt;
-use serde_json::Value;
+use crate::coord::Coord;
+use serde::{Deserialize, Serialize};
 
 use crate::piece::{Color, Piece};
+use crate::wire_error::WireError;
 
-pub fn piece_serialize(p: &Piece) -> String {
-    let mut out = String::new();
-    out += &format!("{{\"type\": \"{}_{}\", \"piece\": \"{}\", \"alive\": {}, \"y\": \"{}\", \"x\": \"{}\", \"color\": \"{}\", \"has_moved\": {}}}", p.get_color().to_string(), p.get_type(), p.get_type(), !p.is_captured(), p.get_rank(), p.get_file(),
-p.get_color().to_string(), p.when_moved());
-    out
+/// A piece as it is sent and saved. `type` is the color and piece together, as the frontend
+/// names its images.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct PieceData {
+    #[serde(rename = "type")]
+    pub(crate) kind: String,
+    pub(crate) piece: String,
+    pub(crate) alive: bool,
+    pub(crate) y: Coord,
+    pub(crate) x: Coord,
+    pub(crate) color: Color,
+    pub(crate) has_moved: usize,
+}
+
+impl From<&Piece> for PieceData {
+    fn from(p: &Piece) -> Self {
+        Self {
+            kind: format!("{}_{}", p.get_color().to_string(), p.get_type()),
+            piece: p.get_type().clone(),
+            alive: !p.is_captured(),
+            y: p.get_rank().clone(),
+            x: p.get_file().clone(),
+            color: p.get_color(),
+            has_moved: p.when_moved(),
+        }
+    }
 }
 
-pub fn piece_deserialize(s: &String) -> Option<Piece> {
-    let v: Value = serde_json::from_str(s).ok()?;
-    let mut p = Piece::new(
-        v["piece"].as_str()?.to_string(),
-        if v["color"].as_str()?.to_string() == "white" {
-            Color::White
-        } else {
-            Color::Black
-        },
-        v["y"].as_str()?.to_string().parse::<BigInt>().ok()?,
-        v["x"].as_str()?.to_string().parse::<BigInt>().ok()?,
-    );
-    if !v["alive"].as_bool()? {
-        p.capture();
+impl PieceData {
+    pub(crate) fn into_piece(self) -> Result<Piece, WireError> {
+        if self.piece.is_empty() || self.kind != format!("{}_{}", self.color.to_string(), self.piece) {
+            return Err(WireError::Invalid(format!("a piece of type \"{}\" can't be a {} {}", self.kind, self.color.to_string(), self.piece)));
+        }
+        let mut p = Piece::new(self.piece, self.color, self.y, self.x);
+        if !self.alive {
+            p.capture();
+        }
+        p.set_has_moved(self.has_moved);
+        Ok(p)
     }
-    p.set_has_moved(v["has_moved"].as_u64()? as usize);
+}
+
+pub fn piece_serialize(p: &Piece) -> String {
+    serde_json::to_string(&PieceData::from(p)).expect("pieces always make JSON")
+}
 
-    //TODO do this better
-    Some(p)
+#[test]
+fn piece_round_trip() {
+    let mut p = Piece::new("queen\"}".to_string(), Color::Black, Coord::from(-3), "123456789012345678901234567890".parse().unwrap());
+    p.set_has_moved(7);
+    p.capture();
+    let s = piece_serialize(&p);
+    let read = serde_json::from_str::<PieceData>(&s).unwrap().into_piece().unwrap();
+    assert_eq!(read.get_type(), "queen\"}");
+    assert_eq!(read.get_file(), p.get_file());
+    assert_eq!((read.get_rank(), read.get_color(), read.when_moved(), read.is_captured()), (&Coord::from(-3), Color::Black, 7, true));
+    // The pieces boards were saved with before there were versions read the same.
+    let old = r#"{"type": "white_pawn", "piece": "pawn", "alive": true, "y": "6", "x": "4", "color": "white", "has_moved": 0}"#;
+    assert_eq!(serde_json::from_str::<PieceData>(old).unwrap().into_piece().unwrap().get_type(), "pawn");
+    let wrong = old.replace("white_pawn", "black_pawn");
+    assert!(matches!(serde_json::from_str::<PieceData>(&wrong).unwrap().into_piece(), Err(WireError::Invalid(_))));
+    assert!(serde_json::from_str::<PieceData>(&old.replace("\"white\"", "\"green\"")).is_err());
 }
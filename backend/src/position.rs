@@ -0,0 +1,61 @@
+use crate::coord::Coord;
+
+use crate::board::Board;
+use crate::piece::Color;
+
+/// What decides whether two positions are the same for the repetition rules: where everything
+/// stands, which pieces have moved (that decides castling and double steps), which files the
+/// infinite rows have lost, whose turn it is and whether en passant is possible.
+///
+/// A row piece that was only looked at, and so is on the board but still on its starting
+/// square, counts the same as one that was never touched.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Position {
+    to_move: Color,
+    pieces: Vec<(String, bool, Coord, Coord, bool)>,
+    /// The files each row has lost, in the order of `Board::rows`.
+    gaps: Vec<Vec<Coord>>,
+    en_passant: Option<(Coord, Coord)>,
+}
+
+impl Position {
+    pub fn of(board: &Board) -> Self {
+        let implicit = |i: usize| board.is_row_piece(i);
+        let mut pieces = Vec::new();
+        for (i, piece) in board.pieces.iter().enumerate() {
+            if !piece.is_captured() && !implicit(i) {
+                pieces.push((
+                    piece.get_type().clone(),
+                    piece.get_color() == Color::White,
+                    piece.get_rank().clone(),
+                    piece.get_file().clone(),
+                    piece.has_moved(),
+                ));
+            }
+        }
+        pieces.sort();
+        let gaps = board
+            .rows
+            .iter()
+            .map(|row| {
+                let mut files: Vec<Coord> = row
+                    .files()
+                    .into_iter()
+                    .filter(|f| {
+                        !board.pieces.iter().enumerate().any(|(i, p)| {
+                            !p.is_captured() && implicit(i) && p.get_rank() == row.get_rank() && p.get_file() == f
+                        })
+                    })
+                    .collect();
+                files.sort();
+                files
+            })
+            .collect();
+        Self {
+            to_move: board.to_move(),
+            pieces,
+            gaps,
+            en_passant: board.en_passant_square(),
+        }
+    }
+}
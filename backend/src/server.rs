@@ -5,25 +5,43 @@ use async_std::sync::{Mutex, Condvar};
 use std::time::Duration;
 mod error;
 #[cfg(feature = "server")]
-use actix_web::{get, HttpResponse};
+use actix_web::{get, http::header, HttpRequest, HttpResponse};
 #[cfg(feature = "server")]
 use actix_web::{web, App, HttpServer};
-use num_bigint::BigInt;
+use crate::coord::Coord;
 mod board;
+mod board_binary;
+mod board_delta;
 mod board_serializer;
-mod pawn_rank;
+mod coord;
+mod fen;
+mod game_record;
+mod piece_row;
+mod move_set;
+mod illegal_move;
 mod moves;
+mod notation;
 mod piece;
+mod piece_definition;
 mod piece_rules;
 mod piece_serializer;
-use crate::piece::Piece;
+mod position;
+mod setup;
+#[cfg(test)]
+mod test_util;
+mod wire_error;
+use crate::setup::Setup;
 /*#[cfg(feature = "server")]
 use rusqlite::Connection;*/
-use crate::piece_rules::StandardChess;
+use crate::piece_rules::{GameStatus, PieceRules, StandardChess};
 
-use crate::board::Board;
+use crate::board::{too_wide, Board};
+use crate::board_binary::board_encode;
 #[cfg(feature = "server")]
+use crate::board_delta::delta_serialize;
 use crate::board_serializer::board_serialize;
+use crate::game_record::GameRecord;
+use crate::piece_serializer::piece_serialize;
 use crate::error::*;
 #[cfg(feature = "server")]
 use actix_files as fs;
@@ -32,117 +50,57 @@ use async_std::task;
 
 type SharedData = (Mutex<Board>, Condvar);
 type Shared = web::Data<SharedData>;
+type Rules = web::Data<Arc<dyn PieceRules>>;
+type Tags = web::Data<Vec<(String, String)>>;
+
+/// The content type of boards in binary, which clients get instead of JSON by asking for it.
+const BINARY_BOARD: &str = "application/x-chess-board";
 
 #[cfg(feature = "server")]
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let mut b = Board::new();
-    b.place_piece(Piece::new(
-        "rook".to_string(),
-        piece::Color::Black,
-        0.into(),
-        0.into(),
-    ));
-    b.place_piece(Piece::new(
-        "rook".to_string(),
-        piece::Color::White,
-        7.into(),
-        0.into(),
-    ));
-    b.place_piece(Piece::new(
-        "knight".to_string(),
-        piece::Color::Black,
-        0.into(),
-        1.into(),
-    ));
-    b.place_piece(Piece::new(
-        "knight".to_string(),
-        piece::Color::White,
-        7.into(),
-        1.into(),
-    ));
-    b.place_piece(Piece::new(
-        "bishop".to_string(),
-        piece::Color::Black,
-        0.into(),
-        2.into(),
-    ));
-    b.place_piece(Piece::new(
-        "bishop".to_string(),
-        piece::Color::White,
-        7.into(),
-        2.into(),
-    ));
-    b.place_piece(Piece::new(
-        "queen".to_string(),
-        piece::Color::Black,
-        0.into(),
-        3.into(),
-    ));
-    b.place_piece(Piece::new(
-        "queen".to_string(),
-        piece::Color::White,
-        7.into(),
-        3.into(),
-    ));
-    b.place_piece(Piece::new(
-        "king".to_string(),
-        piece::Color::Black,
-        0.into(),
-        4.into(),
-    ));
-    b.place_piece(Piece::new(
-        "king".to_string(),
-        piece::Color::White,
-        7.into(),
-        4.into(),
-    ));
-    b.place_piece(Piece::new(
-        "bishop".to_string(),
-        piece::Color::Black,
-        0.into(),
-        5.into(),
-    ));
-    b.place_piece(Piece::new(
-        "bishop".to_string(),
-        piece::Color::White,
-        7.into(),
-        5.into(),
-    ));
-    b.place_piece(Piece::new(
-        "knight".to_string(),
-        piece::Color::Black,
-        0.into(),
-        6.into(),
-    ));
-    b.place_piece(Piece::new(
-        "knight".to_string(),
-        piece::Color::White,
-        7.into(),
-        6.into(),
-    ));
-    b.place_piece(Piece::new(
-        "rook".to_string(),
-        piece::Color::Black,
-        0.into(),
-        7.into(),
-    ));
-    b.place_piece(Piece::new(
-        "rook".to_string(),
-        piece::Color::White,
-        7.into(),
-        7.into(),
-    ));
+    // A setup file given after the rules file replaces the standard opening.
+    // Game records name the rules and setup the game is played with, unless they're standard.
+    let mut tags: Vec<(String, String)> = Vec::new();
+    let setup = match std::env::args().nth(2) {
+        Some(path) => {
+            let text = std::fs::read_to_string(path)?;
+            let setup = Setup::parse(&text).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+            tags.push(("Setup".to_string(), text));
+            setup
+        }
+        None => Setup::standard(),
+    };
+    // A rules file given on the command line replaces the standard pieces.
+    let rules = match std::env::args().nth(1) {
+        Some(path) => {
+            let text = std::fs::read_to_string(path)?;
+            let rules = StandardChess::from_rules(&text).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+            tags.push(("Rules".to_string(), text));
+            rules
+        }
+        None => StandardChess::new(),
+    };
+    let rules: Arc<dyn PieceRules> = Arc::new(rules);
+    let mut b = Board::with_piece_set(rules.piece_set());
+    b.set_up(&setup);
     let board: Shared = web::Data::new((Mutex::new(b), Condvar::new()));
+    let tags: Tags = web::Data::new(tags);
     HttpServer::new(move || {
         App::new()
             .service(get)
             .service(get_legal)
+            .service(get_moves)
+            .service(get_pieces)
             .service(get_move)
-            .service(get_promote)
+            .service(get_move_promote)
             .service(get_version)
+            .service(get_delta)
+            .service(get_draw)
+            .service(get_record)
             .app_data(board.clone())
-            .data(Arc::new(StandardChess::new()))
+            .app_data(tags.clone())
+            .data(rules.clone())
             .service(fs::Files::new("/", "./static").index_file("index.html"))
     })
     .bind("0.0.0.0:8080")?
@@ -151,102 +109,219 @@ async fn main() -> std::io::Result<()> {
     Ok(())
 }
 
+/// How much an Accept header wants `wanted`, going by the most specific media range that
+/// covers it: the type itself, then `type/*`, then `*/*`. Nothing covering it means 0.
+fn quality(accept: &str, wanted: &str) -> f32 {
+    let kind = wanted.split('/').next().unwrap_or_default();
+    let mut best: Option<(u8, f32)> = None;
+    for range in accept.split(',') {
+        let mut params = range.split(';').map(str::trim);
+        let specificity = match params.next() {
+            Some(m) if m == wanted => 2,
+            Some(m) if m.strip_suffix("/*") == Some(kind) => 1,
+            Some("*/*") => 0,
+            _ => continue,
+        };
+        let q = params.find_map(|p| p.strip_prefix("q=")).and_then(|q| q.parse().ok()).unwrap_or(1.0);
+        if best.map_or(true, |(s, _)| specificity > s) {
+            best = Some((specificity, q));
+        }
+    }
+    best.map_or(0.0, |(_, q)| q)
+}
+
+/// The board as JSON, or in binary for clients whose Accept header prefers that. JSON wins a
+/// tie, so clients that accept anything keep getting what they always have.
+fn board_response(req: &HttpRequest, b: &Board, status: GameStatus) -> HttpResponse {
+    let accept = req.headers().get(header::ACCEPT).and_then(|h| h.to_str().ok()).unwrap_or_default();
+    let binary = quality(accept, BINARY_BOARD);
+    let mut response = HttpResponse::Ok();
+    response.header(header::VARY, "Accept");
+    if binary > 0.0 && binary > quality(accept, "application/json") {
+        response.content_type(BINARY_BOARD).body(board_encode(b, status))
+    } else {
+        response.content_type("application/json").body(board_serialize(b, status))
+    }
+}
+
 #[get("/board")]
-pub async fn get(shared: Shared) -> Result<HttpResponse, Error> {
+pub async fn get(req: HttpRequest, shared: Shared, rules: Rules) -> Result<HttpResponse, Error> {
     let (board, cvar) = &**shared;
-    let b = board.lock().await;
-    Ok(HttpResponse::Ok()
-        .content_type("application/json")
-        .body(board_serialize(&b)))
+    let mut b = board.lock().await;
+    let status = Board::game_status(&mut b, rules.get_ref().as_ref());
+    Ok(board_response(&req, &b, status))
 }
 
 #[get("/board/{version}")]
-pub async fn get_version(shared: Shared, web::Path((version)): web::Path<(String)>) -> Result<HttpResponse, Error> {
+pub async fn get_version(req: HttpRequest, shared: Shared, rules: Rules, web::Path((version)): web::Path<(String)>) -> Result<HttpResponse, Error> {
     let (board, cvar) = &**shared;
-    let version2 = version.parse::<BigInt>().map_err(|_| Error::new())?;
+    let version2 = version.parse::<Coord>().map_err(|_| Error::new())?;
     let mut b = board.lock().await;
     while  b.turn < version2 {
        b = cvar.wait(b).await;
     }
+    let status = Board::game_status(&mut b, rules.get_ref().as_ref());
+    Ok(board_response(&req, &b, status))
+}
+
+/// Waits like `/board/{version}`, then sends only what happened after the first `ply` moves.
+#[get("/delta/{version}/{ply}")]
+pub async fn get_delta(
+    shared: Shared,
+    rules: Rules,
+    web::Path((version, ply)): web::Path<(String, usize)>,
+) -> Result<HttpResponse, Error> {
+    let (board, cvar) = &**shared;
+    let version = version.parse::<Coord>().map_err(|_| Error::new())?;
+    let mut b = board.lock().await;
+    while b.turn < version {
+        b = cvar.wait(b).await;
+    }
+    let status = Board::game_status(&mut b, rules.get_ref().as_ref());
+    let delta = delta_serialize(&b, ply, status).ok_or_else(Error::new)?;
     Ok(HttpResponse::Ok()
         .content_type("application/json")
-        .body(board_serialize(&b)))
+        .body(delta))
 }
 
 #[get("/legal/{px}/{py}/{wx}/{wy}/{zoom}")]
 pub async fn get_legal(
     shared: Shared,
-    rules: web::Data<Arc<StandardChess>>,
+    rules: Rules,
     web::Path((px, py, wx, wy, zoom)): web::Path<(String, String, String, String, String)>,
 ) -> Result<HttpResponse, Error> {
     let (board, cvar) = &**shared;
-    let bigpx = BigInt::from_str(&px).map_err(|_| Error::new())?;
-    let bigpy = BigInt::from_str(&py).map_err(|_| Error::new())?;
-    let bigwx = BigInt::from_str(&wx).map_err(|_| Error::new())?;
-    let bigwy = BigInt::from_str(&wy).map_err(|_| Error::new())?;
-    let bigzoom = BigInt::from_str(&zoom).map_err(|_| Error::new())?;
+    let bigpx = Coord::from_str(&px).map_err(|_| Error::new())?;
+    let bigpy = Coord::from_str(&py).map_err(|_| Error::new())?;
+    let bigwx = Coord::from_str(&wx).map_err(|_| Error::new())?;
+    let bigwy = Coord::from_str(&wy).map_err(|_| Error::new())?;
+    let bigzoom = Coord::from_str(&zoom).map_err(|_| Error::new())?;
 
     let mut b = board.lock().await;
 
-    let mut xx = bigwx.clone();
-    let wwx = bigwx + bigzoom.clone();
-    let wwy = bigwy.clone() + bigzoom;
-    let mut results = Vec::new();
-    while xx < wwx {
-        let mut yy = bigwy.clone();
-        while yy < wwy {
-            if Board::is_move_legal(&mut b, &rules, &bigpx, &bigpy, &xx, &yy) {
-                results.push(format!("[{}, {}]", xx, yy));
-            }
-            yy += 1;
+    let wwx = &bigwx + &bigzoom;
+    let wwy = &bigwy + &bigzoom;
+    let results = match Board::move_set(&mut b, rules.get_ref().as_ref(), &bigpx, &bigpy).squares_in(&bigwx, &bigwy, &wwx, &wwy) {
+        Some(squares) => squares.iter().map(|(xx, yy)| format!("[{}, {}]", xx, yy)).collect::<Vec<String>>(),
+        None => {
+            return Ok(HttpResponse::BadRequest()
+                .content_type("application/json")
+                .body(too_wide()))
         }
-        xx += 1;
-    }
+    };
     Ok(HttpResponse::Ok()
         .content_type("application/json")
         .body(format!("[{}]", results.join(","))))
 }
 
-#[get("/move/{px}/{py}/{dx}/{dy}")]
-pub async fn get_move(
+#[get("/moves/{px}/{py}")]
+pub async fn get_moves(
     shared: Shared,
-    rules: web::Data<Arc<StandardChess>>,
-    web::Path((px, py, dx, dy)): web::Path<(String, String, String, String)>,
+    rules: Rules,
+    web::Path((px, py)): web::Path<(String, String)>,
 ) -> Result<HttpResponse, Error> {
-    let (board, cvar) = &**shared;
-    let bigpx = BigInt::from_str(&px).map_err(|_| Error::new())?;
-    let bigpy = BigInt::from_str(&py).map_err(|_| Error::new())?;
-    let bigdx = BigInt::from_str(&dx).map_err(|_| Error::new())?;
-    let bigdy = BigInt::from_str(&dy).map_err(|_| Error::new())?;
+    let (board, _) = &**shared;
+    let bigpx = Coord::from_str(&px).map_err(|_| Error::new())?;
+    let bigpy = Coord::from_str(&py).map_err(|_| Error::new())?;
 
     let mut b = board.lock().await;
 
-    if let Some(m) = Board::move_legal(&mut b, &rules, &bigpx, &bigpy, &bigdx, &bigdy) {
-        b.do_move(m);
-        b.turn += 1;
-    }
-    cvar.notify_all();
     Ok(HttpResponse::Ok()
         .content_type("application/json")
-        .body("swag"))
+        .body(Board::move_set(&mut b, rules.get_ref().as_ref(), &bigpx, &bigpy).serialize()))
 }
 
+#[get("/pieces/{min_rank}/{min_file}/{max_rank}/{max_file}")]
+pub async fn get_pieces(
+    shared: Shared,
+    web::Path((min_rank, min_file, max_rank, max_file)): web::Path<(String, String, String, String)>,
+) -> Result<HttpResponse, Error> {
+    let (board, _) = &**shared;
+    let min_rank = Coord::from_str(&min_rank).map_err(|_| Error::new())?;
+    let min_file = Coord::from_str(&min_file).map_err(|_| Error::new())?;
+    let max_rank = Coord::from_str(&max_rank).map_err(|_| Error::new())?;
+    let max_file = Coord::from_str(&max_file).map_err(|_| Error::new())?;
+
+    let b = board.lock().await;
+    let pieces = match b.pieces_in_rect(&min_rank, &min_file, &max_rank, &max_file) {
+        Some(pieces) => pieces.iter().map(|(_, piece)| piece_serialize(piece)).collect::<Vec<String>>(),
+        None => {
+            return Ok(HttpResponse::BadRequest()
+                .content_type("application/json")
+                .body(too_wide()))
+        }
+    };
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(format!("[{}]", pieces.join(","))))
+}
 
-#[get("/promote/{px}/{py}/{p}")]
-pub async fn get_promote(
+async fn make_move(
     shared: Shared,
-    rules: web::Data<Arc<StandardChess>>,
-    web::Path((px, py, p)): web::Path<(String, String, String)>,
+    rules: Rules,
+    (px, py, dx, dy): (String, String, String, String),
+    promotion: Option<String>,
 ) -> Result<HttpResponse, Error> {
     let (board, cvar) = &**shared;
-    let bigpx = BigInt::from_str(&px).map_err(|_| Error::new())?;
-    let bigpy = BigInt::from_str(&py).map_err(|_| Error::new())?;
+    let bigpx = Coord::from_str(&px).map_err(|_| Error::new())?;
+    let bigpy = Coord::from_str(&py).map_err(|_| Error::new())?;
+    let bigdx = Coord::from_str(&dx).map_err(|_| Error::new())?;
+    let bigdy = Coord::from_str(&dy).map_err(|_| Error::new())?;
 
     let mut b = board.lock().await;
 
-    b.promote(&bigpx, &bigpy, p);
+    match Board::move_legal(&mut b, rules.get_ref().as_ref(), &bigpx, &bigpy, &bigdx, &bigdy, promotion) {
+        Ok(m) => {
+            b.do_move(m);
+            b.turn += 1;
+            cvar.notify_all();
+            Ok(HttpResponse::Ok()
+                .content_type("application/json")
+                .body("swag"))
+        }
+        Err(e) => Ok(HttpResponse::BadRequest()
+            .content_type("application/json")
+            .body(e.serialize())),
+    }
+}
+
+#[get("/move/{px}/{py}/{dx}/{dy}")]
+pub async fn get_move(
+    shared: Shared,
+    rules: Rules,
+    web::Path(path): web::Path<(String, String, String, String)>,
+) -> Result<HttpResponse, Error> {
+    make_move(shared, rules, path, None).await
+}
+
+#[get("/move/{px}/{py}/{dx}/{dy}/{promotion}")]
+pub async fn get_move_promote(
+    shared: Shared,
+    rules: Rules,
+    web::Path((px, py, dx, dy, promotion)): web::Path<(String, String, String, String, String)>,
+) -> Result<HttpResponse, Error> {
+    make_move(shared, rules, (px, py, dx, dy), Some(promotion)).await
+}
+
+#[get("/draw")]
+pub async fn get_draw(shared: Shared, rules: Rules) -> Result<HttpResponse, Error> {
+    let (board, cvar) = &**shared;
+    let mut b = board.lock().await;
+    let draw = Board::claim_draw(&mut b, rules.get_ref().as_ref()).ok_or_else(Error::new)?;
+    b.turn += 1;
     cvar.notify_all();
     Ok(HttpResponse::Ok()
         .content_type("application/json")
-        .body("swag"))
-}
\ No newline at end of file
+        .body(format!("\"{}\"", draw)))
+}
+
+/// The game so far as a record in the style of PGN.
+#[get("/record")]
+pub async fn get_record(shared: Shared, rules: Rules, tags: Tags) -> Result<HttpResponse, Error> {
+    let (board, _) = &**shared;
+    let b = board.lock().await;
+    let record = GameRecord::of(&b, rules.get_ref().as_ref(), &tags).ok_or_else(Error::new)?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/x-chess-pgn")
+        .body(record.write()))
+}
@@ -0,0 +1,174 @@
+use serde::Deserialize;
+
+use crate::coord::Coord;
+use crate::piece::Color;
+use crate::piece_row::PieceRow;
+use crate::piece_rules::pawn_direction;
+#[cfg(test)]
+use crate::{
+    board::Board,
+    piece_rules::{assert_move_sets_match, play, GameStatus, StandardChess},
+};
+
+/// The usual opening: one back rank for each side, with an infinite pawn rank in front of it.
+const STANDARD_SETUP: &str = include_str!("../setups/standard.json");
+/// The usual opening with the back ranks repeating every eight files along with the pawns.
+#[cfg(test)]
+pub(crate) const REPEATING_SETUP: &str = include_str!("../setups/repeating.json");
+
+/// A piece standing on the board when the game starts.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SetupPiece {
+    pub piece: String,
+    pub color: Color,
+    pub rank: Coord,
+    pub file: Coord,
+}
+
+/// A rank filled with one side's pawns forever in both directions.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PawnRow {
+    pub color: Color,
+    pub rank: Coord,
+}
+
+/// A rank filled with one side's pieces forever in both directions, repeating `pattern` from
+/// file 0 on, so that a row of `["rook", "knight"]` has rooks on the even files.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SetupRow {
+    pub color: Color,
+    pub rank: Coord,
+    pub pattern: Vec<String>,
+}
+
+fn white() -> Color {
+    Color::White
+}
+
+/// A starting position, in the format of `setups/standard.json`: the pieces on the board,
+/// the infinite pawn ranks, any other infinite rows and who moves first. Each side has at
+/// most one pawn rank, and a side without one plays with only its pieces, as in a handicap
+/// game. A king among the pieces is royal, the one that can be checked and mated; kings
+/// repeating in a row are ordinary pieces, so a row can take a royal king in its place, as
+/// in `setups/repeating.json`. Pawns promote on the opponent's back rank, wherever that is.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Setup {
+    #[serde(default)]
+    pub pieces: Vec<SetupPiece>,
+    #[serde(default)]
+    pub pawns: Vec<PawnRow>,
+    #[serde(default)]
+    pub rows: Vec<SetupRow>,
+    #[serde(default = "white")]
+    pub to_move: Color,
+}
+
+impl Setup {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let setup: Setup = serde_json::from_str(s).map_err(|e| e.to_string())?;
+        for color in [Color::White, Color::Black] {
+            if setup.pawns.iter().filter(|row| row.color == color).count() > 1 {
+                return Err(format!("{} has more than one pawn rank", color.to_string()));
+            }
+        }
+        if setup.rows.iter().any(|row| row.pattern.is_empty()) {
+            return Err("a row needs at least one piece to repeat".to_string());
+        }
+        let ranks: Vec<&Coord> = setup.pawns.iter().map(|row| &row.rank).chain(setup.rows.iter().map(|row| &row.rank)).collect();
+        for (i, rank) in ranks.iter().enumerate() {
+            if ranks[..i].contains(rank) {
+                return Err(format!("two rows on rank {}", rank));
+            }
+        }
+        for (i, p) in setup.pieces.iter().enumerate() {
+            if setup.pieces[..i].iter().any(|q| q.rank == p.rank && q.file == p.file) {
+                return Err(format!("two pieces on {}, {}", p.rank, p.file));
+            }
+        }
+        Ok(setup)
+    }
+    pub fn standard() -> Self {
+        Self::parse(STANDARD_SETUP).unwrap()
+    }
+    /// Every infinite row, pawn ranks first.
+    pub(crate) fn rows(&self) -> Vec<PieceRow> {
+        let pawns = self.pawns.iter().map(|row| PieceRow::pawns(row.color, row.rank.clone()));
+        let rows = self.rows.iter().map(|row| PieceRow::new(row.color, row.rank.clone(), row.pattern.clone()));
+        pawns.chain(rows).collect()
+    }
+    /// The rank `color`'s pawns promote on: the opponent's piece or row farthest the way they
+    /// move. None if the opponent starts with nothing.
+    pub(crate) fn promotion_rank(&self, color: Color) -> Option<Coord> {
+        let pieces = self.pieces.iter().filter(|p| p.color != color).map(|p| &p.rank);
+        let pawns = self.pawns.iter().filter(|row| row.color != color).map(|row| &row.rank);
+        let rows = self.rows.iter().filter(|row| row.color != color).map(|row| &row.rank);
+        let ranks = pieces.chain(pawns).chain(rows);
+        let farthest = if pawn_direction(color) > 0 { ranks.max() } else { ranks.min() };
+        farthest.cloned()
+    }
+}
+
+#[test]
+fn test_setup() {
+    let setup = Setup::standard();
+    assert_eq!(setup.pieces.len(), 16);
+    let pawns: Vec<(Color, Coord)> = setup.rows().iter().map(|row| (row.get_color(), row.get_rank().clone())).collect();
+    assert_eq!(pawns, vec![(Color::Black, 1.into()), (Color::White, 6.into())]);
+    assert!(setup.rows().iter().all(|row| row.get_pattern() == ["pawn"]));
+    assert_eq!(setup.to_move, Color::White);
+
+    let far = Setup::parse("{\"pieces\": [{\"piece\": \"king\", \"color\": \"black\", \"rank\": \"-100000000000000000000\", \"file\": 4}]}").unwrap();
+    assert_eq!(far.pieces[0].rank, "-100000000000000000000".parse().unwrap());
+    assert!(far.rows().is_empty());
+
+    assert!(Setup::parse("{\"pawns\": [{\"color\": \"white\", \"rank\": 6}, {\"color\": \"white\", \"rank\": 5}]}").is_err());
+    assert!(Setup::parse("{\"pieces\": [{\"piece\": \"king\", \"color\": \"black\", \"rank\": 0, \"file\": 4}, {\"piece\": \"rook\", \"color\": \"white\", \"rank\": 0, \"file\": 4}]}").is_err());
+    assert!(Setup::parse("{\"to_move\": \"red\"}").is_err());
+    assert!(Setup::parse("{\"pawns\": [{\"color\": \"white\", \"rank\": 6}], \"rows\": [{\"color\": \"black\", \"rank\": 6, \"pattern\": [\"rook\"]}]}").is_err());
+    assert!(Setup::parse("{\"rows\": [{\"color\": \"black\", \"rank\": 0, \"pattern\": []}]}").is_err());
+
+    let repeating = Setup::parse(REPEATING_SETUP).unwrap();
+    let rows = repeating.rows();
+    assert_eq!(rows.len(), 4);
+    assert_eq!(rows[2].type_at(&(-4).into()), "king");
+    assert_eq!(rows[2].type_at(&(-1).into()), "rook");
+    assert_eq!(repeating.pieces.len(), 2);
+    assert!(Setup::parse("{\"kings\": []}").is_err());
+}
+
+#[test]
+fn test_setups() {
+    use crate::board_serializer::{board_deserialize, board_serialize};
+    let pm = StandardChess::new();
+    // Black gives white a rook's odds and no pawns, and moves first from further back.
+    let setup = Setup::parse(
+        "{\"to_move\": \"black\", \"pawns\": [{\"color\": \"black\", \"rank\": -1}], \"pieces\": [
+            {\"piece\": \"king\", \"color\": \"black\", \"rank\": -2, \"file\": 4},
+            {\"piece\": \"king\", \"color\": \"white\", \"rank\": 7, \"file\": 4},
+            {\"piece\": \"rook\", \"color\": \"white\", \"rank\": 7, \"file\": 0}]}",
+    )
+    .unwrap();
+    let mut b = Board::from_setup(&setup);
+    assert_eq!(b.to_move(), Color::Black);
+    assert_eq!(b.implicit_piece(&(-1).into(), &100.into()).map(|(c, _)| c), Some(Color::Black));
+    assert_eq!(b.implicit_piece(&1.into(), &100.into()).map(|(c, _)| c), None);
+    assert_eq!(b.implicit_piece(&6.into(), &100.into()).map(|(c, _)| c), None);
+    assert_move_sets_match(&mut b, &pm, -4, 12);
+    assert_eq!(Board::game_status(&mut b, &pm), GameStatus::Ongoing);
+    play(&mut b, &pm, &[(-1, 4, 1, 4), (7, 0, -1, 0)]);
+    assert_eq!(b.implicit_piece(&(-1).into(), &0.into()).map(|(c, _)| c), None);
+    assert_eq!(Board::game_status(&mut b, &pm), GameStatus::Ongoing);
+
+    // The pawn ranks survive saving and loading, including the one white doesn't have.
+    let s = board_serialize(&b, GameStatus::Ongoing);
+    let mut loaded = Board::new();
+    board_deserialize(&mut loaded, &s).unwrap();
+    assert!(loaded.rows_of(Color::White).next().is_none());
+    assert_eq!(loaded.rows_of(Color::Black).next().map(|p| p.get_rank().clone()), Some((-1).into()));
+    assert_eq!(loaded.implicit_piece(&(-1).into(), &0.into()).map(|(c, _)| c), None);
+    assert_eq!(loaded.to_move(), Color::Black);
+}
@@ -0,0 +1,20 @@
+use crate::coord::Coord;
+
+/// Numbers that look random enough for tests, the same ones every run.
+pub(crate) struct Rng(pub(crate) u64);
+
+impl Rng {
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+    /// A coordinate at most `reach` either side of 0.
+    pub(crate) fn offset(&mut self, reach: usize) -> Coord {
+        Coord::from(self.below(2 * reach + 1) as i64 - reach as i64)
+    }
+}
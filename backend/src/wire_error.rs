@@ -0,0 +1,40 @@
+/// Why a saved board, or a piece or move in one, couldn't be read.
+#[derive(Clone, Debug, PartialEq)]
+pub enum WireError {
+    /// The text isn't JSON of the right shape; holds what serde said about it.
+    Json(String),
+    /// The board was written by a newer version than this one reads.
+    Version(u32),
+    /// The JSON has the right shape but doesn't make a board; says what is wrong with it.
+    Invalid(String),
+}
+
+impl WireError {
+    fn reason(&self) -> &'static str {
+        match self {
+            WireError::Json(_) => "bad_json",
+            WireError::Version(_) => "unknown_version",
+            WireError::Invalid(_) => "invalid",
+        }
+    }
+
+    pub fn serialize(&self) -> String {
+        format!("{{\"reason\": \"{}\", \"message\": {}}}", self.reason(), serde_json::Value::from(self.to_string()))
+    }
+}
+
+impl From<serde_json::Error> for WireError {
+    fn from(e: serde_json::Error) -> Self {
+        WireError::Json(e.to_string())
+    }
+}
+
+impl std::fmt::Display for WireError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WireError::Json(s) => write!(f, "not a saved board: {}", s),
+            WireError::Version(v) => write!(f, "can't read boards of version {}", v),
+            WireError::Invalid(s) => write!(f, "the board doesn't add up: {}", s),
+        }
+    }
+}
@@ -42,38 +42,33 @@ function cycleColor(color) {
     render();
 }
 
-function promote(n) {
-    var pt = ["knight", "bishop", "rook", "queen"][n];
-    var toPromoteInfo = getPieceInfo(toPromote);
-    if (OFFLINE) {
-        document.getElementById("overlay").style.display = "none";
-        board.promote("" + toPromoteInfo.y, "" + toPromoteInfo.x, pt);
-        render();
-        toPromote = null;
-        return;
+// Offers the pieces the rules let a piece of this color promote to.
+function showPromotions(color) {
+    var list = document.getElementById("promotions");
+    while (list.firstChild) {
+        list.removeChild(list.firstChild);
     }
-    fetch("/promote/" + toPromoteInfo.y + "/" + toPromoteInfo.x + "/" + pt)
-        .then(response => {
-            if (!response.ok) {
-                throw new Error(`Request failed with status ${response.status}`)
-            }
-            return response.text()
-        })
-        .then(data => {
-            document.getElementById("overlay").style.display = "none";
-            board.promote("" + toPromoteInfo.y, "" + toPromoteInfo.x, pt);
-            render();
-            toPromote = null;
-        })
-        .catch(error => console.log(error))
+    var pieces = JSON.parse(board.promotion_pieces());
+    for (var i = 0; i < pieces.length; i++) {
+        var n = document.createElement("li");
+        n.className = color + "_" + pieces[i];
+        n.onclick = promote.bind(null, pieces[i]);
+        n.appendChild(document.createElement("wbr"));
+        list.appendChild(n);
+    }
+    document.getElementById("overlay").style.display = "block";
+}
 
+function promote(pt) {
+    document.getElementById("overlay").style.display = "none";
+    make_move(toPromote.fromX, toPromote.fromY, toPromote.x, toPromote.y, pt);
+    toPromote = null;
 }
 function undo() {
     console.log(board.undo_move());
     render();
 }
 
-window.promote = promote;
 window.cycleColor = cycleColor;
 window.flip = flip;
 window.undo = undo;
@@ -97,11 +92,12 @@ function getSpace(x, y) {
     return d;
 }
 function getPiece(x, y) {
-    return board.get_piece_at("" + y, "" + x);
+    var piece = board.piece_at("" + y, "" + x);
+    return piece ? JSON.parse(piece) : null;
 }
 
-function getPieceInfo(i) {
-    return JSON.parse(board.get_piece_info(i));
+function samePiece(a, b) {
+    return a != null && b != null && a.x == b.x && a.y == b.y;
 }
 
 function addSquares() {
@@ -138,22 +134,21 @@ function render() {
     if (flipped) {
         yCord = -yCord - size + 1;
     }
-    var toMoveInfo = getPieceInfo(toMove);
     for (var i = 0; i < size * size; i++) {
         var n = document.getElementById(cell_prefix + i);
         n.className = "";
         var f = size % 2 == 0 ? Math.floor(i / size) : 0;
         n.classList.add((f + i + xCord) % 2 == 0 ? "white_square" : "black_square");
     }
-    board.add_pawns("" + xCord, "" + size);
-    var pieces = JSON.parse(board.get_pieces());
+    var minRank = flipped ? -yCord - size + 1 : yCord;
+    var pieces = JSON.parse(board.pieces_in_rect("" + minRank, "" + xCord, "" + (minRank + size - 1), "" + (xCord + size - 1)));
     for (var i = 0; i < pieces.length; i++) {
         var piece = pieces[i];
         if (displayed(piece.x, piece.y) && piece.alive) {
             var d = getSpace(piece.x, piece.y);
             var n = document.getElementById(cell_prefix + d);
             n.classList.add(piece.type);
-            if (piece.x == toMoveInfo.x && piece.y == toMoveInfo.y && toMove) {
+            if (samePiece(piece, toMove)) {
                 n.classList.add("selected");
             }
         }
@@ -212,16 +207,14 @@ function moveEnd(e) {
     }
     y += yCord;
     var grabbedPiece = getPiece(x, y);
-    var grabbedPieceInfo = getPieceInfo(grabbedPiece);
-    var toMoveInfo = getPieceInfo(toMove);
-    if (toMove != null && toMove != grabbedPiece) {
-        if (ismovable(e) && (!grabbedPiece || grabbedPieceInfo.color != toMoveInfo.color)) {
-            make_move(x, y);
-            toMoveInfo.x = x;
-            toMoveInfo.y = y;
-            if ((toMoveInfo.type == "white_pawn" && y == 0) || (toMoveInfo.type == "black_pawn" && y == 7)) {
-                document.getElementById("overlay").style.display = "block";
-                toPromote = toMove;
+    var toMoveInfo = toMove;
+    if (toMove != null && !samePiece(toMove, grabbedPiece)) {
+        if (ismovable(e) && (!grabbedPiece || grabbedPiece.color != toMoveInfo.color)) {
+            if (board.promotes("" + toMoveInfo.y, "" + toMoveInfo.x, "" + y)) {
+                showPromotions(toMoveInfo.color);
+                toPromote = { fromX: toMoveInfo.x, fromY: toMoveInfo.y, x: x, y: y };
+            } else {
+                make_move(toMoveInfo.x, toMoveInfo.y, x, y);
             }
         }
         toMove = null;
@@ -255,7 +248,9 @@ function getBoard() {
     if (OFFLINE) {
         return;
     }
-    fetch("/board/" + gTurn)
+    // The whole board comes once; after that only what changed since the moves we have.
+    var whole = gTurn == 0;
+    fetch(whole ? "/board/0" : "/delta/" + gTurn + "/" + board.plies())
         .then(response => {
             if (!response.ok) {
                 throw new Error(`Request failed with status ${response.status}`)
@@ -263,7 +258,11 @@ function getBoard() {
             return response.text();
         }).then(text => {
             var data = JSON.parse(text);
-            board.build(text);
+            if (whole) {
+                board.build(text);
+            } else {
+                board.apply_delta(text);
+            }
             gTurn = parseInt(data["turn"]) + 1;
             render();
             getBoard();
@@ -276,8 +275,8 @@ function getBoard() {
 }
 
 function getMoves() {
-    var toMoveInfo = getPieceInfo(toMove);
-    console.log(toMove, toMoveInfo);
+    var toMoveInfo = toMove;
+    console.log(toMoveInfo);
     var yyCord = yCord;
     if (flipped) {
         yyCord = -yyCord - size + 1;
@@ -285,137 +284,42 @@ function getMoves() {
     movable = JSON.parse(board.get_legal_moves("" + toMoveInfo.y, "" + toMoveInfo.x, "" + yyCord, "" + xCord, "" + size));
 }
 
-function make_move(x, y) {
-    var toMoveInfo = getPieceInfo(toMove);
-    var tomx = toMoveInfo.x;
-    var tomy = toMoveInfo.y;
+function make_move(tomx, tomy, x, y, promotion) {
     if (OFFLINE) {
-        board.do_move("" + tomy, "" + tomx, "" + y, "" + x);
+        try {
+            board.do_move("" + tomy, "" + tomx, "" + y, "" + x, promotion);
+        } catch (error) {
+            console.log(JSON.parse(error).message);
+        }
         render();
         return;
     }
-    fetch("/move/" + tomy + "/" + tomx + "/" + y + "/" + x)
+    fetch("/move/" + tomy + "/" + tomx + "/" + y + "/" + x + (promotion ? "/" + promotion : ""))
         .then(response => {
+            if (response.status == 400) {
+                return response.json().then(reason => {
+                    throw new Error(reason.message)
+                })
+            }
             if (!response.ok) {
                 throw new Error(`Request failed with status ${response.status}`)
             }
             return response.text()
         })
         .then(data => {
-            board.do_move("" + tomy, "" + tomx, "" + y, "" + x);
+            board.do_move("" + tomy, "" + tomx, "" + y, "" + x, promotion);
             render();
         })
         .catch(error => console.log(error))
 }
 
-
 init()
     .then(() => {
         board = new WasmBoard();
         if (OFFLINE) {
-            place_pieces()
+            board.set_up();
+            render();
             return;
         }
         getBoard();
     });
-
-
-function place_pieces() {
-    board.place_piece(
-        "rook",
-        false,
-        "0",
-        "0",
-    );
-    board.place_piece(
-        "rook",
-        true,
-        "7",
-        "0",
-    );
-    board.place_piece(
-        "knight",
-        false,
-        "0",
-        "1",
-    );
-    board.place_piece(
-        "knight",
-        true,
-        "7",
-        "1",
-    );
-    board.place_piece(
-        "bishop",
-        false,
-        "0",
-        "2",
-    );
-    board.place_piece(
-        "bishop",
-        true,
-        "7",
-        "2",
-    );
-    board.place_piece(
-        "queen",
-        false,
-        "0",
-        "3",
-    );
-    board.place_piece(
-        "queen",
-        true,
-        "7",
-        "3",
-    );
-    board.place_piece(
-        "king",
-        false,
-        "0",
-        "4",
-    );
-    board.place_piece(
-        "king",
-        true,
-        "7",
-        "4",
-    );
-    board.place_piece(
-        "bishop",
-        false,
-        "0",
-        "5",
-    );
-    board.place_piece(
-        "bishop",
-        true,
-        "7",
-        "5",
-    );
-    board.place_piece(
-        "knight",
-        false,
-        "0",
-        "6",
-    );
-    board.place_piece(
-        "knight",
-        true,
-        "7",
-        "6",
-    );
-    board.place_piece(
-        "rook",
-        false,
-        "0",
-        "7",
-    );
-    board.place_piece(
-        "rook",
-        true,
-        "7",
-        "7",
-    );
-    render();
-}
\ No newline at end of file